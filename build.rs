@@ -0,0 +1,41 @@
+use std::{
+    env,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Embeds the source commit and build time for `/version`. `GIT_COMMIT` wins
+/// over asking git, since container builds don't copy `.git` into the context.
+fn main() {
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    let commit = env::var("GIT_COMMIT")
+        .ok()
+        .filter(|commit| !commit.trim().is_empty())
+        .or_else(|| {
+            let output = Command::new("git")
+                .args(["rev-parse", "HEAD"])
+                .output()
+                .ok()?;
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+        })
+        .map_or_else(|| "unknown".to_string(), |commit| commit.trim().to_string());
+    println!("cargo:rustc-env=REPO_SYNC_GIT_COMMIT={commit}");
+
+    // SOURCE_DATE_EPOCH keeps reproducible builds reproducible.
+    let built_at = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.trim().parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_secs())
+        });
+    println!("cargo:rustc-env=REPO_SYNC_BUILD_TIMESTAMP={built_at}");
+}
@@ -0,0 +1,84 @@
+use serde::Serialize;
+
+use crate::{
+    config::{AppConfig, SyncMode},
+    sync::{SyncErrorKind, error_kind, remote_branch_tip},
+};
+
+/// Result of `repo-sync --check`: the config loaded and every configured
+/// branch was looked up in the remote's ref advertisement, without cloning.
+#[derive(Debug, Serialize)]
+pub struct CheckReport {
+    pub ok: bool,
+    /// Why the config failed to load; nothing else is checked then.
+    pub config_error: Option<String>,
+    pub repo_url: Option<String>,
+    pub branches: Vec<BranchCheck>,
+}
+
+/// Lookup of one branch: its tip, or why it couldn't be read (connection,
+/// auth, or `branch_missing`).
+#[derive(Debug, Serialize)]
+pub struct BranchCheck {
+    pub branch: String,
+    pub ok: bool,
+    pub tip: Option<String>,
+    pub error_kind: Option<SyncErrorKind>,
+    pub error: Option<String>,
+}
+
+impl CheckReport {
+    pub fn invalid_config(err: &anyhow::Error) -> Self {
+        Self {
+            ok: false,
+            config_error: Some(format!("{err:#}")),
+            repo_url: None,
+            branches: Vec::new(),
+        }
+    }
+}
+
+/// Connects to the remote once per configured branch. An empty branch counts
+/// as ok since the service waits for its first commit.
+pub fn check(config: &AppConfig) -> CheckReport {
+    if config.sync_mode != SyncMode::Git {
+        return CheckReport {
+            ok: true,
+            config_error: None,
+            repo_url: None,
+            branches: Vec::new(),
+        };
+    }
+    let branches: Vec<BranchCheck> = std::iter::once(&config.git_branch)
+        .chain(&config.git_branches)
+        .map(|branch| check_branch(config, branch))
+        .collect();
+    CheckReport {
+        ok: branches.iter().all(|branch| branch.ok),
+        config_error: None,
+        repo_url: Some(config.git_repo_url.clone()),
+        branches,
+    }
+}
+
+fn check_branch(config: &AppConfig, branch: &str) -> BranchCheck {
+    match remote_branch_tip(&config.for_branch(branch)) {
+        Ok(tip) => BranchCheck {
+            branch: branch.to_string(),
+            ok: true,
+            tip: Some(tip),
+            error_kind: None,
+            error: None,
+        },
+        Err(err) => {
+            let kind = error_kind(&err);
+            BranchCheck {
+                branch: branch.to_string(),
+                ok: kind == SyncErrorKind::EmptyBranch,
+                tip: None,
+                error_kind: Some(kind),
+                error: Some(format!("{err:#}")),
+            }
+        }
+    }
+}
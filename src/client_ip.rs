@@ -0,0 +1,57 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{extract::ConnectInfo, http::Request};
+
+/// Client address for a request: with `trusted_hops` proxies in front, the
+/// `X-Forwarded-For` entry that many from the right, which the outermost
+/// trusted proxy appended. Anything left of it came from the client and is
+/// ignored. Without trusted proxies, or with too few entries, the socket peer
+/// address.
+pub fn client_ip<B>(request: &Request<B>, trusted_hops: Option<usize>) -> Option<IpAddr> {
+    if let Some(hops) = trusted_hops
+        && let Some(ip) = request
+            .headers()
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit(',').nth(hops.saturating_sub(1)))
+            .and_then(|v| v.trim().parse::<IpAddr>().ok())
+    {
+        return Some(ip);
+    }
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+
+    use super::*;
+
+    #[test]
+    fn takes_forwarded_for_entry_appended_by_trusted_proxies() {
+        let mut request = Request::builder()
+            .header("x-forwarded-for", "198.51.100.9, 203.0.113.7, 10.0.0.1")
+            .body(Body::empty())
+            .expect("request");
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 2], 4000))));
+
+        assert_eq!(
+            client_ip(&request, Some(1)),
+            Some(IpAddr::from([10, 0, 0, 1]))
+        );
+        assert_eq!(
+            client_ip(&request, Some(2)),
+            Some(IpAddr::from([203, 0, 113, 7]))
+        );
+        assert_eq!(
+            client_ip(&request, Some(4)),
+            Some(IpAddr::from([10, 0, 0, 2]))
+        );
+        assert_eq!(client_ip(&request, None), Some(IpAddr::from([10, 0, 0, 2])));
+    }
+}
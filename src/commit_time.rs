@@ -0,0 +1,88 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Utc};
+use git2::{Oid, Repository};
+
+/// Memoizes the last commit touching each path. Entries are only valid for a
+/// single tip SHA, so the whole map is dropped when the SHA changes.
+#[derive(Default)]
+pub struct CommitTimeCache {
+    inner: Mutex<CacheInner>,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    sha: String,
+    times: HashMap<PathBuf, Option<DateTime<Utc>>>,
+}
+
+impl CommitTimeCache {
+    pub fn get(&self, sha: &str, path: &Path) -> Option<Option<DateTime<Utc>>> {
+        let inner = self.inner.lock().ok()?;
+        if inner.sha != sha {
+            return None;
+        }
+        inner.times.get(path).copied()
+    }
+
+    pub fn insert(&self, sha: &str, path: PathBuf, time: Option<DateTime<Utc>>) {
+        let Ok(mut inner) = self.inner.lock() else {
+            return;
+        };
+        if inner.sha != sha {
+            inner.sha = sha.to_string();
+            inner.times.clear();
+        }
+        inner.times.insert(path, time);
+    }
+}
+
+/// Commit time of the most recent commit reachable from `sha` that changed
+/// `path` (relative to the repository root), or `None` if it was never tracked.
+pub fn last_commit_time(repo_dir: &Path, sha: &str, path: &Path) -> Result<Option<DateTime<Utc>>> {
+    let repo = Repository::open(repo_dir)
+        .with_context(|| format!("failed opening repo in {}", repo_dir.display()))?;
+    let tip = Oid::from_str(sha).with_context(|| format!("invalid sha {sha}"))?;
+
+    let mut revwalk = repo.revwalk().context("git revwalk failed")?;
+    revwalk.push(tip).context("git revwalk push failed")?;
+    revwalk
+        .simplify_first_parent()
+        .context("git revwalk failed")?;
+
+    for oid in revwalk {
+        let commit = repo
+            .find_commit(oid.context("git revwalk failed")?)
+            .context("failed reading commit")?;
+        let blob = commit
+            .tree()
+            .context("failed reading commit tree")?
+            .get_path(path)
+            .ok()
+            .map(|entry| entry.id());
+        let Some(blob) = blob else {
+            continue;
+        };
+        let parent_blob = match commit.parents().next() {
+            Some(parent) => parent
+                .tree()
+                .context("failed reading parent tree")?
+                .get_path(path)
+                .ok()
+                .map(|entry| entry.id()),
+            None => None,
+        };
+        if parent_blob != Some(blob) {
+            let seconds = commit.time().seconds();
+            return DateTime::from_timestamp(seconds, 0)
+                .map(Some)
+                .ok_or_else(|| anyhow!("commit time out of range: {seconds}"));
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,145 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use git2::{Delta, DiffFile, Odb, Oid, Repository};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Deleted,
+    Modified,
+    TypeChanged,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangedPath {
+    /// Relative to the repository root, not the serve root.
+    pub path: String,
+    pub change: ChangeKind,
+    pub old_size: Option<u64>,
+    pub new_size: Option<u64>,
+    /// `new_size - old_size`, counting a missing side as zero.
+    pub size_delta: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TreeDiff {
+    pub from: String,
+    pub to: String,
+    pub changes: Vec<ChangedPath>,
+    /// More paths changed than the `limit` passed to `diff_commits`.
+    pub truncated: bool,
+}
+
+/// Paths that differ between the trees of commits `from` and `to` in the
+/// repository at `repo_dir` and pass `visible`, at most `limit` of them. A
+/// rename is a deletion and an addition. `None` when either is not a commit
+/// in the local object database.
+pub fn diff_commits(
+    repo_dir: &Path,
+    from: Oid,
+    to: Oid,
+    visible: impl Fn(&Path) -> bool,
+    limit: usize,
+) -> Result<Option<TreeDiff>> {
+    let repo = Repository::open(repo_dir)
+        .with_context(|| format!("failed opening repo in {}", repo_dir.display()))?;
+    let (Ok(from_commit), Ok(to_commit)) = (repo.find_commit(from), repo.find_commit(to)) else {
+        return Ok(None);
+    };
+    let from_tree = from_commit.tree().context("failed reading commit tree")?;
+    let to_tree = to_commit.tree().context("failed reading commit tree")?;
+    let diff = repo
+        .diff_tree_to_tree(Some(&from_tree), Some(&to_tree), None)
+        .context("git diff failed")?;
+    let odb = repo.odb().context("failed opening object database")?;
+
+    let mut deltas = diff.deltas().filter_map(|delta| {
+        let file = match delta.status() {
+            Delta::Deleted => delta.old_file(),
+            _ => delta.new_file(),
+        };
+        let path = file.path().filter(|path| visible(path))?.to_path_buf();
+        Some((delta, path))
+    });
+    let changes = deltas
+        .by_ref()
+        .take(limit)
+        .map(|(delta, path)| {
+            let old_size = blob_size(&odb, &delta.old_file());
+            let new_size = blob_size(&odb, &delta.new_file());
+            ChangedPath {
+                path: path.to_string_lossy().into_owned(),
+                change: match delta.status() {
+                    Delta::Added => ChangeKind::Added,
+                    Delta::Deleted => ChangeKind::Deleted,
+                    Delta::Typechange => ChangeKind::TypeChanged,
+                    _ => ChangeKind::Modified,
+                },
+                old_size,
+                new_size,
+                size_delta: new_size.unwrap_or(0) as i64 - old_size.unwrap_or(0) as i64,
+            }
+        })
+        .collect();
+    let truncated = deltas.next().is_some();
+    Ok(Some(TreeDiff {
+        from: from.to_string(),
+        to: to.to_string(),
+        changes,
+        truncated,
+    }))
+}
+
+/// Paths, relative to the repository root, with a file in `from`'s tree but
+/// none in `to`'s, at most `limit` of them. Renamed files count as deleted
+/// under their old path.
+pub fn deleted_paths(repo: &Repository, from: Oid, to: Oid, limit: usize) -> Result<Vec<PathBuf>> {
+    let from_tree = repo
+        .find_commit(from)
+        .and_then(|commit| commit.tree())
+        .context("failed reading commit tree")?;
+    let to_tree = repo
+        .find_commit(to)
+        .and_then(|commit| commit.tree())
+        .context("failed reading commit tree")?;
+    let diff = repo
+        .diff_tree_to_tree(Some(&from_tree), Some(&to_tree), None)
+        .context("git diff failed")?;
+    Ok(diff
+        .deltas()
+        .filter(|delta| delta.status() == Delta::Deleted)
+        .filter_map(|delta| delta.old_file().path().map(Path::to_path_buf))
+        .take(limit)
+        .collect())
+}
+
+/// How far `synced` trails `remote` in the repository at `repo_dir`: commits
+/// reachable from `remote` but not `synced`, and how much newer `remote`'s
+/// commit time is (never negative). `None` when either commit isn't in the
+/// local object database.
+pub fn commits_behind(repo_dir: &Path, synced: Oid, remote: Oid) -> Result<Option<(usize, i64)>> {
+    let repo = Repository::open(repo_dir)
+        .with_context(|| format!("failed opening repo in {}", repo_dir.display()))?;
+    let (Ok(synced_commit), Ok(remote_commit)) =
+        (repo.find_commit(synced), repo.find_commit(remote))
+    else {
+        return Ok(None);
+    };
+    let (behind, _) = repo
+        .graph_ahead_behind(remote, synced)
+        .context("failed counting commits behind")?;
+    let seconds = remote_commit.time().seconds() - synced_commit.time().seconds();
+    Ok(Some((behind, seconds.max(0))))
+}
+
+/// Size of the blob on one side of a delta; `None` for the missing side of an
+/// addition or deletion and for submodule entries, whose commits aren't local.
+fn blob_size(odb: &Odb, file: &DiffFile) -> Option<u64> {
+    if file.id().is_zero() {
+        return None;
+    }
+    odb.read_header(file.id()).ok().map(|(size, _)| size as u64)
+}
@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    fs::{self, Metadata},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::config::HashAlgorithm;
+
+/// Upper bound on cached digests; the cache is reset when exceeded.
+const MAX_ENTRIES: usize = 100_000;
+
+/// Longest a `precompute` pass may run; whatever it didn't reach is hashed on
+/// first request as usual.
+pub const PRECOMPUTE_BUDGET: Duration = Duration::from_secs(30);
+
+/// Quoted hex digest of `bytes`, used as a strong `ETag`.
+pub fn strong_etag(algorithm: HashAlgorithm, bytes: &[u8]) -> String {
+    let digest = match algorithm {
+        HashAlgorithm::Sha256 => hex::encode(Sha256::digest(bytes)),
+        HashAlgorithm::Blake3 => blake3::hash(bytes).to_hex().to_string(),
+    };
+    format!("\"{digest}\"")
+}
+
+/// Strong ETags keyed by path and validated against the file's length and
+/// mtime, so unchanged files aren't re-hashed on every request.
+#[derive(Default)]
+pub struct DigestCache {
+    entries: Mutex<HashMap<PathBuf, CachedDigest>>,
+}
+
+struct CachedDigest {
+    len: u64,
+    modified: Option<SystemTime>,
+    etag: String,
+}
+
+impl DigestCache {
+    pub fn get(&self, path: &Path, metadata: &Metadata) -> Option<String> {
+        let entries = self.entries.lock().ok()?;
+        let cached = entries.get(path)?;
+        (cached.len == metadata.len() && cached.modified == metadata.modified().ok())
+            .then(|| cached.etag.clone())
+    }
+
+    pub fn insert(&self, path: PathBuf, metadata: &Metadata, etag: String) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        if entries.len() >= MAX_ENTRIES && !entries.contains_key(&path) {
+            entries.clear();
+        }
+        entries.insert(
+            path,
+            CachedDigest {
+                len: metadata.len(),
+                modified: metadata.modified().ok(),
+                etag,
+            },
+        );
+    }
+
+    /// Drops every cached digest, returning how many there were.
+    pub fn clear(&self) -> usize {
+        let Ok(mut entries) = self.entries.lock() else {
+            return 0;
+        };
+        let purged = entries.len();
+        entries.clear();
+        purged
+    }
+}
+
+impl DigestCache {
+    /// Hashes every regular file under `root` of at most `max_bytes` that
+    /// isn't cached yet, skipping `.git` and symlinks. Stops once `budget`
+    /// has passed or the cache is full; returns how many files were hashed.
+    pub fn precompute(
+        &self,
+        root: &Path,
+        algorithm: HashAlgorithm,
+        max_bytes: u64,
+        budget: Duration,
+    ) -> usize {
+        let deadline = Instant::now() + budget;
+        let mut hashed = 0;
+        let mut pending = vec![root.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            let Ok(read_dir) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in read_dir.flatten() {
+                if Instant::now() >= deadline || self.len() >= MAX_ENTRIES {
+                    return hashed;
+                }
+                let path = entry.path();
+                let Ok(file_type) = entry.file_type() else {
+                    continue;
+                };
+                if file_type.is_dir() {
+                    if entry.file_name() != ".git" {
+                        pending.push(path);
+                    }
+                    continue;
+                }
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                if !file_type.is_file()
+                    || metadata.len() > max_bytes
+                    || self.get(&path, &metadata).is_some()
+                {
+                    continue;
+                }
+                let Ok(bytes) = fs::read(&path) else {
+                    continue;
+                };
+                self.insert(path, &metadata, strong_etag(algorithm, &bytes));
+                hashed += 1;
+            }
+        }
+        hashed
+    }
+
+    fn len(&self) -> usize {
+        self.entries.lock().map_or(0, |entries| entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn invalidates_when_file_changes() {
+        let temp = tempdir().expect("temp dir");
+        let path = temp.path().join("a.txt");
+        std::fs::write(&path, "one").expect("write file");
+        let cache = DigestCache::default();
+
+        let metadata = std::fs::metadata(&path).expect("metadata");
+        assert_eq!(cache.get(&path, &metadata), None);
+        cache.insert(path.clone(), &metadata, "\"one\"".to_string());
+        assert_eq!(cache.get(&path, &metadata).as_deref(), Some("\"one\""));
+
+        std::fs::write(&path, "longer").expect("rewrite file");
+        let metadata = std::fs::metadata(&path).expect("metadata");
+        assert_eq!(cache.get(&path, &metadata), None);
+    }
+
+    #[test]
+    fn precomputes_small_files_outside_git_dir() {
+        let temp = tempdir().expect("temp dir");
+        std::fs::create_dir_all(temp.path().join("nested")).expect("create dir");
+        std::fs::create_dir_all(temp.path().join(".git")).expect("create git dir");
+        std::fs::write(temp.path().join("nested/small.txt"), "small").expect("write file");
+        std::fs::write(temp.path().join("big.txt"), "0123456789").expect("write file");
+        std::fs::write(temp.path().join(".git/HEAD"), "ref").expect("write file");
+        let cache = DigestCache::default();
+
+        let hashed = cache.precompute(temp.path(), HashAlgorithm::Sha256, 5, PRECOMPUTE_BUDGET);
+        assert_eq!(hashed, 1);
+        let small = temp.path().join("nested/small.txt");
+        let metadata = std::fs::metadata(&small).expect("metadata");
+        assert_eq!(
+            cache.get(&small, &metadata),
+            Some(strong_etag(HashAlgorithm::Sha256, b"small"))
+        );
+    }
+}
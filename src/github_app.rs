@@ -0,0 +1,280 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, TimeDelta, Utc};
+use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
+use serde::{Deserialize, Serialize};
+
+pub const GITHUB_API_URL: &str = "https://api.github.com";
+
+/// Cached tokens are replaced once they have less than this left, so a token
+/// never expires halfway through a clone.
+const REFRESH_MARGIN: TimeDelta = TimeDelta::minutes(5);
+
+/// GitHub App credentials used to mint installation tokens instead of a PAT.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GithubApp {
+    pub app_id: String,
+    pub installation_id: String,
+    pub private_key_path: PathBuf,
+}
+
+#[derive(Serialize)]
+struct Claims {
+    iat: i64,
+    exp: i64,
+    iss: String,
+}
+
+/// Signs the RS256 JWT that authenticates as the app itself. `iat` is
+/// backdated a minute for clock drift and GitHub caps `exp` at ten minutes.
+pub fn app_jwt(app_id: &str, private_key_pem: &[u8], now: DateTime<Utc>) -> Result<String> {
+    let key =
+        EncodingKey::from_rsa_pem(private_key_pem).context("invalid GitHub App private key")?;
+    let claims = Claims {
+        iat: (now - TimeDelta::seconds(60)).timestamp(),
+        exp: (now + TimeDelta::minutes(9)).timestamp(),
+        iss: app_id.to_string(),
+    };
+    encode(&Header::new(Algorithm::RS256), &claims, &key).context("failed signing GitHub App JWT")
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct InstallationToken {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Exchanges a fresh app JWT for an installation access token at `api_url`.
+pub fn mint_installation_token(
+    app: &GithubApp,
+    api_url: &str,
+    now: DateTime<Utc>,
+) -> Result<InstallationToken> {
+    let private_key = fs::read(&app.private_key_path).with_context(|| {
+        format!(
+            "failed reading GitHub App private key {}",
+            app.private_key_path.display()
+        )
+    })?;
+    let jwt = app_jwt(&app.app_id, &private_key, now)?;
+    let url = format!(
+        "{}/app/installations/{}/access_tokens",
+        api_url.trim_end_matches('/'),
+        app.installation_id
+    );
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(Duration::from_secs(30)))
+        .build()
+        .into();
+    agent
+        .post(&url)
+        .header("Authorization", &format!("Bearer {jwt}"))
+        .header("Accept", "application/vnd.github+json")
+        .header("User-Agent", "repo-sync")
+        .send_empty()
+        .with_context(|| format!("GitHub installation token request to {url} failed"))?
+        .body_mut()
+        .read_json()
+        .context("invalid GitHub installation token response")
+}
+
+/// Installation tokens keyed by installation id, shared across syncs.
+#[derive(Default)]
+pub struct TokenCache {
+    tokens: Mutex<HashMap<String, InstallationToken>>,
+}
+
+impl TokenCache {
+    /// The cached token for `installation_id` unless it expires within
+    /// `REFRESH_MARGIN` of `now`; otherwise the one `mint` returns, which is
+    /// then cached. Minting holds the lock so concurrent syncs share one token.
+    pub fn get_or_mint(
+        &self,
+        installation_id: &str,
+        now: DateTime<Utc>,
+        mint: impl FnOnce() -> Result<InstallationToken>,
+    ) -> Result<String> {
+        let mut tokens = self
+            .tokens
+            .lock()
+            .map_err(|_| anyhow!("installation token cache lock poisoned"))?;
+        if let Some(cached) = tokens.get(installation_id)
+            && cached.expires_at - now > REFRESH_MARGIN
+        {
+            return Ok(cached.token.clone());
+        }
+        let minted = mint()?;
+        let token = minted.token.clone();
+        tokens.insert(installation_id.to_string(), minted);
+        Ok(token)
+    }
+}
+
+static TOKENS: LazyLock<TokenCache> = LazyLock::new(TokenCache::default);
+
+/// Installation token for `app`, minted from the GitHub API only when the
+/// cached one is missing or close to expiring.
+pub fn installation_token(app: &GithubApp) -> Result<String> {
+    let now = Utc::now();
+    TOKENS.get_or_mint(&app.installation_id, now, || {
+        mint_installation_token(app, GITHUB_API_URL, now)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use jsonwebtoken::{DecodingKey, Validation, decode};
+    use serde::Deserialize;
+
+    use super::*;
+
+    const PRIVATE_KEY: &[u8] = include_bytes!("../tests/fixtures/github-app-test-key.pem");
+    const PUBLIC_KEY: &[u8] = include_bytes!("../tests/fixtures/github-app-test-key.pub.pem");
+
+    #[derive(Deserialize)]
+    struct DecodedClaims {
+        iat: i64,
+        exp: i64,
+        iss: String,
+    }
+
+    fn decode_jwt(jwt: &str) -> DecodedClaims {
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.validate_exp = false;
+        decode::<DecodedClaims>(
+            jwt,
+            &DecodingKey::from_rsa_pem(PUBLIC_KEY).expect("public key"),
+            &validation,
+        )
+        .expect("valid signature")
+        .claims
+    }
+
+    #[test]
+    fn signs_app_jwt_with_backdated_short_lived_claims() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0).expect("timestamp");
+        let claims = decode_jwt(&app_jwt("12345", PRIVATE_KEY, now).expect("jwt"));
+
+        assert_eq!(claims.iss, "12345");
+        assert_eq!(claims.iat, 1_700_000_000 - 60);
+        assert_eq!(claims.exp, 1_700_000_000 + 540);
+        assert!(app_jwt("12345", b"not a key", now).is_err());
+    }
+
+    #[test]
+    fn token_cache_reuses_tokens_until_near_expiry() {
+        let cache = TokenCache::default();
+        let start = Utc::now();
+        let mints = Cell::new(0);
+        let mint = |now: DateTime<Utc>| {
+            mints.set(mints.get() + 1);
+            Ok(InstallationToken {
+                token: format!("token-{}", mints.get()),
+                expires_at: now + TimeDelta::hours(1),
+            })
+        };
+
+        let first = cache
+            .get_or_mint("1", start, || mint(start))
+            .expect("token");
+        let later = start + TimeDelta::minutes(50);
+        let reused = cache
+            .get_or_mint("1", later, || mint(later))
+            .expect("token");
+        assert_eq!((first.as_str(), reused.as_str()), ("token-1", "token-1"));
+
+        let near_expiry = start + TimeDelta::minutes(56);
+        let refreshed = cache
+            .get_or_mint("1", near_expiry, || mint(near_expiry))
+            .expect("token");
+        assert_eq!(refreshed, "token-2");
+
+        let other = cache
+            .get_or_mint("2", near_expiry, || mint(near_expiry))
+            .expect("token");
+        assert_eq!(other, "token-3");
+
+        assert!(
+            cache
+                .get_or_mint("3", start, || Err(anyhow!("api down")))
+                .is_err()
+        );
+        assert_eq!(
+            cache
+                .get_or_mint("1", near_expiry, || Err(anyhow!("unused")))
+                .expect("cached"),
+            "token-2"
+        );
+    }
+
+    #[test]
+    fn mints_installation_token_from_api() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock api");
+        let api_url = format!("http://{}", listener.local_addr().expect("addr"));
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("accept");
+            let mut reader = BufReader::new(stream.try_clone().expect("clone stream"));
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).expect("request line");
+            let mut authorization = None;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).expect("header line");
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':')
+                    && name.eq_ignore_ascii_case("authorization")
+                {
+                    authorization = Some(value.trim().to_string());
+                }
+            }
+            let body = r#"{"token":"ghs_minted","expires_at":"2030-01-01T00:00:00Z"}"#;
+            write!(
+                &stream,
+                "HTTP/1.1 201 Created\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .expect("write response");
+            (request_line, authorization)
+        });
+
+        let dir = tempfile::tempdir().expect("temp dir");
+        let private_key_path = dir.path().join("app.pem");
+        fs::write(&private_key_path, PRIVATE_KEY).expect("write key");
+        let app = GithubApp {
+            app_id: "12345".to_string(),
+            installation_id: "678".to_string(),
+            private_key_path,
+        };
+
+        let token = mint_installation_token(&app, &api_url, Utc::now()).expect("token");
+        assert_eq!(token.token, "ghs_minted");
+        assert_eq!(token.expires_at.to_rfc3339(), "2030-01-01T00:00:00+00:00");
+
+        let (request_line, authorization) = server.join().expect("mock api");
+        assert!(
+            request_line.starts_with("POST /app/installations/678/access_tokens "),
+            "{request_line}"
+        );
+        let jwt = authorization
+            .as_deref()
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .expect("bearer token");
+        assert_eq!(decode_jwt(jwt).iss, "12345");
+    }
+}
@@ -0,0 +1,92 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncOutcomeKind {
+    Success,
+    Failure,
+    /// The branch had no commits to sync yet.
+    Empty,
+}
+
+/// One sync attempt as recorded in the history buffer.
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncEvent {
+    pub branch: String,
+    pub at: DateTime<Utc>,
+    pub outcome: SyncOutcomeKind,
+    pub sha: Option<String>,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+/// Bounded ring buffer of recent sync attempts; the oldest event is dropped
+/// once `capacity` is reached.
+pub struct SyncHistory {
+    capacity: usize,
+    events: Mutex<VecDeque<SyncEvent>>,
+}
+
+impl SyncHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn record(&self, event: SyncEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+        let Ok(mut events) = self.events.lock() else {
+            return;
+        };
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Recorded events, newest first.
+    pub fn newest_first(&self) -> Vec<SyncEvent> {
+        self.events
+            .lock()
+            .map(|events| events.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(sha: &str) -> SyncEvent {
+        SyncEvent {
+            branch: "main".to_string(),
+            at: Utc::now(),
+            outcome: SyncOutcomeKind::Success,
+            sha: Some(sha.to_string()),
+            duration_ms: 1,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn keeps_only_the_newest_events() {
+        let history = SyncHistory::new(2);
+        history.record(event("a"));
+        history.record(event("b"));
+        history.record(event("c"));
+
+        let shas: Vec<_> = history
+            .newest_first()
+            .into_iter()
+            .filter_map(|event| event.sha)
+            .collect();
+        assert_eq!(shas, ["c", "b"]);
+    }
+}
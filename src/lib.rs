@@ -1,4 +1,25 @@
+pub mod check;
+pub mod client_ip;
+pub mod commit_time;
 pub mod config;
+pub mod diff;
+pub mod digest_cache;
+pub mod github_app;
+pub mod history;
+pub mod log_buffer;
+pub mod markdown;
+pub mod metrics;
+pub mod object_db;
+pub mod openapi;
 pub mod path_guard;
+pub mod range;
+pub mod rate_limit;
+pub mod redirects;
+pub mod runtime;
+pub mod schema_index;
+pub mod serve;
+pub mod serve_ignore;
 pub mod server;
+pub mod snapshot;
+pub mod sniff;
 pub mod sync;
@@ -1,39 +1,241 @@
-use std::sync::Arc;
+use std::{io::ErrorKind, sync::Arc, time::Duration};
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use arc_swap::ArcSwap;
 use repo_sync::{
-    config::AppConfig,
-    server::{AppState, router},
-    sync::{SyncStatus, sync_loop, sync_once},
+    check::{CheckReport, check},
+    config::{AppConfig, SyncMode},
+    log_buffer,
+    runtime::build_runtime,
+    serve::{Listener, ServeOptions, serve},
+    server::{AppState, admin_router, public_router, router},
+    sync::{SyncStatus, prepare_work_dir, set_git_user_agent, sync_loop, sync_once},
 };
-use tokio::{net::TcpListener, sync::RwLock};
-use tracing::info;
-use tracing_subscriber::{EnvFilter, fmt};
-
-#[tokio::main]
-async fn main() -> Result<()> {
-    fmt()
-        .with_env_filter(
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::{RwLock, Semaphore},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
+
+fn main() -> Result<()> {
+    match std::env::args().nth(1).as_deref() {
+        None => {}
+        Some("--check") => run_check(),
+        Some(other) => {
+            return Err(anyhow!(
+                "unknown argument '{other}'; usage: repo-sync [--check]"
+            ));
+        }
+    }
+
+    // Loaded before logging starts so the log buffer can be sized from it;
+    // config errors reach stderr through the returned error instead.
+    let config = AppConfig::from_env()?;
+    let logs = log_buffer::install(config.log_buffer_size);
+    tracing_subscriber::registry()
+        .with(
             EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| EnvFilter::new("repo_sync=info,tower_http=info,axum=info")),
         )
+        .with(fmt::layer())
+        .with(logs)
         .init();
+    redact_secrets(&config);
+    log_effective_config(&config);
 
-    let config = AppConfig::from_env()?;
-    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    set_git_user_agent(&config.git_user_agent)?;
+    build_runtime(&config)?.block_on(run(config))
+}
+
+/// `--check`: validates the config and the remote without binding anything,
+/// prints the report as JSON and exits non-zero if anything failed.
+fn run_check() -> ! {
+    let report = match AppConfig::from_env() {
+        Ok(config) => match set_git_user_agent(&config.git_user_agent) {
+            Ok(()) => check(&config),
+            Err(err) => CheckReport::invalid_config(&err),
+        },
+        Err(err) => CheckReport::invalid_config(&err),
+    };
+    match serde_json::to_string_pretty(&report) {
+        Ok(json) => println!("{json}"),
+        Err(err) => eprintln!("failed rendering check report: {err}"),
+    }
+    std::process::exit(if report.ok { 0 } else { 1 })
+}
 
-    sync_once(&config, status.clone()).await?;
-    let sync_config = config.clone();
-    let sync_status = status.clone();
-    tokio::spawn(async move {
-        sync_loop(sync_config, sync_status).await;
-    });
+async fn run(config: AppConfig) -> Result<()> {
+    if config.sync_mode == SyncMode::Git {
+        prepare_work_dir(&config.work_dir, &config.mirror_dir)?;
+    }
+    let status = Arc::new(RwLock::new(SyncStatus::restore(&config.mirror_dir)));
+    let state = AppState::new(config, status);
 
-    let state = AppState { config, status };
-    let app = router(state.clone());
-    let listener = TcpListener::bind(&state.config.http_bind_addr).await?;
-    info!("listening on {}", state.config.http_bind_addr);
-    axum::serve(listener, app).await?;
+    if state.config.sync_on_startup {
+        sync_once(&state.config, state.status.clone(), &state.history).await?;
+    } else {
+        info!("SYNC_ON_STARTUP disabled; serving before the first sync completes");
+    }
+    let shutdown = CancellationToken::new();
+    let sync_slots = Arc::new(Semaphore::new(state.config.sync_concurrency));
+    let mut sync_loops = vec![sync_loop(
+        state.live_config.clone(),
+        None,
+        state.status.clone(),
+        state.history.clone(),
+        state.digests.clone(),
+        sync_slots.clone(),
+        shutdown.clone(),
+    )];
+    // Extra branches sync in the background only; a failing secondary branch
+    // must not keep the primary from serving.
+    for (branch, branch_state) in state.branches.iter() {
+        sync_loops.push(sync_loop(
+            state.live_config.clone(),
+            Some(branch.clone()),
+            branch_state.status.clone(),
+            branch_state.history.clone(),
+            state.digests.clone(),
+            sync_slots.clone(),
+            shutdown.clone(),
+        ));
+    }
+    tokio::spawn(reload_on_hangup(state.live_config.clone()));
 
+    let options = ServeOptions {
+        http2: state.config.http2_enabled,
+        header_read_timeout: Some(Duration::from_secs(
+            state.config.header_read_timeout_seconds,
+        )),
+    };
+    let uds_mode = state.config.uds_mode;
+    // Returning drops the listeners, which removes any Unix socket files.
+    let serving = async {
+        match state.config.admin_bind_addr.clone() {
+            Some(admin_bind_addr) => {
+                let listener =
+                    bind("HTTP_BIND_ADDR", &state.config.http_bind_addr, uds_mode).await?;
+                let admin_listener = bind("ADMIN_BIND_ADDR", &admin_bind_addr, uds_mode).await?;
+                info!("listening on {}", state.config.http_bind_addr);
+                info!("admin listening on {admin_bind_addr}");
+                tokio::try_join!(
+                    serve(listener, public_router(state.clone()), options),
+                    serve(admin_listener, admin_router(state.clone()), options),
+                )
+                .map(|_| ())
+            }
+            None => {
+                let listener =
+                    bind("HTTP_BIND_ADDR", &state.config.http_bind_addr, uds_mode).await?;
+                info!("listening on {}", state.config.http_bind_addr);
+                serve(listener, router(state.clone()), options).await
+            }
+        }
+    };
+    tokio::select! {
+        result = serving => result?,
+        () = shutdown_signal() => info!("shutting down"),
+    }
+
+    // Let syncs in flight finish rather than abandon a half-reset worktree,
+    // but don't let a hung fetch block the exit forever.
+    shutdown.cancel();
+    let finished = tokio::time::timeout(SYNC_SHUTDOWN_GRACE, async {
+        for sync_loop in sync_loops {
+            if let Err(err) = sync_loop.await {
+                warn!("sync loop ended abnormally: {err}");
+            }
+        }
+    })
+    .await;
+    if finished.is_err() {
+        warn!(
+            "sync still running after {}s; exiting anyway",
+            SYNC_SHUTDOWN_GRACE.as_secs()
+        );
+    }
     Ok(())
 }
+
+/// How long shutdown waits for in-flight syncs.
+const SYNC_SHUTDOWN_GRACE: Duration = Duration::from_secs(30);
+
+/// Binds `addr`, naming the setting it came from when the port or socket path
+/// is taken since the bare OS error is easy to miss.
+async fn bind(setting: &str, addr: &str, uds_mode: Option<u32>) -> Result<Listener> {
+    Listener::bind(addr, uds_mode)
+        .await
+        .map_err(|err| match err.kind() {
+            ErrorKind::AddrInUse => anyhow!("{setting} {addr} is already in use"),
+            _ => anyhow::Error::new(err).context(format!("failed binding {setting} {addr}")),
+        })
+}
+
+/// Resolves on SIGTERM or SIGINT.
+async fn shutdown_signal() {
+    let (mut terminate, mut interrupt) = match (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::interrupt()),
+    ) {
+        (Ok(terminate), Ok(interrupt)) => (terminate, interrupt),
+        (Err(err), _) | (_, Err(err)) => {
+            warn!("failed installing shutdown handlers: {err}");
+            return std::future::pending().await;
+        }
+    };
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = interrupt.recv() => {}
+    }
+}
+
+/// One record with every resolved setting, secrets masked, so misconfiguration
+/// shows up in the first lines of the log.
+fn log_effective_config(config: &AppConfig) {
+    let redacted = config.redacted();
+    if config.log_config {
+        info!(config = ?redacted, "effective configuration");
+    } else {
+        debug!(config = ?redacted, "effective configuration");
+    }
+}
+
+/// Keeps the configured tokens out of `/meta/logs`.
+fn redact_secrets(config: &AppConfig) {
+    if let Some(buffer) = log_buffer::installed() {
+        buffer.redact(config.git_token.iter().chain(&config.admin_token).cloned());
+    }
+}
+
+/// Re-reads the config on every SIGHUP and publishes its hot-reloadable
+/// settings; anything else that changed is logged and left as is.
+async fn reload_on_hangup(live_config: Arc<ArcSwap<AppConfig>>) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(v) => v,
+        Err(err) => {
+            warn!("failed installing SIGHUP handler: {err}");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        let new = match AppConfig::from_env() {
+            Ok(v) => v,
+            Err(err) => {
+                warn!("config reload failed, keeping current config: {err:#}");
+                continue;
+            }
+        };
+        redact_secrets(&new);
+        let (reloaded, ignored) = live_config.load().reloaded(&new);
+        live_config.store(Arc::new(reloaded));
+        info!("config reloaded");
+        if !ignored.is_empty() {
+            warn!(
+                "ignoring changes that require a restart: {}",
+                ignored.join(", ")
+            );
+        }
+    }
+}
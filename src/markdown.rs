@@ -0,0 +1,63 @@
+use std::path::Path;
+
+use pulldown_cmark::{Options, Parser, html};
+
+/// Whether `path` is rendered by `RENDER_MARKDOWN`.
+pub fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("md") || ext.eq_ignore_ascii_case("markdown"))
+}
+
+/// Renders `markdown` as a standalone HTML page titled `title`. Raw HTML in
+/// the source is passed through, so only render repositories you trust.
+pub fn render_page(markdown: &str, title: &str) -> String {
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let mut body = String::new();
+    html::push_html(&mut body, Parser::new_ext(markdown, options));
+    html_page(title, &body)
+}
+
+/// Minimal HTML document titled `title` (escaped) around `body_html` (as-is).
+pub fn html_page(title: &str, body_html: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n{body_html}</body>\n</html>\n",
+        escape_html(title)
+    )
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_markdown_into_an_escaped_page() {
+        let page = render_page("# Hello\n\n| a |\n|---|\n| 1 |\n", "<notes>.md");
+        assert!(page.contains("<title>&lt;notes&gt;.md</title>"));
+        assert!(page.contains("<h1>Hello</h1>"));
+        assert!(page.contains("<table>"));
+    }
+
+    #[test]
+    fn detects_markdown_extensions() {
+        assert!(is_markdown(Path::new("docs/README.md")));
+        assert!(is_markdown(Path::new("guide.MARKDOWN")));
+        assert!(!is_markdown(Path::new("data.json")));
+        assert!(!is_markdown(Path::new("md")));
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+/// Upper bounds, in seconds, of the request latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0,
+];
+
+/// Coarse route grouping used as the histogram label, so cardinality stays
+/// fixed no matter which paths clients request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteClass {
+    Files,
+    List,
+    Meta,
+    Health,
+}
+
+impl RouteClass {
+    const ALL: [RouteClass; 4] = [Self::Files, Self::List, Self::Meta, Self::Health];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Files => "files",
+            Self::List => "list",
+            Self::Meta => "meta",
+            Self::Health => "health",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Non-cumulative counts per bucket; the last slot is `+Inf`.
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_nanos: AtomicU64,
+}
+
+/// Sync activity rendered alongside the request histograms; read from the
+/// primary branch's `SyncStatus` at scrape time.
+#[derive(Debug, Default, Serialize)]
+pub struct SyncMetrics {
+    pub sha_changes: u64,
+    /// `None` until a SHA change has been observed.
+    pub seconds_since_last_change: Option<f64>,
+}
+
+/// One route class's histogram as read at scrape time.
+#[derive(Debug, Serialize)]
+pub struct RouteStats {
+    pub count: u64,
+    pub duration_seconds_sum: f64,
+    /// Cumulative, ending with the `+Inf` bucket.
+    pub duration_buckets: Vec<Bucket>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Bucket {
+    /// Upper bound in seconds as rendered in `/metrics`, e.g. `0.25` or `+Inf`.
+    pub le: String,
+    pub count: u64,
+}
+
+/// `/metrics` as JSON, for consumers that can't parse OpenMetrics text.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    /// Keyed by route class label.
+    pub requests: BTreeMap<&'static str, RouteStats>,
+    #[serde(flatten)]
+    pub sync: SyncMetrics,
+}
+
+/// Request latency histograms per `RouteClass`.
+#[derive(Default)]
+pub struct RequestMetrics {
+    histograms: [Histogram; RouteClass::ALL.len()],
+}
+
+impl RequestMetrics {
+    pub fn observe(&self, class: RouteClass, elapsed: Duration) {
+        let histogram = &self.histograms[class as usize];
+        let seconds = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|le| seconds <= *le)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        histogram.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        histogram.sum_nanos.fetch_add(
+            elapsed.as_nanos().try_into().unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    fn route_stats(&self, class: RouteClass) -> RouteStats {
+        let histogram = &self.histograms[class as usize];
+        let mut cumulative = 0;
+        let duration_buckets = histogram
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(i, count)| {
+                cumulative += count.load(Ordering::Relaxed);
+                Bucket {
+                    le: LATENCY_BUCKETS
+                        .get(i)
+                        .map_or_else(|| "+Inf".to_string(), |le| format!("{le:?}")),
+                    count: cumulative,
+                }
+            })
+            .collect();
+        let sum = Duration::from_nanos(histogram.sum_nanos.load(Ordering::Relaxed));
+        RouteStats {
+            // Derived from the buckets so the count always equals the `+Inf` bucket.
+            count: cumulative,
+            duration_seconds_sum: sum.as_secs_f64(),
+            duration_buckets,
+        }
+    }
+
+    /// The values `render` exposes, for `/stats`.
+    pub fn stats(&self, sync: SyncMetrics) -> Stats {
+        Stats {
+            requests: RouteClass::ALL
+                .into_iter()
+                .map(|class| (class.label(), self.route_stats(class)))
+                .collect(),
+            sync,
+        }
+    }
+
+    /// OpenMetrics text exposition, terminated by `# EOF`.
+    pub fn render(&self, sync: &SyncMetrics) -> String {
+        let name = "repo_sync_request_duration_seconds";
+        let mut out = String::new();
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        let _ = writeln!(out, "# UNIT {name} seconds");
+        let _ = writeln!(out, "# HELP {name} HTTP request latency by route class.");
+        for class in RouteClass::ALL {
+            let route = class.label();
+            let stats = self.route_stats(class);
+            for Bucket { le, count } in &stats.duration_buckets {
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{{route=\"{route}\",le=\"{le}\"}} {count}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{name}_sum{{route=\"{route}\"}} {:?}",
+                stats.duration_seconds_sum
+            );
+            let _ = writeln!(out, "{name}_count{{route=\"{route}\"}} {}", stats.count);
+        }
+
+        let name = "repo_sync_sha_changes";
+        let _ = writeln!(out, "# TYPE {name} counter");
+        let _ = writeln!(out, "# HELP {name} Times a sync observed a new commit SHA.");
+        let _ = writeln!(out, "{name}_total {}", sync.sha_changes);
+
+        let name = "repo_sync_seconds_since_last_change";
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        let _ = writeln!(
+            out,
+            "# HELP {name} Seconds since a sync last observed a new commit SHA."
+        );
+        if let Some(seconds) = sync.seconds_since_last_change {
+            let _ = writeln!(out, "{name} {seconds:?}");
+        }
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_are_cumulative_per_route() {
+        let metrics = RequestMetrics::default();
+        metrics.observe(RouteClass::Files, Duration::from_micros(500));
+        metrics.observe(RouteClass::Files, Duration::from_millis(30));
+        metrics.observe(RouteClass::Files, Duration::from_secs(60));
+
+        let text = metrics.render(&SyncMetrics::default());
+        let line = |needle: &str| {
+            text.lines()
+                .find(|line| line.starts_with(needle))
+                .unwrap_or_else(|| panic!("missing {needle} in\n{text}"))
+                .rsplit(' ')
+                .next()
+                .expect("value")
+                .to_string()
+        };
+        let bucket = "repo_sync_request_duration_seconds_bucket";
+        assert_eq!(
+            line(&format!("{bucket}{{route=\"files\",le=\"0.001\"}}")),
+            "1"
+        );
+        assert_eq!(
+            line(&format!("{bucket}{{route=\"files\",le=\"0.05\"}}")),
+            "2"
+        );
+        assert_eq!(
+            line(&format!("{bucket}{{route=\"files\",le=\"10.0\"}}")),
+            "2"
+        );
+        assert_eq!(
+            line(&format!("{bucket}{{route=\"files\",le=\"+Inf\"}}")),
+            "3"
+        );
+        assert_eq!(
+            line("repo_sync_request_duration_seconds_count{route=\"files\"}"),
+            "3"
+        );
+        assert_eq!(
+            line("repo_sync_request_duration_seconds_count{route=\"health\"}"),
+            "0"
+        );
+        assert!(text.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn renders_sync_change_metrics() {
+        let metrics = RequestMetrics::default();
+        let text = metrics.render(&SyncMetrics::default());
+        assert!(text.contains("repo_sync_sha_changes_total 0\n"));
+        assert!(
+            !text
+                .lines()
+                .any(|line| line.starts_with("repo_sync_seconds_since_last_change "))
+        );
+
+        let text = metrics.render(&SyncMetrics {
+            sha_changes: 3,
+            seconds_since_last_change: Some(12.5),
+        });
+        assert!(text.contains("repo_sync_sha_changes_total 3\n"));
+        assert!(text.contains("repo_sync_seconds_since_last_change 12.5\n"));
+        assert!(text.ends_with("# EOF\n"));
+    }
+}
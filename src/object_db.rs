@@ -0,0 +1,174 @@
+use std::{fs, io, path::Path};
+
+use anyhow::{Context, Result};
+use git2::{ObjectType, Oid, Repository};
+use serde::Serialize;
+
+use crate::sync::lock_mirror;
+
+/// How much space the mirror's `.git` takes, reported in `/meta` so bloat
+/// that needs a gc is visible.
+#[derive(Debug, Clone, Copy, Serialize, Default, PartialEq, Eq)]
+pub struct ObjectDbStats {
+    /// Everything under `.git`: objects, index, logs and refs.
+    pub size_bytes: u64,
+    pub pack_count: usize,
+}
+
+/// Measures `<mirror_dir>/.git` without following symlinks.
+pub fn object_db_stats(mirror_dir: &Path) -> Result<ObjectDbStats> {
+    let git_dir = mirror_dir.join(".git");
+    let size_bytes =
+        dir_size(&git_dir).with_context(|| format!("failed measuring {}", git_dir.display()))?;
+    let pack_count = pack_files(&git_dir.join("objects/pack"))?
+        .iter()
+        .filter(|name| name.ends_with(".pack"))
+        .count();
+    Ok(ObjectDbStats {
+        size_bytes,
+        pack_count,
+    })
+}
+
+fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        total += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(total)
+}
+
+/// File names in `objects/pack`, empty when there is no such directory yet.
+fn pack_files(pack_dir: &Path) -> Result<Vec<String>> {
+    let entries = match fs::read_dir(pack_dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(
+                anyhow::Error::new(err).context(format!("failed listing {}", pack_dir.display()))
+            );
+        }
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry.with_context(|| format!("failed listing {}", pack_dir.display()))?;
+        if let Some(name) = entry.file_name().to_str() {
+            names.push(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// Repacks everything reachable from refs, `HEAD` and their reflogs into a
+/// single pack, then removes the old packs and loose objects, the part of
+/// `git gc` that reclaims space. libgit2 has no gc and the container image no
+/// `git`, so the pack is built in-process. Packs with a `.keep` file are left
+/// alone. Holds the mirror lock throughout, so a sync starting meanwhile fails
+/// with `mirror_locked` and retries on its next interval.
+pub fn gc(mirror_dir: &Path) -> Result<ObjectDbStats> {
+    let _lock = lock_mirror(mirror_dir)?;
+    let repo = Repository::open(mirror_dir)
+        .with_context(|| format!("failed opening repo in {}", mirror_dir.display()))?;
+
+    let mut walk = repo.revwalk().context("failed starting revwalk")?;
+    let mut tags = Vec::new();
+    let mut ref_names = vec!["HEAD".to_string()];
+    for reference in repo.references().context("failed listing refs")? {
+        let reference = reference.context("failed reading ref")?;
+        if let Some(name) = reference.name() {
+            ref_names.push(name.to_string());
+        }
+        if let Some(id) = reference.target()
+            && repo.find_tag(id).is_ok()
+        {
+            tags.push(id);
+        }
+    }
+    for name in &ref_names {
+        let mut tips: Vec<Oid> = repo.refname_to_id(name).into_iter().collect();
+        if let Ok(reflog) = repo.reflog(name) {
+            tips.extend(
+                reflog
+                    .iter()
+                    .flat_map(|entry| [entry.id_old(), entry.id_new()]),
+            );
+        }
+        for id in tips {
+            if let Ok(commit) = repo
+                .find_object(id, None)
+                .and_then(|object| object.peel(ObjectType::Commit))
+            {
+                walk.push(commit.id())
+                    .with_context(|| format!("failed walking from {id}"))?;
+            }
+        }
+    }
+
+    let mut packer = repo.packbuilder().context("failed starting packbuilder")?;
+    packer
+        .insert_walk(&mut walk)
+        .context("failed collecting reachable objects")?;
+    for id in tags {
+        packer
+            .insert_recursive(id, None)
+            .with_context(|| format!("failed collecting tag {id}"))?;
+    }
+    let objects_dir = mirror_dir.join(".git/objects");
+    let pack_dir = objects_dir.join("pack");
+    if packer.object_count() > 0 {
+        fs::create_dir_all(&pack_dir)
+            .with_context(|| format!("failed creating {}", pack_dir.display()))?;
+        packer.write(&pack_dir, 0).context("failed writing pack")?;
+        let new_pack = format!(
+            "pack-{}",
+            packer.name().context("packbuilder produced no pack name")?
+        );
+        remove_old_packs(&pack_dir, &new_pack)?;
+        remove_loose_objects(&objects_dir)?;
+    }
+    object_db_stats(mirror_dir)
+}
+
+/// Removes every `pack-*` file not belonging to `keep` or to a pack marked
+/// with a `.keep` file.
+fn remove_old_packs(pack_dir: &Path, keep: &str) -> Result<()> {
+    let names = pack_files(pack_dir)?;
+    for name in &names {
+        let Some((stem, _)) = name.split_once('.') else {
+            continue;
+        };
+        if !stem.starts_with("pack-")
+            || stem == keep
+            || names.iter().any(|other| *other == format!("{stem}.keep"))
+        {
+            continue;
+        }
+        let path = pack_dir.join(name);
+        fs::remove_file(&path).with_context(|| format!("failed removing {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Removes the two-hex-digit fan-out directories holding loose objects.
+fn remove_loose_objects(objects_dir: &Path) -> Result<()> {
+    for entry in fs::read_dir(objects_dir)
+        .with_context(|| format!("failed listing {}", objects_dir.display()))?
+    {
+        let entry = entry.with_context(|| format!("failed listing {}", objects_dir.display()))?;
+        let name = entry.file_name();
+        let is_fan_out = name
+            .to_str()
+            .is_some_and(|name| name.len() == 2 && name.bytes().all(|b| b.is_ascii_hexdigit()));
+        if is_fan_out {
+            fs::remove_dir_all(entry.path())
+                .with_context(|| format!("failed removing {}", entry.path().display()))?;
+        }
+    }
+    Ok(())
+}
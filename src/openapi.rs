@@ -0,0 +1,461 @@
+use serde_json::{Map, Value, json};
+
+/// OpenAPI 3 document for the routes one listener serves. `endpoints` are the
+/// axum route paths that listener mounted and `mount_prefixes` the
+/// `SERVE_MOUNTS` prefixes, so file routes and disabled routes match what is
+/// actually served. Schemas mirror the response structs in `server`.
+pub fn document(endpoints: &[String], mount_prefixes: &[String]) -> Value {
+    let mut paths = Map::new();
+    for endpoint in endpoints {
+        let mut item = Map::new();
+        if let Some(get) = operation(endpoint, mount_prefixes) {
+            item.insert("get".to_string(), get);
+        }
+        if let Some(post) = post_operation(endpoint) {
+            item.insert("post".to_string(), post);
+        }
+        if !item.is_empty() {
+            paths.insert(openapi_path(endpoint), Value::Object(item));
+        }
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "repo-sync",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": { "schemas": schemas() },
+    })
+}
+
+/// `/b/:branch/files/*path` becomes `/b/{branch}/files/{path}`.
+fn openapi_path(endpoint: &str) -> String {
+    endpoint
+        .split('/')
+        .map(|segment| match segment.strip_prefix([':', '*']) {
+            Some(name) => format!("{{{name}}}"),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn operation(endpoint: &str, mount_prefixes: &[String]) -> Option<Value> {
+    let op = match endpoint {
+        "/health" => ok("Service and sync status", "Health"),
+        "/readyz" => json!({
+            "summary": "Readiness probe",
+            "responses": {
+                "200": json_response("Ready", "Ready"),
+                "503": json_response("Not ready", "Ready"),
+            },
+        }),
+        "/.well-known/health" => json!({
+            "summary": "Health in application/health+json format",
+            "responses": {
+                "200": { "description": "Passing or warning", "content": { "application/health+json": {} } },
+                "503": { "description": "Failing", "content": { "application/health+json": {} } },
+            },
+        }),
+        "/version" => ok("Build of this binary", "Version"),
+        "/robots.txt" => json!({
+            "summary": "ROBOTS_TXT for crawlers",
+            "responses": { "200": {
+                "description": "robots.txt",
+                "content": { "text/plain": {} },
+            } },
+        }),
+        "/favicon.ico" => json!({
+            "summary": "Empty favicon, so browsers stop asking",
+            "responses": { "204": { "description": "No icon" } },
+        }),
+        "/exists/*path" => json!({
+            "summary": "Whether a mounted path exists",
+            "parameters": [path_param("path")],
+            "responses": {
+                "200": json_response("Existence and size", "Exists"),
+                "404": error_response("Invalid or hidden path"),
+            },
+        }),
+        "/snapshot.tar.gz" => json!({
+            "summary": "Gzipped tar of the mounted paths at the current SHA",
+            "responses": {
+                "200": {
+                    "description": "Snapshot; the ETag is the quoted SHA",
+                    "content": { "application/gzip": {} },
+                },
+                "304": { "description": "If-None-Match matches the current SHA" },
+                "404": error_response("Nothing synced yet"),
+                "500": error_response("The synced commit can't be read from the mirror"),
+                "503": error_response("Maintenance mode"),
+            },
+        }),
+        "/list-stream/*path" => json!({
+            "summary": "Every entry below a mounted directory as newline-delimited JSON, streamed",
+            "parameters": [path_param("path")],
+            "responses": {
+                "200": {
+                    "description": "One {name, kind, size} object per line; directories at the depth limit add truncated: true",
+                    "content": { "application/x-ndjson": {} },
+                },
+                "404": error_response("Missing, hidden or not a directory"),
+                "503": error_response("Maintenance mode or a checkout in progress"),
+            },
+        }),
+        "/schemas/index.json" => json!({
+            "summary": "JSON Schema ids mapped to their paths under the serve root",
+            "responses": {
+                "200": {
+                    "description": "Schema index",
+                    "content": { "application/json": { "schema": {
+                        "type": "object",
+                        "additionalProperties": { "type": "string" },
+                    } } },
+                },
+            },
+        }),
+        "/meta" => json!({
+            "summary": "Repository, branch and sync metadata",
+            "parameters": [query_param("refresh", "Re-read the remote branch tip first")],
+            "responses": { "200": json_response("Metadata", "Meta") },
+        }),
+        "/meta/history" => json!({
+            "summary": "Recent sync attempts, newest first",
+            "responses": {
+                "200": {
+                    "description": "Sync attempts",
+                    "content": { "application/json": { "schema": {
+                        "type": "array",
+                        "items": { "type": "object" },
+                    } } },
+                },
+            },
+        }),
+        "/meta/logs" => json!({
+            "summary": "Recent log records, newest first",
+            "responses": {
+                "200": {
+                    "description": "Log records",
+                    "content": { "application/json": { "schema": {
+                        "type": "array",
+                        "items": { "type": "object" },
+                    } } },
+                },
+                "401": error_response("Missing or wrong ADMIN_TOKEN"),
+            },
+        }),
+        "/meta/drift" => json!({
+            "summary": "How far the synced commit trails the remote branch tip",
+            "parameters": [query_param("refresh", "Re-read the remote branch tip first")],
+            "responses": {
+                "200": {
+                    "description": "Drift",
+                    "content": { "application/json": { "schema": {
+                        "type": "object",
+                        "properties": {
+                            "synced_sha": { "type": ["string", "null"] },
+                            "remote_tip_sha": { "type": ["string", "null"] },
+                            "behind_commits": { "type": ["integer", "null"] },
+                            "behind_seconds": { "type": ["integer", "null"] },
+                        },
+                    } } },
+                },
+            },
+        }),
+        "/metrics" => json!({
+            "summary": "OpenMetrics request latency and SHA change metrics",
+            "responses": {
+                "200": { "description": "Metrics", "content": { "application/openmetrics-text": {} } },
+            },
+        }),
+        "/stats" => json!({
+            "summary": "The /metrics values as JSON",
+            "responses": {
+                "200": {
+                    "description": "Request histograms per route class and SHA change metrics",
+                    "content": { "application/json": { "schema": { "type": "object" } } },
+                },
+            },
+        }),
+        "/diff" => json!({
+            "summary": "Paths changed between two mirrored commits",
+            "parameters": [
+                query_param("from", "Base SHA; defaults to the previous snapshot"),
+                query_param("to", "Target SHA; defaults to the current snapshot"),
+            ],
+            "responses": {
+                "200": json_response("Changed paths", "Diff"),
+                "400": error_response("Malformed SHA"),
+                "404": error_response("Unknown SHA or no snapshot to diff"),
+            },
+        }),
+        "/admin/maintenance" => json!({
+            "summary": "Whether maintenance mode is on",
+            "responses": {
+                "200": json_response("Whether maintenance mode is on", "Maintenance"),
+                "401": error_response("Missing or wrong ADMIN_TOKEN"),
+            },
+        }),
+        "/openapi.json" => json!({
+            "summary": "This document",
+            "responses": { "200": { "description": "OpenAPI document", "content": { "application/json": {} } } },
+        }),
+        _ => {
+            let (branch, prefix) = match endpoint.strip_prefix("/b/:branch") {
+                Some(rest) => (true, rest.strip_suffix("/*path")?),
+                None => (false, endpoint.strip_suffix("/*path")?),
+            };
+            if !mount_prefixes.iter().any(|p| p == prefix) {
+                return None;
+            }
+            file_operation(branch)
+        }
+    };
+    Some(op)
+}
+
+/// The few routes that also accept `POST`.
+fn post_operation(endpoint: &str) -> Option<Value> {
+    let op = match endpoint {
+        "/admin/maintenance" => json!({
+            "summary": "Turn maintenance mode on or off",
+            "requestBody": {
+                "required": true,
+                "content": { "application/json": { "schema": {
+                    "type": "object",
+                    "required": ["enabled"],
+                    "properties": { "enabled": { "type": "boolean" } },
+                } } },
+            },
+            "responses": {
+                "200": json_response("Maintenance mode after the change", "Maintenance"),
+                "401": error_response("Missing or wrong ADMIN_TOKEN"),
+            },
+        }),
+        "/admin/gc" => json!({
+            "summary": "Repack the mirror's objects into one pack and drop unreachable ones",
+            "responses": {
+                "200": {
+                    "description": "Object database after the gc",
+                    "content": { "application/json": { "schema": {
+                        "type": "object",
+                        "properties": {
+                            "object_db": { "$ref": "#/components/schemas/ObjectDb" },
+                            "last_gc_at": { "type": "string", "format": "date-time" },
+                        },
+                    } } },
+                },
+                "401": error_response("Missing or wrong ADMIN_TOKEN"),
+                "404": error_response("SYNC_MODE=none"),
+                "409": error_response("A sync holds the mirror lock"),
+                "500": error_response("Repack failed"),
+            },
+        }),
+        "/admin/purge-cache" => json!({
+            "summary": "Drop every cached strong ETag",
+            "responses": {
+                "200": {
+                    "description": "How many cache entries were dropped",
+                    "content": { "application/json": { "schema": {
+                        "type": "object",
+                        "properties": { "purged": { "type": "integer" } },
+                    } } },
+                },
+                "401": error_response("Missing or wrong ADMIN_TOKEN"),
+            },
+        }),
+        _ => return None,
+    };
+    Some(op)
+}
+
+fn file_operation(branch: bool) -> Value {
+    let mut parameters = vec![path_param("path")];
+    if branch {
+        parameters.insert(0, path_param("branch"));
+    }
+    parameters.extend([
+        query_param("download", "Serve as an attachment"),
+        query_param("raw", "Skip markdown rendering"),
+        query_param("recursive", "Recursive directory listing"),
+        json!({
+            "name": "X-If-Repo-Sha",
+            "in": "header",
+            "required": false,
+            "description": "304 for any file while this commit is the one served",
+            "schema": { "type": "string" },
+        }),
+    ]);
+    json!({
+        "summary": "File contents from the mirror",
+        "parameters": parameters,
+        "responses": {
+            "200": { "description": "File bytes", "content": { "*/*": {} } },
+            "206": { "description": "Requested byte range" },
+            "301": { "description": "Matched by redirects.json (302 with REDIRECT_STATUS=302)" },
+            "304": { "description": "Not modified, or X-If-Repo-Sha is the current SHA" },
+            "308": { "description": "Directory requested without a trailing slash (DIR_TRAILING_SLASH_REDIRECT)" },
+            "403": error_response("Path escapes the serve root (404 with HIDE_FORBIDDEN)"),
+            "404": error_response("Missing, hidden, not a file, or a file requested with a trailing slash"),
+            "410": error_response("Deleted by the last sync within GONE_WINDOW_SECONDS"),
+            "413": error_response("File larger than the configured limit"),
+            "414": error_response("Path too long"),
+            "503": error_response("Checkout outlasted SERVE_GATE_TIMEOUT_MS, or maintenance mode"),
+            "504": error_response("Handler outlasted REQUEST_TIMEOUT_SECONDS"),
+        },
+    })
+}
+
+fn ok(summary: &str, schema: &str) -> Value {
+    json!({ "summary": summary, "responses": { "200": json_response(summary, schema) } })
+}
+
+fn json_response(description: &str, schema: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": {
+            "$ref": format!("#/components/schemas/{schema}"),
+        } } },
+    })
+}
+
+fn error_response(description: &str) -> Value {
+    json_response(description, "Error")
+}
+
+fn path_param(name: &str) -> Value {
+    json!({ "name": name, "in": "path", "required": true, "schema": { "type": "string" } })
+}
+
+fn query_param(name: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "required": false,
+        "description": description,
+        "schema": { "type": "string" },
+    })
+}
+
+fn nullable(kind: &str) -> Value {
+    json!({ "type": kind, "nullable": true })
+}
+
+fn timestamp() -> Value {
+    json!({ "type": "string", "format": "date-time", "nullable": true })
+}
+
+fn schemas() -> Value {
+    json!({
+        "Error": {
+            "type": "object",
+            "required": ["code", "error"],
+            "properties": {
+                "code": { "type": "string" },
+                "error": { "type": "string" },
+            },
+        },
+        "Health": {
+            "type": "object",
+            "properties": {
+                "status": { "type": "string", "enum": ["ok", "degraded", "maintenance"] },
+                "current_sha": nullable("string"),
+                "last_success_at": timestamp(),
+                "last_error": nullable("string"),
+                "warnings": { "type": "array", "items": { "type": "string" } },
+            },
+        },
+        "Ready": {
+            "type": "object",
+            "properties": {
+                "ready": { "type": "boolean" },
+                "current_sha": nullable("string"),
+                "consecutive_failures": { "type": "integer" },
+                "check_output": { "type": "string" },
+            },
+        },
+        "Version": {
+            "type": "object",
+            "properties": {
+                "version": { "type": "string" },
+                "git_commit": { "type": "string" },
+                "built_at": timestamp(),
+            },
+        },
+        "Exists": {
+            "type": "object",
+            "properties": {
+                "exists": { "type": "boolean" },
+                "is_dir": { "type": "boolean" },
+                "size": nullable("integer"),
+                "sha": nullable("string"),
+            },
+        },
+        "Meta": {
+            "type": "object",
+            "properties": {
+                "synced_repo_url": nullable("string"),
+                "branch": nullable("string"),
+                "serve_root": { "type": "string" },
+                "sync_interval_seconds": { "type": "integer" },
+                "now": { "type": "string", "format": "date-time" },
+                "sync": { "type": "object" },
+                "branches": {
+                    "type": "object",
+                    "additionalProperties": nullable("string"),
+                },
+            },
+        },
+        "ObjectDb": {
+            "type": "object",
+            "properties": {
+                "size_bytes": { "type": "integer" },
+                "pack_count": { "type": "integer" },
+            },
+        },
+        "Maintenance": {
+            "type": "object",
+            "properties": { "maintenance": { "type": "boolean" } },
+        },
+        "Diff": {
+            "type": "object",
+            "properties": {
+                "from": { "type": "string" },
+                "to": { "type": "string" },
+                "truncated": { "type": "boolean" },
+                "changes": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "path": { "type": "string" },
+                            "change": {
+                                "type": "string",
+                                "enum": ["added", "deleted", "modified", "type_changed"],
+                            },
+                            "old_size": nullable("integer"),
+                            "new_size": nullable("integer"),
+                            "size_delta": { "type": "integer" },
+                        },
+                    },
+                },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_route_paths_to_openapi_templates() {
+        assert_eq!(openapi_path("/files/*path"), "/files/{path}");
+        assert_eq!(
+            openapi_path("/b/:branch/files/*path"),
+            "/b/{branch}/files/{path}"
+        );
+        assert_eq!(openapi_path("/meta/history"), "/meta/history");
+    }
+}
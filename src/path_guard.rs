@@ -1,9 +1,22 @@
-use std::path::{Component, Path, PathBuf};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Component, Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
 
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow};
 
 pub fn normalize_relative_path(value: &str) -> Result<String> {
-    let path = Path::new(value);
+    normalize_relative(Path::new(value))?
+        .into_os_string()
+        .into_string()
+        .map_err(|_| anyhow!("path contains invalid unicode"))
+}
+
+/// Lexically normalizes `path` as relative to some root, keeping component
+/// bytes as-is so filenames that aren't valid UTF-8 survive.
+pub fn normalize_relative(path: &Path) -> Result<PathBuf> {
     let mut normalized = PathBuf::new();
 
     for component in path.components() {
@@ -21,15 +34,55 @@ pub fn normalize_relative_path(value: &str) -> Result<String> {
         }
     }
 
-    let normalized_str = normalized
-        .to_str()
-        .ok_or_else(|| anyhow!("path contains invalid unicode"))?;
-    Ok(normalized_str.to_string())
+    Ok(normalized)
+}
+
+/// `request_path` normalized and joined onto `root`. When `canonical_root` is
+/// given and the joined path exists, the path with every symlink resolved must
+/// also lie under it, so a symlinked directory or file in the tree can't point
+/// outside the root. The returned path is the lexical one either way.
+pub fn resolve_under_root(
+    root: &Path,
+    canonical_root: Option<&Path>,
+    request_path: impl AsRef<Path>,
+) -> Result<PathBuf> {
+    let path = root.join(normalize_relative(request_path.as_ref())?);
+    let Some(canonical_root) = canonical_root else {
+        return Ok(path);
+    };
+    match fs::canonicalize(&path) {
+        Ok(canonical) if canonical.starts_with(canonical_root) => Ok(path),
+        Ok(_) => Err(anyhow!("path escapes root through a symlink")),
+        // Nothing to serve, and nothing for a symlink to leak.
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(path),
+        Err(err) => Err(err).with_context(|| format!("failed resolving {}", path.display())),
+    }
+}
+
+/// Canonical forms of serve roots, each kept until the tree it was resolved
+/// in changes, since the root itself may sit behind a symlink in the repo.
+#[derive(Default)]
+pub struct CanonicalRoots {
+    roots: Mutex<HashMap<PathBuf, (Option<String>, PathBuf)>>,
 }
 
-pub fn resolve_under_root(root: &Path, request_path: &str) -> Result<PathBuf> {
-    let normalized = normalize_relative_path(request_path)?;
-    Ok(root.join(normalized))
+impl CanonicalRoots {
+    /// `root` with symlinks resolved, cached per `tree` (the synced SHA).
+    /// `None` while the root doesn't exist.
+    pub fn get(&self, root: &Path, tree: Option<&str>) -> Option<PathBuf> {
+        let mut roots = self.roots.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((cached_tree, canonical)) = roots.get(root)
+            && cached_tree.as_deref() == tree
+        {
+            return Some(canonical.clone());
+        }
+        let canonical = fs::canonicalize(root).ok()?;
+        roots.insert(
+            root.to_path_buf(),
+            (tree.map(str::to_string), canonical.clone()),
+        );
+        Some(canonical)
+    }
 }
 
 #[cfg(test)]
@@ -61,4 +114,56 @@ mod tests {
     fn rejects_absolute_paths() {
         assert!(normalize_relative_path("/var/data/file").is_err());
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_non_utf8_components() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let request = Path::new(OsStr::from_bytes(b"legacy/./caf\xe9.txt"));
+        assert_eq!(
+            resolve_under_root(Path::new("/srv"), None, request).expect("valid path"),
+            Path::new(OsStr::from_bytes(b"/srv/legacy/caf\xe9.txt"))
+        );
+        assert!(normalize_relative(Path::new(OsStr::from_bytes(b"../caf\xe9"))).is_err());
+        assert!(normalize_relative_path("caf\u{e9}.txt").is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlinks_leading_outside_the_root() {
+        use std::os::unix::fs::symlink;
+
+        let temp = tempfile::tempdir().expect("temp dir");
+        let root = temp.path().join("root");
+        let outside = temp.path().join("outside");
+        fs::create_dir_all(root.join("docs")).expect("create root");
+        fs::create_dir_all(&outside).expect("create outside dir");
+        fs::write(root.join("docs/a.txt"), "in").expect("write file");
+        fs::write(outside.join("secret.txt"), "out").expect("write file");
+        symlink(&outside, root.join("escape")).expect("dir symlink");
+        symlink(outside.join("secret.txt"), root.join("secret.txt")).expect("file symlink");
+        symlink(root.join("docs"), root.join("alias")).expect("inner symlink");
+
+        let roots = CanonicalRoots::default();
+        let canonical = roots.get(&root, Some("abc")).expect("root exists");
+        let resolve = |path: &str| resolve_under_root(&root, Some(&canonical), path);
+
+        assert_eq!(
+            resolve("docs/a.txt").expect("plain file"),
+            root.join("docs/a.txt")
+        );
+        assert_eq!(
+            resolve("alias/a.txt").expect("symlink within root"),
+            root.join("alias/a.txt")
+        );
+        assert!(resolve("escape/secret.txt").is_err());
+        assert!(resolve("secret.txt").is_err());
+        assert!(resolve("escape/missing.txt").is_ok());
+        assert!(
+            roots
+                .get(&temp.path().join("missing"), Some("abc"))
+                .is_none()
+        );
+    }
 }
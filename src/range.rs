@@ -0,0 +1,113 @@
+/// Outcome of interpreting a `Range` header against a representation length.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    /// Inclusive byte offsets to serve with 206.
+    Satisfiable { start: u64, end: u64 },
+    /// Syntactically valid but outside the representation; answer 416.
+    Unsatisfiable,
+    /// Malformed, non-byte or multi-range request; serve the full body.
+    Ignored,
+}
+
+/// Parses a single `bytes=` range (`a-b`, `a-` or `-suffix`). Multi-range
+/// requests are ignored rather than answered with multipart bodies.
+pub fn parse_range(value: &str, len: u64) -> ByteRange {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return ByteRange::Ignored;
+    };
+    if spec.contains(',') {
+        return ByteRange::Ignored;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Ignored;
+    };
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        let Ok(suffix) = end.parse::<u64>() else {
+            return ByteRange::Ignored;
+        };
+        if suffix == 0 || len == 0 {
+            return ByteRange::Unsatisfiable;
+        }
+        return ByteRange::Satisfiable {
+            start: len.saturating_sub(suffix),
+            end: len - 1,
+        };
+    }
+
+    let Ok(start) = start.parse::<u64>() else {
+        return ByteRange::Ignored;
+    };
+    let end = if end.is_empty() {
+        None
+    } else {
+        match end.parse::<u64>() {
+            Ok(end) if end >= start => Some(end),
+            _ => return ByteRange::Ignored,
+        }
+    };
+    if start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Satisfiable {
+        start,
+        end: end.map_or(len - 1, |end| end.min(len - 1)),
+    }
+}
+
+/// Whether an `If-Range` validator still identifies the current representation.
+/// Entity tags use strong comparison, so weak ETags never match.
+pub fn if_range_matches(if_range: &str, etag: &str, last_modified: Option<&str>) -> bool {
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return !etag.starts_with("W/") && if_range == etag;
+    }
+    last_modified.is_some_and(|last_modified| last_modified == if_range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_byte_ranges() {
+        assert_eq!(
+            parse_range("bytes=0-3", 10),
+            ByteRange::Satisfiable { start: 0, end: 3 }
+        );
+        assert_eq!(
+            parse_range("bytes=5-", 10),
+            ByteRange::Satisfiable { start: 5, end: 9 }
+        );
+        assert_eq!(
+            parse_range("bytes=-4", 10),
+            ByteRange::Satisfiable { start: 6, end: 9 }
+        );
+        assert_eq!(
+            parse_range("bytes=8-100", 10),
+            ByteRange::Satisfiable { start: 8, end: 9 }
+        );
+        assert_eq!(parse_range("bytes=10-", 10), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-1,4-5", 10), ByteRange::Ignored);
+        assert_eq!(parse_range("items=0-1", 10), ByteRange::Ignored);
+        assert_eq!(parse_range("bytes=5-2", 10), ByteRange::Ignored);
+    }
+
+    #[test]
+    fn if_range_uses_strong_comparison() {
+        assert!(if_range_matches("\"abc\"", "\"abc\"", None));
+        assert!(!if_range_matches("\"abc\"", "\"def\"", None));
+        assert!(!if_range_matches("W/\"1-2\"", "W/\"1-2\"", None));
+        assert!(if_range_matches(
+            "Thu, 02 Jan 2020 03:04:05 GMT",
+            "\"abc\"",
+            Some("Thu, 02 Jan 2020 03:04:05 GMT")
+        ));
+        assert!(!if_range_matches(
+            "Thu, 02 Jan 2020 03:04:05 GMT",
+            "\"abc\"",
+            None
+        ));
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Buckets untouched for this long are dropped during cleanup.
+const IDLE_BUCKET_TTL: Duration = Duration::from_secs(300);
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Token-bucket limiter keyed by client IP. Clients whose address isn't
+/// known, such as Unix-socket peers, share the `None` bucket.
+pub struct RateLimiter {
+    rate_per_second: f64,
+    burst: f64,
+    state: Mutex<LimiterState>,
+}
+
+struct LimiterState {
+    buckets: HashMap<Option<IpAddr>, Bucket>,
+    last_cleanup: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate_per_second: f64, burst: u32) -> Self {
+        Self {
+            rate_per_second,
+            burst: f64::from(burst.max(1)),
+            state: Mutex::new(LimiterState {
+                buckets: HashMap::new(),
+                last_cleanup: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes a token for `ip`, or returns how long until one is available.
+    pub fn check(&self, ip: Option<IpAddr>, now: Instant) -> Result<(), Duration> {
+        let Ok(mut state) = self.state.lock() else {
+            return Ok(());
+        };
+        if now.duration_since(state.last_cleanup) >= CLEANUP_INTERVAL {
+            state
+                .buckets
+                .retain(|_, bucket| now.duration_since(bucket.updated_at) < IDLE_BUCKET_TTL);
+            state.last_cleanup = now;
+        }
+
+        let bucket = state.buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated_at: now,
+        });
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate_per_second).min(self.burst);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.rate_per_second,
+            ))
+        }
+    }
+
+    pub fn tracked_clients(&self) -> usize {
+        self.state.lock().map_or(0, |state| state.buckets.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refills_over_time_and_drops_idle_buckets() {
+        let limiter = RateLimiter::new(2.0, 2);
+        let ip = Some(IpAddr::from([127, 0, 0, 1]));
+        let start = Instant::now();
+
+        assert!(limiter.check(ip, start).is_ok());
+        assert!(limiter.check(ip, start).is_ok());
+        let retry = limiter.check(ip, start).expect_err("bucket empty");
+        assert_eq!(retry, Duration::from_millis(500));
+        assert!(
+            limiter
+                .check(ip, start + Duration::from_millis(500))
+                .is_ok()
+        );
+
+        let other = Some(IpAddr::from([127, 0, 0, 2]));
+        assert!(limiter.check(other, start + IDLE_BUCKET_TTL * 2).is_ok());
+        assert_eq!(limiter.tracked_clients(), 1);
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    fmt,
+    path::{Component, Path},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result, anyhow};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{
+    Deserialize, Deserializer,
+    de::{MapAccess, Visitor},
+};
+use tracing::warn;
+
+pub const REDIRECTS_FILE_NAME: &str = "redirects.json";
+
+/// Source globs mapped to target paths from `redirects.json` at the serve
+/// root, reloaded whenever the synced SHA changes.
+#[derive(Default)]
+pub struct Redirects {
+    inner: Mutex<Option<(Option<String>, Arc<Rules>)>>,
+}
+
+impl Redirects {
+    /// Where a request for `relative` (within a mount) should go at `sha`.
+    pub fn target(&self, serve_root: &Path, sha: Option<&str>, relative: &Path) -> Option<String> {
+        self.rules(serve_root, sha).target(relative)
+    }
+
+    fn rules(&self, serve_root: &Path, sha: Option<&str>) -> Arc<Rules> {
+        let Ok(mut inner) = self.inner.lock() else {
+            return Arc::default();
+        };
+        if let Some((cached_sha, rules)) = inner.as_ref()
+            && cached_sha.as_deref() == sha
+        {
+            return rules.clone();
+        }
+        let rules = Arc::new(load(serve_root));
+        *inner = Some((sha.map(str::to_string), rules.clone()));
+        rules
+    }
+}
+
+/// Targets in file order, with their source globs compiled into one set.
+#[derive(Default)]
+struct Rules {
+    sources: GlobSet,
+    targets: Vec<String>,
+}
+
+impl Rules {
+    /// The target of the first rule, in file order, matching `relative`.
+    fn target(&self, relative: &Path) -> Option<String> {
+        let first = self.sources.matches(relative).into_iter().min()?;
+        Some(self.targets[first].clone())
+    }
+}
+
+fn load(serve_root: &Path) -> Rules {
+    let path = serve_root.join(REDIRECTS_FILE_NAME);
+    if !path.is_file() {
+        return Rules::default();
+    }
+    match std::fs::read(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(|bytes| parse(&bytes))
+    {
+        Ok(rules) => rules,
+        Err(err) => {
+            warn!("ignoring {}: {err:#}", path.display());
+            Rules::default()
+        }
+    }
+}
+
+/// Parses a JSON object of source glob to target path. `*` stays within one
+/// path segment and `**` spans several. Rules whose target is absolute or
+/// steps outside the mount with `..` are skipped with a warning.
+fn parse(bytes: &[u8]) -> Result<Rules> {
+    let OrderedMap(entries) = serde_json::from_slice(bytes).context("invalid JSON")?;
+    let mut sources = GlobSetBuilder::new();
+    let mut targets = Vec::new();
+    for (source, target) in entries {
+        let glob = match source_glob(&source) {
+            Ok(glob) => glob,
+            Err(err) => {
+                warn!("skipping redirect from {source}: {err:#}");
+                continue;
+            }
+        };
+        if let Err(err) = check_target(&target) {
+            warn!("skipping redirect from {source}: {err:#}");
+            continue;
+        }
+        sources.add(glob);
+        targets.push(target);
+    }
+    Ok(Rules {
+        sources: sources.build().context("failed building redirect globs")?,
+        targets,
+    })
+}
+
+fn source_glob(source: &str) -> Result<Glob> {
+    GlobBuilder::new(source.trim_start_matches('/'))
+        .literal_separator(true)
+        .build()
+        .context("invalid glob")
+}
+
+fn check_target(target: &str) -> Result<()> {
+    let path = Path::new(target);
+    if target.is_empty()
+        || !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(anyhow!(
+            "target {target} is not a relative path inside the mount"
+        ));
+    }
+    Ok(())
+}
+
+/// A JSON object's entries in document order; `serde_json::Map` sorts them.
+struct OrderedMap(Vec<(String, String)>);
+
+impl<'de> Deserialize<'de> for OrderedMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor;
+
+        impl<'de> Visitor<'de> for EntriesVisitor {
+            type Value = OrderedMap;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an object of source globs to target paths")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<OrderedMap, A::Error> {
+                let mut entries = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(OrderedMap(entries))
+            }
+        }
+
+        deserializer.deserialize_map(EntriesVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn first_matching_rule_wins_and_unsafe_targets_are_skipped() {
+        let rules = parse(
+            br#"{
+                "old/exact.json": "new/exact.json",
+                "old/*.json": "new/data.json",
+                "old/**": "new/index.html",
+                "escape.json": "../outside.json",
+                "absolute.json": "/etc/passwd"
+            }"#,
+        )
+        .expect("valid rules");
+        let target = |path: &str| rules.target(Path::new(path));
+
+        assert_eq!(target("old/exact.json").as_deref(), Some("new/exact.json"));
+        assert_eq!(target("old/a.json").as_deref(), Some("new/data.json"));
+        assert_eq!(
+            target("old/nested/a.json").as_deref(),
+            Some("new/index.html")
+        );
+        assert_eq!(target("escape.json"), None);
+        assert_eq!(target("absolute.json"), None);
+        assert_eq!(target("new/data.json"), None);
+    }
+
+    #[test]
+    fn reloads_on_sha_change() {
+        let temp = tempdir().expect("temp dir");
+        let file = temp.path().join(REDIRECTS_FILE_NAME);
+        std::fs::write(&file, r#"{"a.json": "b.json"}"#).expect("write rules");
+        let redirects = Redirects::default();
+        let target = |sha| redirects.target(temp.path(), Some(sha), Path::new("a.json"));
+
+        assert_eq!(target("one").as_deref(), Some("b.json"));
+        std::fs::write(&file, "{}").expect("rewrite rules");
+        assert_eq!(target("one").as_deref(), Some("b.json"));
+        assert_eq!(target("two"), None);
+    }
+}
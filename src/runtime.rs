@@ -0,0 +1,32 @@
+use anyhow::{Context, Result};
+use tokio::runtime::{Builder, Runtime};
+
+use crate::config::AppConfig;
+
+/// Builds the multi-threaded runtime with the configured worker and blocking
+/// pool sizes, so a long clone on `spawn_blocking` can't grow without bound.
+pub fn build_runtime(config: &AppConfig) -> Result<Runtime> {
+    Builder::new_multi_thread()
+        .worker_threads(config.worker_threads)
+        .max_blocking_threads(config.max_blocking_threads)
+        .enable_all()
+        .build()
+        .context("failed building tokio runtime")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_runtime_with_configured_worker_threads() {
+        let config = AppConfig {
+            worker_threads: 2,
+            max_blocking_threads: 4,
+            ..AppConfig::default()
+        };
+        let runtime = build_runtime(&config).expect("runtime");
+        assert_eq!(runtime.metrics().num_workers(), 2);
+        assert_eq!(runtime.block_on(async { 1 + 1 }), 2);
+    }
+}
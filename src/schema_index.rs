@@ -0,0 +1,175 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use tracing::warn;
+
+use crate::serve_ignore::ServeIgnore;
+
+/// Most files a scan parses; anything beyond is left out of the index.
+const MAX_SCANNED_FILES: usize = 10_000;
+
+/// Larger files are assumed not to be schemas and skipped unread.
+const MAX_SCHEMA_BYTES: u64 = 1024 * 1024;
+
+/// Schema `$id` (or `id`) to the path of the file declaring it, relative to
+/// the serve root. Rebuilt whenever the synced SHA changes.
+#[derive(Default)]
+pub struct SchemaIndex {
+    inner: Mutex<Option<(Option<String>, Arc<Index>)>>,
+}
+
+type Index = BTreeMap<String, String>;
+
+impl SchemaIndex {
+    /// The index for `serve_root` at `sha`, scanning the tree on first use
+    /// after a sync. Paths hidden by `ignore` are left out.
+    pub fn get(&self, serve_root: &Path, sha: Option<&str>, ignore: &ServeIgnore) -> Arc<Index> {
+        let Ok(mut inner) = self.inner.lock() else {
+            return Arc::default();
+        };
+        if let Some((cached_sha, index)) = inner.as_ref()
+            && cached_sha.as_deref() == sha
+        {
+            return index.clone();
+        }
+        let index = Arc::new(scan(serve_root, sha, ignore));
+        *inner = Some((sha.map(str::to_string), index.clone()));
+        index
+    }
+}
+
+/// Walks `serve_root` depth-first with entries sorted by name, so which file
+/// wins a duplicated id doesn't depend on directory order.
+fn scan(serve_root: &Path, sha: Option<&str>, ignore: &ServeIgnore) -> Index {
+    let mut index = BTreeMap::new();
+    let mut scanned = 0;
+    let mut pending = vec![serve_root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(read_dir) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut entries: Vec<_> = read_dir.flatten().collect();
+        entries.sort_by_key(|entry| entry.file_name());
+        // Popped last-in first-out, so push subdirectories in reverse.
+        let mut subdirs = Vec::new();
+        for entry in entries {
+            let path = entry.path();
+            let Ok(relative) = path.strip_prefix(serve_root) else {
+                continue;
+            };
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if entry.file_name() == ".git" || ignore.is_ignored(serve_root, sha, relative) {
+                continue;
+            }
+            if file_type.is_dir() {
+                subdirs.push(path);
+                continue;
+            }
+            let Some(parse) = parser_for(&path) else {
+                continue;
+            };
+            if !file_type.is_file()
+                || entry
+                    .metadata()
+                    .map_or(true, |m| m.len() > MAX_SCHEMA_BYTES)
+            {
+                continue;
+            }
+            if scanned == MAX_SCANNED_FILES {
+                warn!("schema index stopped after {MAX_SCANNED_FILES} files");
+                return index;
+            }
+            scanned += 1;
+            let id = fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|contents| parse(&contents));
+            let relative = relative.to_string_lossy().into_owned();
+            match id {
+                Ok(Some(id)) => {
+                    if let Some(existing) = index.get(&id) {
+                        warn!("schema id {id} in {relative} already declared by {existing}");
+                    } else {
+                        index.insert(id, relative);
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => warn!("skipping schema {relative}: {err:#}"),
+            }
+        }
+        pending.extend(subdirs.into_iter().rev());
+    }
+    index
+}
+
+type Parser = fn(&str) -> Result<Option<String>>;
+
+fn parser_for(path: &Path) -> Option<Parser> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "json" => Some(json_id),
+        "yaml" | "yml" => Some(yaml_id),
+        _ => None,
+    }
+}
+
+/// Top-level `$id`, falling back to the draft-04 `id`.
+fn json_id(contents: &str) -> Result<Option<String>> {
+    let value: serde_json::Value = serde_json::from_str(contents)?;
+    Ok(["$id", "id"]
+        .iter()
+        .find_map(|key| value.get(key)?.as_str().map(str::to_string)))
+}
+
+fn yaml_id(contents: &str) -> Result<Option<String>> {
+    let value: serde_norway::Value = serde_norway::from_str(contents)?;
+    Ok(["$id", "id"]
+        .iter()
+        .find_map(|key| value.get(key)?.as_str().map(str::to_string)))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn indexes_ids_from_json_and_yaml() {
+        let temp = tempdir().expect("temp dir");
+        std::fs::create_dir_all(temp.path().join("v1")).expect("create dir");
+        std::fs::write(
+            temp.path().join("v1/user.json"),
+            r#"{"$id": "https://example.com/user.json", "type": "object"}"#,
+        )
+        .expect("write json");
+        std::fs::write(
+            temp.path().join("order.yaml"),
+            "id: https://example.com/order\ntype: object\n",
+        )
+        .expect("write yaml");
+        std::fs::write(temp.path().join("broken.json"), "{").expect("write broken");
+        std::fs::write(temp.path().join("plain.json"), "[1, 2]").expect("write plain");
+
+        let index = SchemaIndex::default().get(temp.path(), Some("a"), &ServeIgnore::default());
+        assert_eq!(
+            *index,
+            BTreeMap::from([
+                (
+                    "https://example.com/order".to_string(),
+                    "order.yaml".to_string()
+                ),
+                (
+                    "https://example.com/user.json".to_string(),
+                    "v1/user.json".to_string()
+                ),
+            ])
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    convert::Infallible,
+    fs,
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Result;
+use axum::{Router, body::Body, extract::ConnectInfo};
+use hyper::{body::Incoming, service::service_fn};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UnixListener},
+};
+use tower::ServiceExt;
+use tracing::{debug, warn};
+
+/// Prefix of bind addresses naming a Unix domain socket path.
+pub const UNIX_ADDR_PREFIX: &str = "unix:";
+
+/// Per-connection protocol settings for `serve`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ServeOptions {
+    /// Serve connections opening with the HTTP/2 preface over HTTP/2.
+    pub http2: bool,
+    /// Close HTTP/1 connections whose request headers take longer than this
+    /// to arrive; `None` waits indefinitely.
+    pub header_read_timeout: Option<Duration>,
+}
+
+/// A bound `HTTP_BIND_ADDR` or `ADMIN_BIND_ADDR`.
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, SocketFile),
+}
+
+impl From<TcpListener> for Listener {
+    fn from(listener: TcpListener) -> Self {
+        Self::Tcp(listener)
+    }
+}
+
+impl Listener {
+    /// Binds `addr`: a TCP `ip:port`, or `unix:/path` for a Unix domain
+    /// socket created with permissions `uds_mode` when set.
+    pub async fn bind(addr: &str, uds_mode: Option<u32>) -> io::Result<Self> {
+        let Some(path) = addr.strip_prefix(UNIX_ADDR_PREFIX) else {
+            return TcpListener::bind(addr).await.map(Self::Tcp);
+        };
+        let path = Path::new(path);
+        remove_stale_socket(path)?;
+        let listener = UnixListener::bind(path)?;
+        // Owns the file from here on, so a failed chmod doesn't leave it behind.
+        let socket_file = SocketFile(path.to_path_buf());
+        if let Some(mode) = uds_mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+        Ok(Self::Unix(listener, socket_file))
+    }
+}
+
+/// Socket file of a bound Unix listener, removed when dropped so a clean
+/// shutdown doesn't leave it for the next start to trip over.
+pub struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.0)
+            && err.kind() != ErrorKind::NotFound
+        {
+            warn!("failed removing socket {}: {err}", self.0.display());
+        }
+    }
+}
+
+/// Removes a socket file left behind by a process that didn't shut down
+/// cleanly. Anything that isn't a socket, or a socket something still
+/// accepts on, is left alone.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(v) => v,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            "path exists and is not a socket",
+        ));
+    }
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(ErrorKind::AddrInUse.into()),
+        Err(err) if err.kind() == ErrorKind::ConnectionRefused => fs::remove_file(path),
+        Err(err) => Err(err),
+    }
+}
+
+/// Accept loop serving `router` on `listener`. TCP connections get
+/// `ConnectInfo<SocketAddr>` on every request; Unix socket peers have no IP
+/// address, so their client IP comes only from a trusted `X-Forwarded-For`.
+/// HTTP/1.1 is always served; with `options.http2` enabled, connections
+/// opening with the HTTP/2 preface (h2c prior knowledge) are served over HTTP/2
+/// and can multiplex concurrent requests.
+pub async fn serve(
+    listener: impl Into<Listener>,
+    router: Router,
+    options: ServeOptions,
+) -> Result<()> {
+    match listener.into() {
+        Listener::Tcp(listener) => loop {
+            if let Some((stream, remote_addr)) = accepted(listener.accept().await).await {
+                spawn_connection(stream, router.clone(), options, Some(remote_addr));
+            }
+        },
+        // The socket file lives as long as this future.
+        Listener::Unix(listener, _socket_file) => loop {
+            if let Some((stream, _)) = accepted(listener.accept().await).await {
+                spawn_connection(stream, router.clone(), options, None);
+            }
+        },
+    }
+}
+
+async fn accepted<T>(result: io::Result<T>) -> Option<T> {
+    match result {
+        Ok(v) => Some(v),
+        Err(err) => {
+            // Typically fd exhaustion; back off instead of spinning.
+            warn!("accept failed: {err}");
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            None
+        }
+    }
+}
+
+fn spawn_connection<S>(
+    stream: S,
+    router: Router,
+    options: ServeOptions,
+    remote_addr: Option<SocketAddr>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let service = service_fn(move |mut request: hyper::Request<Incoming>| {
+            if let Some(remote_addr) = remote_addr {
+                request.extensions_mut().insert(ConnectInfo(remote_addr));
+            }
+            let router = router.clone();
+            async move {
+                Ok::<_, Infallible>(
+                    router
+                        .oneshot(request.map(Body::new))
+                        .await
+                        .unwrap_or_else(|err| match err {}),
+                )
+            }
+        });
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        if !options.http2 {
+            builder = builder.http1_only();
+        }
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .header_read_timeout(options.header_read_timeout);
+        if let Err(err) = builder
+            .serve_connection(TokioIo::new(stream), service)
+            .await
+        {
+            match remote_addr {
+                Some(remote_addr) => {
+                    debug!("connection from {remote_addr} ended with error: {err}")
+                }
+                None => debug!("unix socket connection ended with error: {err}"),
+            }
+        }
+    });
+}
@@ -0,0 +1,83 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use tracing::warn;
+
+pub const IGNORE_FILE_NAME: &str = ".repo-sync-ignore";
+
+/// Gitignore-style patterns from `.repo-sync-ignore` at the serve root,
+/// reloaded whenever the synced SHA changes.
+#[derive(Default)]
+pub struct ServeIgnore {
+    inner: Mutex<Option<(Option<String>, Arc<Gitignore>)>>,
+}
+
+impl ServeIgnore {
+    /// Whether `relative` (to `serve_root`) is hidden from serving at `sha`.
+    pub fn is_ignored(&self, serve_root: &Path, sha: Option<&str>, relative: &Path) -> bool {
+        if relative == Path::new(IGNORE_FILE_NAME) {
+            return true;
+        }
+        self.matcher(serve_root, sha)
+            .matched_path_or_any_parents(relative, false)
+            .is_ignore()
+    }
+
+    fn matcher(&self, serve_root: &Path, sha: Option<&str>) -> Arc<Gitignore> {
+        let Ok(mut inner) = self.inner.lock() else {
+            return Arc::new(Gitignore::empty());
+        };
+        if let Some((cached_sha, matcher)) = inner.as_ref()
+            && cached_sha.as_deref() == sha
+        {
+            return matcher.clone();
+        }
+        let matcher = Arc::new(load(serve_root));
+        *inner = Some((sha.map(str::to_string), matcher.clone()));
+        matcher
+    }
+}
+
+fn load(serve_root: &Path) -> Gitignore {
+    let path = serve_root.join(IGNORE_FILE_NAME);
+    if !path.is_file() {
+        return Gitignore::empty();
+    }
+    let mut builder = GitignoreBuilder::new(serve_root);
+    if let Some(err) = builder.add(&path) {
+        warn!("failed parsing {}: {err}", path.display());
+    }
+    builder.build().unwrap_or_else(|err| {
+        warn!("failed building {} matcher: {err}", path.display());
+        Gitignore::empty()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn matches_patterns_and_reloads_on_sha_change() {
+        let temp = tempdir().expect("temp dir");
+        std::fs::write(temp.path().join(IGNORE_FILE_NAME), "notes/\n*.tmpl\n")
+            .expect("write ignore file");
+        let ignore = ServeIgnore::default();
+
+        assert!(ignore.is_ignored(temp.path(), Some("a"), Path::new("notes/todo.md")));
+        assert!(ignore.is_ignored(temp.path(), Some("a"), Path::new("x/secret.tmpl")));
+        assert!(ignore.is_ignored(temp.path(), Some("a"), Path::new(IGNORE_FILE_NAME)));
+        assert!(!ignore.is_ignored(temp.path(), Some("a"), Path::new("data.json")));
+
+        std::fs::write(temp.path().join(IGNORE_FILE_NAME), "data.json\n")
+            .expect("rewrite ignore file");
+        assert!(ignore.is_ignored(temp.path(), Some("a"), Path::new("notes/todo.md")));
+        assert!(ignore.is_ignored(temp.path(), Some("b"), Path::new("data.json")));
+        assert!(!ignore.is_ignored(temp.path(), Some("b"), Path::new("notes/todo.md")));
+    }
+}
@@ -1,24 +1,157 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsString,
+    fs::Metadata,
+    io::{BufWriter, Write},
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path as FsPath, PathBuf},
+    process::Stdio,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
+use arc_swap::ArcSwap;
 use axum::{
-    Json, Router,
-    body::Body,
-    extract::{Path, State},
-    http::{HeaderMap, HeaderValue, StatusCode, header},
+    Extension, Json, Router,
+    body::{Body, Bytes},
+    extract::{MatchedPath, Query, Request, State},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri, header},
+    middleware::{self, Next},
     response::{IntoResponse, Response},
-    routing::get,
+    routing::{MethodRouter, get, post},
 };
 use chrono::Utc;
-use serde::Serialize;
-use sha2::{Digest, Sha256};
-use tokio::{fs, sync::RwLock};
+use git2::Oid;
+use ipnet::IpNet;
+use percent_encoding::{AsciiSet, CONTROLS, percent_decode_str, utf8_percent_encode};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream},
+    sync::{RwLock, mpsc},
+    task,
+};
+use tokio_util::io::ReaderStream;
+use tower_http::{limit::RequestBodyLimitLayer, timeout::TimeoutLayer};
+use tracing::{info, warn};
 
-use crate::{config::AppConfig, path_guard::resolve_under_root, sync::SyncStatus};
+use crate::{
+    client_ip::client_ip,
+    commit_time::{CommitTimeCache, last_commit_time},
+    config::{
+        AppConfig, ContentEncoding, DirRequestMode, EtagMode, LastModifiedSource, ServeMount,
+        SyncMode,
+    },
+    diff::{commits_behind, diff_commits},
+    digest_cache::{DigestCache, strong_etag},
+    history::SyncHistory,
+    log_buffer::{self, LogBuffer},
+    markdown::{escape_html, html_page, is_markdown, render_page},
+    metrics::{RequestMetrics, RouteClass, SyncMetrics},
+    object_db::{self, ObjectDbStats},
+    openapi,
+    path_guard::{CanonicalRoots, resolve_under_root},
+    range::{ByteRange, if_range_matches, parse_range},
+    rate_limit::RateLimiter,
+    redirects::Redirects,
+    schema_index::SchemaIndex,
+    serve_ignore::ServeIgnore,
+    snapshot::{Snapshot, SnapshotTree},
+    sniff::{SNIFF_LEN, sniff},
+    sync::{
+        SyncErrorKind, SyncStatus, SyncWarning, available_bytes, error_kind, is_object_id,
+        nearest_existing_dir, refresh_remote_tip, serve_gate,
+    },
+};
 
 #[derive(Clone)]
 pub struct AppState {
     pub config: AppConfig,
+    /// Config as of the last SIGHUP reload; only its hot-reloadable settings
+    /// (see `AppConfig::reloaded`) are read from here.
+    pub live_config: Arc<ArcSwap<AppConfig>>,
     pub status: Arc<RwLock<SyncStatus>>,
+    pub history: Arc<SyncHistory>,
+    pub commit_times: Arc<CommitTimeCache>,
+    pub digests: Arc<DigestCache>,
+    pub canonical_roots: Arc<CanonicalRoots>,
+    /// Number of file bodies read from disk by `/files`.
+    pub files_read: Arc<AtomicU64>,
+    /// While set, file routes and `/snapshot.tar.gz` answer 503; toggled by
+    /// `POST /admin/maintenance` and shared with every branch.
+    pub maintenance: Arc<AtomicBool>,
+    pub serve_ignore: Arc<ServeIgnore>,
+    pub redirects: Arc<Redirects>,
+    pub schema_index: Arc<SchemaIndex>,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    pub request_metrics: Arc<RequestMetrics>,
+    /// Held for reading while a file request touches the mirror; see `serve_gate`.
+    pub serve_gate: Arc<RwLock<()>>,
+    /// Last `HEALTH_CHECK_COMMAND` outcome and when it finished; locked while
+    /// the command runs, so concurrent probes share one run.
+    pub health_check: Arc<tokio::sync::Mutex<Option<(Instant, bool, String)>>>,
+    /// State for each of `GIT_BRANCHES`, sharing history and caches keyed by
+    /// absolute path with this one.
+    pub branches: Arc<BTreeMap<String, AppState>>,
+}
+
+impl AppState {
+    pub fn new(config: AppConfig, status: Arc<RwLock<SyncStatus>>) -> Self {
+        let rate_limiter = config
+            .rate_limit_rps
+            .map(|rps| Arc::new(RateLimiter::new(rps, config.rate_limit_burst)));
+        let mut state = Self {
+            history: Arc::new(SyncHistory::new(config.sync_history_size)),
+            live_config: Arc::new(ArcSwap::from_pointee(config.clone())),
+            status,
+            commit_times: Arc::new(CommitTimeCache::default()),
+            digests: Arc::new(DigestCache::default()),
+            canonical_roots: Arc::default(),
+            files_read: Arc::new(AtomicU64::new(0)),
+            maintenance: Arc::new(AtomicBool::new(config.maintenance_mode)),
+            serve_ignore: Arc::new(ServeIgnore::default()),
+            redirects: Arc::default(),
+            schema_index: Arc::default(),
+            rate_limiter,
+            request_metrics: Arc::default(),
+            serve_gate: serve_gate(&config.mirror_dir),
+            health_check: Arc::default(),
+            config,
+            branches: Arc::default(),
+        };
+        let branches = state
+            .config
+            .git_branches
+            .iter()
+            .map(|branch| {
+                let branch_config = state.config.for_branch(branch);
+                let branch_state = Self {
+                    status: Arc::new(RwLock::new(SyncStatus::restore(&branch_config.mirror_dir))),
+                    serve_gate: serve_gate(&branch_config.mirror_dir),
+                    config: branch_config,
+                    commit_times: Arc::new(CommitTimeCache::default()),
+                    serve_ignore: Arc::new(ServeIgnore::default()),
+                    redirects: Arc::default(),
+                    ..state.clone()
+                };
+                (branch.clone(), branch_state)
+            })
+            .collect();
+        state.branches = Arc::new(branches);
+        state
+    }
+
+    /// State serving `branch`, whether it is the primary branch or one of `GIT_BRANCHES`.
+    fn branch(&self, branch: &str) -> Option<&AppState> {
+        if branch == self.config.git_branch {
+            Some(self)
+        } else {
+            self.branches.get(branch)
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -27,42 +160,515 @@ struct HealthResponse {
     current_sha: Option<String>,
     last_success_at: Option<chrono::DateTime<chrono::Utc>>,
     last_error: Option<String>,
+    warnings: Vec<SyncWarning>,
+}
+
+/// `application/health+json` body (draft-inadarei-api-health-check).
+#[derive(Serialize)]
+struct HealthCheckResponse {
+    status: CheckStatus,
+    version: &'static str,
+    checks: BTreeMap<&'static str, Vec<HealthCheck>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HealthCheck {
+    status: CheckStatus,
+    observed_value: Option<u64>,
+    observed_unit: &'static str,
+    time: chrono::DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+}
+
+/// Ordered so the overall status is the worst of the checks.
+#[derive(Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
 }
 
 #[derive(Serialize)]
 struct MetaResponse {
-    synced_repo_url: String,
-    branch: String,
+    /// `None` for both when `SYNC_MODE=none`.
+    synced_repo_url: Option<String>,
+    branch: Option<String>,
     serve_root: String,
     sync_interval_seconds: u64,
     now: chrono::DateTime<Utc>,
     sync: SyncStatus,
+    /// Current SHA of the primary branch and each of `GIT_BRANCHES`.
+    branches: BTreeMap<String, Option<String>>,
+}
+
+#[derive(Serialize)]
+struct DriftResponse {
+    synced_sha: Option<String>,
+    remote_tip_sha: Option<String>,
+    /// `None` unless both commits are in the mirror.
+    behind_commits: Option<usize>,
+    behind_seconds: Option<i64>,
+}
+
+/// Size of the chunks `/snapshot.tar.gz` is sent in; it runs at most one
+/// chunk ahead of a slow client before blocking.
+const SNAPSHOT_BUFFER_BYTES: usize = 64 * 1024;
+
+/// A client holding a whole commit's files sends its SHA here to get 304 for
+/// any file while that commit is still the one served.
+const X_IF_REPO_SHA: &str = "x-if-repo-sha";
+
+/// `Cache-Control` for `/robots.txt` and `/favicon.ico`.
+const STATIC_ASSET_CACHE_CONTROL: &str = "public, max-age=86400";
+
+/// How far `/list-stream` runs ahead of a slow client before blocking.
+const LIST_STREAM_BUFFER_BYTES: usize = 64 * 1024;
+
+/// `Retry-After` sent with maintenance-mode 503s.
+const MAINTENANCE_RETRY_AFTER_SECONDS: u64 = 30;
+
+/// Files served for a directory request in `DirRequestMode::Index`, in order.
+const INDEX_FILES: &[&str] = &["index.html", "index.json"];
+
+/// `/diff` lists at most this many paths and sets `truncated` past it.
+const MAX_DIFF_ENTRIES: usize = 1000;
+
+#[derive(Serialize)]
+struct DirEntry {
+    name: String,
+    kind: EntryKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+}
+
+/// A `/list-stream` line.
+#[derive(Serialize)]
+struct StreamedEntry {
+    #[serde(flatten)]
+    entry: DirEntry,
+    /// A directory at the depth limit, listed but not entered.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+}
+
+#[derive(Serialize)]
+struct RecursiveListing {
+    entries: Vec<DirEntry>,
+    /// Directories at the depth limit were listed but not entered.
+    truncated: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum EntryKind {
+    File,
+    Dir,
+}
+
+#[derive(Serialize)]
+struct ReadyResponse {
+    ready: bool,
+    current_sha: Option<String>,
+    consecutive_failures: u32,
+    /// Stdout of `HEALTH_CHECK_COMMAND`, when it ran.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    check_output: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ExistsResponse {
+    exists: bool,
+    is_dir: bool,
+    size: Option<u64>,
+    sha: Option<String>,
+}
+
+#[derive(Serialize)]
+struct VersionResponse {
+    version: &'static str,
+    git_commit: &'static str,
+    built_at: Option<chrono::DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct MetaQuery {
+    refresh: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DiffQuery {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FileQuery {
+    download: Option<String>,
+    raw: Option<String>,
+    recursive: Option<String>,
+}
+
+impl FileQuery {
+    fn download(&self) -> bool {
+        matches!(self.download.as_deref(), Some("1" | "true"))
+    }
+
+    fn raw(&self) -> bool {
+        matches!(self.raw.as_deref(), Some("1" | "true"))
+    }
+
+    fn recursive(&self) -> bool {
+        matches!(self.recursive.as_deref(), Some("1" | "true"))
+    }
+}
+
+#[derive(Deserialize)]
+struct MaintenanceRequest {
+    enabled: bool,
+}
+
+#[derive(Serialize)]
+struct MaintenanceResponse {
+    maintenance: bool,
 }
 
 #[derive(Serialize)]
+struct GcResponse {
+    object_db: ObjectDbStats,
+    last_gc_at: chrono::DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct PurgeCacheResponse {
+    purged: usize,
+}
+
+#[derive(Clone, Serialize)]
 struct ErrorResponse {
+    code: &'static str,
     error: String,
 }
 
+#[derive(Serialize)]
+struct IndexResponse {
+    name: &'static str,
+    current_sha: Option<String>,
+    last_success_at: Option<chrono::DateTime<Utc>>,
+    endpoints: Vec<String>,
+}
+
+/// Which group of routes a router should serve.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Listener {
+    Combined,
+    Public,
+    Admin,
+}
+
+/// Router builder that records each mounted path so the index can list
+/// exactly what this listener serves.
+/// Routes named in `DISABLED_ROUTES` are skipped and fall through to 404.
+struct Routes {
+    router: Router<AppState>,
+    endpoints: Vec<String>,
+    disabled: Vec<String>,
+}
+
+impl Routes {
+    fn new(disabled: &[String]) -> Self {
+        Self {
+            router: Router::new(),
+            endpoints: Vec::new(),
+            disabled: disabled.to_vec(),
+        }
+    }
+
+    fn is_disabled(&self, path: &str) -> bool {
+        let name = path.strip_suffix("/*path").unwrap_or(path);
+        self.disabled.iter().any(|d| d == name)
+    }
+
+    fn route(self, path: &str, method_router: MethodRouter<AppState>) -> Self {
+        self.route_allowing(path, method_router, READ_METHODS)
+    }
+
+    /// `route` for a path accepting more than `READ_METHODS`, listed in `allow`
+    /// for `OPTIONS`.
+    fn route_allowing(
+        mut self,
+        path: &str,
+        method_router: MethodRouter<AppState>,
+        allow: &'static str,
+    ) -> Self {
+        if self.is_disabled(path) {
+            return self;
+        }
+        self.router = self.router.route(
+            path,
+            method_router.options(move || async move { allowed(allow) }),
+        );
+        self.endpoints.push(path.to_string());
+        self
+    }
+
+    fn finish(self, state: AppState) -> Router {
+        let endpoints: Arc<[String]> = self.endpoints.into();
+        let mut router = self.router.layer(Extension(endpoints));
+        // Inside `record_latency` so timed-out requests are still observed.
+        // The timeout covers producing the response, not streaming its body.
+        if let Some(seconds) = state.config.request_timeout_seconds {
+            router = router.layer(TimeoutLayer::with_status_code(
+                StatusCode::GATEWAY_TIMEOUT,
+                Duration::from_secs(seconds),
+            ));
+        }
+        router = router.layer(middleware::from_fn_with_state(
+            state.clone(),
+            record_latency,
+        ));
+        if state.rate_limiter.is_some() {
+            router = router.layer(middleware::from_fn_with_state(state.clone(), rate_limit));
+        }
+        // Outside the rate limiter so rejected clients don't use up a budget.
+        if !state.config.allowed_cidrs.is_empty() {
+            router = router.layer(middleware::from_fn_with_state(
+                state.clone(),
+                restrict_networks,
+            ));
+        }
+        router
+            .fallback(not_found)
+            .layer(RequestBodyLimitLayer::new(
+                state.config.max_request_body_bytes,
+            ))
+            .layer(middleware::map_response(middleware_errors))
+            .layer(middleware::from_fn(negotiate_errors))
+            .with_state(state)
+    }
+}
+
+/// All routes on a single listener, used when no separate admin address is configured.
 pub fn router(state: AppState) -> Router {
-    Router::new()
-        .route("/", get(index))
-        .route("/health", get(health))
-        .route("/meta", get(meta))
-        .route("/files/*path", get(get_file))
-        .with_state(state)
+    build_router(state, Listener::Combined)
+}
+
+/// Routes safe to expose publicly: index, health and file serving.
+pub fn public_router(state: AppState) -> Router {
+    build_router(state, Listener::Public)
+}
+
+/// Operational routes that can be bound to a separate `ADMIN_BIND_ADDR`.
+pub fn admin_router(state: AppState) -> Router {
+    build_router(state, Listener::Admin)
+}
+
+fn build_router(state: AppState, listener: Listener) -> Router {
+    let mut routes = Routes::new(&state.config.disabled_routes);
+    if listener != Listener::Admin {
+        routes = routes
+            .route("/health", get(health))
+            .route("/readyz", get(readyz))
+            .route("/.well-known/health", get(well_known_health))
+            .route("/version", get(version))
+            .route("/robots.txt", get(robots_txt))
+            .route("/favicon.ico", get(favicon))
+            .route("/exists/*path", get(exists));
+        routes = routes.route("/snapshot.tar.gz", get(snapshot));
+        if state.config.schema_index {
+            routes = routes.route("/schemas/index.json", get(schema_index));
+        }
+        if state.config.dir_request_mode == DirRequestMode::List {
+            routes = routes.route("/list-stream/*path", get(list_stream));
+        }
+        for mount in &state.config.serve_mounts {
+            routes = routes.route(
+                &format!("{}/*path", mount.prefix),
+                get(get_file).layer(Extension(mount.clone())),
+            );
+            if !state.config.git_branches.is_empty() {
+                routes = routes.route(
+                    &format!("/b/:branch{}/*path", mount.prefix),
+                    get(get_branch_file).layer(Extension(mount.clone())),
+                );
+            }
+        }
+    }
+    if listener != Listener::Public {
+        routes = routes
+            .route("/meta", get(meta))
+            .route("/meta/history", get(meta_history))
+            .route("/meta/drift", get(meta_drift))
+            .route("/metrics", get(metrics))
+            .route("/stats", get(stats))
+            .route("/diff", get(diff));
+        // Without a token, only a listener of their own keeps these private.
+        if state.config.admin_token.is_some() || listener == Listener::Admin {
+            let guard = middleware::from_fn_with_state(state.clone(), require_admin_token);
+            routes = routes
+                .route("/meta/logs", get(meta_logs).layer(guard.clone()))
+                .route_allowing(
+                    "/admin/maintenance",
+                    get(maintenance).post(set_maintenance).layer(guard.clone()),
+                    "GET, HEAD, POST, OPTIONS",
+                )
+                .route_allowing("/admin/gc", post(gc).layer(guard.clone()), "POST, OPTIONS")
+                .route_allowing(
+                    "/admin/purge-cache",
+                    post(purge_cache).layer(guard),
+                    "POST, OPTIONS",
+                );
+        }
+    }
+    routes = routes.route("/openapi.json", get(openapi));
+    if listener != Listener::Admin && !routes.is_disabled("/") {
+        routes.router = routes
+            .router
+            .route("/", get(index).options(allowed_methods));
+    }
+    routes.finish(state)
+}
+
+/// Records request latency by route class. Handlers that answer a file route
+/// with something other than a file (directory listings) mark the response
+/// with their own `RouteClass`.
+async fn record_latency(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let class = match request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str)
+    {
+        Some("/health" | "/readyz" | "/.well-known/health") => RouteClass::Health,
+        Some(path) if path == "/" || path == "/version" || path.starts_with("/meta") => {
+            RouteClass::Meta
+        }
+        Some(
+            "/metrics" | "/stats" | "/diff" | "/admin/maintenance" | "/admin/gc"
+            | "/admin/purge-cache" | "/openapi.json",
+        ) => RouteClass::Meta,
+        _ => RouteClass::Files,
+    };
+    let response = next.run(request).await;
+    let class = response
+        .extensions()
+        .get::<RouteClass>()
+        .copied()
+        .unwrap_or(class);
+    state.request_metrics.observe(class, started.elapsed());
+    response
+}
+
+/// Answers 401 unless the request carries `Authorization: Bearer` with
+/// `ADMIN_TOKEN`; a no-op when no token is configured.
+async fn require_admin_token(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(token) = state.config.admin_token.as_deref() else {
+        return next.run(request).await;
+    };
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    // `blake3::Hash` compares in constant time.
+    if presented.is_some_and(|p| blake3::hash(p.as_bytes()) == blake3::hash(token.as_bytes())) {
+        return next.run(request).await;
+    }
+    let mut response = error_response(
+        StatusCode::UNAUTHORIZED,
+        "unauthorized",
+        "missing or wrong admin token",
+    );
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+/// Answers 403 to clients outside `ALLOWED_CIDRS`, or for the health
+/// endpoints outside both it and `HEALTH_ALLOWED_CIDRS`. A client without a
+/// known address (a Unix socket peer without a trusted `X-Forwarded-For`) is
+/// rejected.
+async fn restrict_networks(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let is_health = matches!(
+        request.uri().path(),
+        "/health" | "/readyz" | "/.well-known/health"
+    );
+    let allowed = client_ip(&request, state.config.forwarded_hops())
+        .map(|ip| ip.to_canonical())
+        .is_some_and(|ip| {
+            let within = |nets: &[IpNet]| nets.iter().any(|net| net.contains(&ip));
+            within(&state.config.allowed_cidrs)
+                || (is_health && within(&state.config.health_allowed_cidrs))
+        });
+    if !allowed {
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            "client address not allowed",
+        );
+    }
+    next.run(request).await
+}
+
+/// Rejects clients over their request budget with 429; health endpoints are
+/// exempt so probes are never throttled. Clients without a known address
+/// (Unix-socket peers with no trusted `X-Forwarded-For`) share one budget.
+async fn rate_limit(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if let Some(limiter) = &state.rate_limiter
+        && !matches!(
+            request.uri().path(),
+            "/health" | "/readyz" | "/.well-known/health"
+        )
+        && let Err(retry_after) = limiter.check(
+            client_ip(&request, state.config.forwarded_hops()),
+            Instant::now(),
+        )
+    {
+        let mut response = error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            "rate limit exceeded",
+        );
+        response.headers_mut().insert(
+            header::RETRY_AFTER,
+            HeaderValue::from(retry_after.as_secs_f64().ceil().max(1.0) as u64),
+        );
+        return response;
+    }
+    next.run(request).await
 }
 
-async fn index() -> impl IntoResponse {
-    Json(serde_json::json!({
-        "name": "repo-sync",
-        "endpoints": ["/health", "/meta", "/files/*path"]
-    }))
+async fn index(
+    State(state): State<AppState>,
+    Extension(endpoints): Extension<Arc<[String]>>,
+) -> impl IntoResponse {
+    let status = state.status.read().await;
+    Json(IndexResponse {
+        name: "repo-sync",
+        current_sha: status.current_sha.clone(),
+        last_success_at: status.last_success_at,
+        endpoints: endpoints.to_vec(),
+    })
 }
 
 async fn health(State(state): State<AppState>) -> impl IntoResponse {
     let status = state.status.read().await.clone();
-    let service_status = if status.last_error.is_some() && status.last_success_at.is_none() {
+    let service_status = if state.maintenance.load(Ordering::Relaxed) {
+        "maintenance"
+    } else if (status.last_error.is_some() && status.last_success_at.is_none())
+        || !status.warnings.is_empty()
+    {
         "degraded"
     } else {
         "ok"
@@ -72,259 +678,4933 @@ async fn health(State(state): State<AppState>) -> impl IntoResponse {
         current_sha: status.current_sha,
         last_success_at: status.last_success_at,
         last_error: status.last_error,
+        warnings: status.warnings,
     })
 }
 
-async fn meta(State(state): State<AppState>) -> impl IntoResponse {
+/// `/health` in the standard `application/health+json` shape. `git:sync`
+/// fails whenever `/readyz` would, and warns while syncs are failing or the
+/// serve root is missing; `disk:space` fails below `MIN_FREE_DISK_BYTES`.
+async fn well_known_health(State(state): State<AppState>) -> Response {
+    let now = Utc::now();
     let status = state.status.read().await.clone();
-    Json(MetaResponse {
-        synced_repo_url: state.config.git_repo_url.clone(),
-        branch: state.config.git_branch.clone(),
-        serve_root: state.config.serve_root().to_string_lossy().to_string(),
-        sync_interval_seconds: state.config.git_sync_interval_seconds,
-        now: Utc::now(),
-        sync: status,
-    })
+    let sync_status = if !is_ready(&state.config, &status) {
+        CheckStatus::Fail
+    } else if status.consecutive_failures > 0 || !status.warnings.is_empty() {
+        CheckStatus::Warn
+    } else {
+        CheckStatus::Pass
+    };
+    let sync_check = HealthCheck {
+        status: sync_status,
+        observed_value: status
+            .last_success_at
+            .map(|at| (now - at).num_seconds().max(0) as u64),
+        observed_unit: "s",
+        time: status.last_attempt_at.unwrap_or(now),
+        output: status.last_error.clone(),
+    };
+
+    let mirror_dir = state.config.mirror_dir.clone();
+    let free = task::spawn_blocking(move || available_bytes(nearest_existing_dir(&mirror_dir)))
+        .await
+        .map_err(std::io::Error::other)
+        .and_then(|result| result);
+    let min_free = state.config.min_free_disk_bytes;
+    let disk_check = match free {
+        Ok(free) => HealthCheck {
+            status: if free < min_free {
+                CheckStatus::Fail
+            } else {
+                CheckStatus::Pass
+            },
+            observed_value: Some(free),
+            observed_unit: "bytes",
+            time: now,
+            output: (free < min_free).then(|| format!("need {min_free} bytes free")),
+        },
+        Err(err) => HealthCheck {
+            status: CheckStatus::Warn,
+            observed_value: None,
+            observed_unit: "bytes",
+            time: now,
+            output: Some(format!("failed reading free space: {err}")),
+        },
+    };
+
+    let overall = sync_check.status.max(disk_check.status);
+    let code = if overall == CheckStatus::Fail {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    let body = HealthCheckResponse {
+        status: overall,
+        version: env!("CARGO_PKG_VERSION"),
+        checks: BTreeMap::from([
+            ("git:sync", vec![sync_check]),
+            ("disk:space", vec![disk_check]),
+        ]),
+    };
+    (
+        code,
+        [(header::CONTENT_TYPE, "application/health+json")],
+        serde_json::to_vec(&body).unwrap_or_default(),
+    )
+        .into_response()
 }
 
-async fn get_file(
-    State(state): State<AppState>,
-    Path(path): Path<String>,
-    headers: HeaderMap,
-) -> Response {
-    if path.len() > state.config.max_path_length {
-        return (
-            StatusCode::URI_TOO_LONG,
-            Json(ErrorResponse {
-                error: "path too long".to_string(),
-            }),
+/// Keyed on `last_success_at` rather than `current_sha`, which is restored
+/// from disk at startup and, with `SYNC_MODE=none`, never set.
+fn is_ready(config: &AppConfig, status: &SyncStatus) -> bool {
+    status.last_success_at.is_some() && status.consecutive_failures < config.unready_after_failures
+}
+
+/// Ready once a sync has succeeded, and until `UNREADY_AFTER_FAILURES`
+/// consecutive failures; fewer failures keep serving the last good mirror.
+/// `HEALTH_CHECK_COMMAND` then has the last word, and only runs once syncing
+/// is ready; its result is reused for `HEALTH_CHECK_CACHE`, so probes can't
+/// fork it faster than that.
+async fn readyz(State(state): State<AppState>) -> Response {
+    let (mut ready, current_sha, consecutive_failures) = {
+        let status = state.status.read().await;
+        (
+            is_ready(&state.config, &status),
+            status.current_sha.clone(),
+            status.consecutive_failures,
         )
-            .into_response();
+    };
+    let (command, timeout) = {
+        let live_config = state.live_config.load();
+        (
+            live_config.health_check_command.clone(),
+            Duration::from_millis(live_config.health_check_timeout_ms),
+        )
+    };
+    let mut check_output = None;
+    if ready && let Some(command) = command {
+        let mut last = state.health_check.lock().await;
+        let (passed, output) = match &*last {
+            Some((at, passed, output)) if at.elapsed() < HEALTH_CHECK_CACHE => {
+                (*passed, output.clone())
+            }
+            _ => {
+                let (passed, output) =
+                    run_health_check(&command, &state.config.serve_root(), timeout).await;
+                *last = Some((Instant::now(), passed, output.clone()));
+                (passed, output)
+            }
+        };
+        ready = passed;
+        check_output = Some(output);
     }
+    let code = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        code,
+        Json(ReadyResponse {
+            ready,
+            current_sha,
+            consecutive_failures,
+            check_output,
+        }),
+    )
+        .into_response()
+}
 
-    let serve_root = state.config.serve_root();
-    let file_path = match resolve_under_root(&serve_root, &path) {
-        Ok(p) => p,
+/// Most of a health check's stdout kept for `/readyz`.
+const MAX_HEALTH_CHECK_OUTPUT: usize = 4096;
+
+/// How long `/readyz` reuses a health check's result.
+const HEALTH_CHECK_CACHE: Duration = Duration::from_secs(1);
+
+/// Runs `command` (a program followed by whitespace-separated arguments,
+/// without a shell) in `dir`, killing it after `timeout`. Returns whether it
+/// exited zero in time, and its trimmed stdout or why it didn't run. Stdout
+/// past `MAX_HEALTH_CHECK_OUTPUT` is never read: the pipe is closed instead.
+async fn run_health_check(command: &str, dir: &FsPath, timeout: Duration) -> (bool, String) {
+    let mut parts = command.split_whitespace();
+    let Some(program) = parts.next() else {
+        return (false, "HEALTH_CHECK_COMMAND is empty".to_string());
+    };
+    let spawned = tokio::process::Command::new(program)
+        .args(parts)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn();
+    let run = async {
+        let mut child = spawned?;
+        let mut output = Vec::new();
+        if let Some(stdout) = child.stdout.take() {
+            stdout
+                .take(MAX_HEALTH_CHECK_OUTPUT as u64 + 1)
+                .read_to_end(&mut output)
+                .await?;
+        }
+        Ok::<_, std::io::Error>((child.wait().await?, output))
+    };
+    match tokio::time::timeout(timeout, run).await {
+        Ok(Ok((status, output))) => {
+            let mut stdout = String::from_utf8_lossy(&output).trim().to_string();
+            if stdout.len() > MAX_HEALTH_CHECK_OUTPUT {
+                let end = stdout.floor_char_boundary(MAX_HEALTH_CHECK_OUTPUT);
+                stdout.truncate(end);
+            }
+            if !status.success() {
+                info!("health check {program} failed ({status})");
+            }
+            (status.success(), stdout)
+        }
+        Ok(Err(err)) => {
+            warn!("failed running health check {program}: {err}");
+            (false, format!("failed running {program}: {err}"))
+        }
         Err(_) => {
-            return (
-                StatusCode::FORBIDDEN,
-                Json(ErrorResponse {
-                    error: "invalid path".to_string(),
-                }),
+            warn!("health check {program} timed out");
+            (
+                false,
+                format!("{program} timed out after {}ms", timeout.as_millis()),
             )
-                .into_response();
         }
+    }
+}
+
+/// Whether the file route `/*path` names something, from metadata alone.
+/// Paths hidden by `.repo-sync-ignore` and special files report as missing;
+/// paths outside every mount are not found.
+async fn exists(State(state): State<AppState>, uri: Uri) -> Response {
+    let Some((mount, path)) =
+        wildcard_path(uri.path(), "/exists").and_then(|path| mounted_path(&state, &path))
+    else {
+        return error_response(StatusCode::NOT_FOUND, "not_found", "path not found");
+    };
+    if path.as_os_str().len() > state.live_config.load().max_path_length {
+        return error_response(StatusCode::URI_TOO_LONG, "path_too_long", "path too long");
+    }
+    let file_path = match resolve_request_paths(&state, state.config.mount_roots(mount), path).await
+    {
+        Ok(layers) => first_existing(layers).await,
+        Err(err) => return invalid_path(&state, &err, "path not found"),
     };
 
-    serve_file(file_path, headers, state.config.max_file_size_bytes).await
+    let metadata = if is_ignored(&state, &file_path).await {
+        None
+    } else {
+        fs::metadata(&file_path)
+            .await
+            .ok()
+            .filter(|m| m.is_file() || m.is_dir())
+    };
+    Json(ExistsResponse {
+        exists: metadata.is_some(),
+        is_dir: metadata.as_ref().is_some_and(|m| m.is_dir()),
+        size: metadata.filter(|m| m.is_file()).map(|m| m.len()),
+        sha: state.status.read().await.current_sha.clone(),
+    })
+    .into_response()
 }
 
-async fn serve_file(file_path: PathBuf, headers: HeaderMap, max_size: u64) -> Response {
-    let metadata = match fs::metadata(&file_path).await {
-        Ok(v) => v,
-        Err(_) => {
-            return (
-                StatusCode::NOT_FOUND,
-                Json(ErrorResponse {
-                    error: "file not found".to_string(),
-                }),
-            )
-                .into_response();
-        }
-    };
+/// Schema ids found under the serve root, mapped to their file paths.
+async fn schema_index(State(state): State<AppState>) -> Response {
+    let sha = state.status.read().await.current_sha.clone();
+    let serve_root = state.config.serve_root();
+    let result = task::spawn_blocking(move || {
+        state
+            .schema_index
+            .get(&serve_root, sha.as_deref(), &state.serve_ignore)
+    })
+    .await;
+    match result {
+        Ok(index) => Json(&*index).into_response(),
+        Err(_) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "scan_failed",
+            "schema scan failed",
+        ),
+    }
+}
 
-    if !metadata.is_file() {
-        return (
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: "not a file".to_string(),
-            }),
-        )
-            .into_response();
+/// Gzipped tar of the mounted paths at the current SHA, streamed while it is
+/// built. The SHA is the ETag, so a client holding this commit's snapshot
+/// gets 304 instead of downloading it again.
+async fn snapshot(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if state.maintenance.load(Ordering::Relaxed) {
+        return maintenance_response();
     }
-    if metadata.len() > max_size {
-        return (
-            StatusCode::PAYLOAD_TOO_LARGE,
-            Json(ErrorResponse {
-                error: "file exceeds max size".to_string(),
-            }),
-        )
-            .into_response();
+    let (sha, commit_time) = {
+        let status = state.status.read().await;
+        (status.current_sha.clone(), status.commit_time)
+    };
+    let Some(oid) = sha.as_deref().and_then(parse_oid) else {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            "no synced commit to snapshot",
+        );
+    };
+    let sha = oid.to_string();
+    let etag = format!("\"{sha}\"");
+    if if_none_match(&headers, &etag) {
+        return not_modified(None);
     }
 
-    let bytes = match fs::read(&file_path).await {
-        Ok(v) => v,
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: "failed to read file".to_string(),
-                }),
-            )
-                .into_response();
+    let trees: Vec<SnapshotTree> = state
+        .config
+        .serve_mounts
+        .iter()
+        .map(|mount| SnapshotTree {
+            archive_dir: PathBuf::from(mount.prefix.trim_start_matches('/')),
+            layers: state
+                .config
+                .mount_roots(mount)
+                .iter()
+                .filter_map(|root| root.strip_prefix(&state.config.mirror_dir).ok())
+                .map(FsPath::to_path_buf)
+                .collect(),
+        })
+        .collect();
+    let repo_dir = state.config.mirror_dir.clone();
+    let opened = task::spawn_blocking(move || Snapshot::open(&repo_dir, oid, &trees)).await;
+    let snapshot = match opened {
+        Ok(Ok(snapshot)) => snapshot,
+        Ok(Err(err)) => {
+            warn!("snapshot of {sha} failed: {err:#}");
+            return snapshot_failed_response();
         }
+        Err(_) => return snapshot_failed_response(),
     };
 
-    let mut hasher = Sha256::new();
-    hasher.update(&bytes);
-    let digest = hex::encode(hasher.finalize());
-    let etag = format!("\"{digest}\"");
-
-    if let Some(client_etag) = headers
-        .get(header::IF_NONE_MATCH)
-        .and_then(|v| v.to_str().ok())
-        && client_etag == etag
-    {
-        return StatusCode::NOT_MODIFIED.into_response();
-    }
+    let (tx, mut rx) = mpsc::channel(1);
+    let live_config = state.live_config.load_full();
+    let mtime = commit_time.map_or(0, |time| time.timestamp().max(0) as u64);
+    let filename = format!("snapshot-{sha}.tar.gz");
+    task::spawn_blocking(move || {
+        let serve_root = state.config.serve_root();
+        let subdir = state.config.serve_subdir.clone().unwrap_or_default();
+        let include = |path: &FsPath, size: u64| {
+            let relative = path.strip_prefix(&subdir).unwrap_or(path);
+            size <= live_config.max_file_size_for(relative)
+                && !state
+                    .serve_ignore
+                    .is_ignored(&serve_root, Some(&sha), relative)
+        };
+        let out = BufWriter::with_capacity(SNAPSHOT_BUFFER_BYTES, ChunkSender(tx.clone()));
+        if let Err(err) = snapshot.write(mtime, include, out) {
+            warn!("snapshot of {sha} failed: {err:#}");
+            // Ending the body with an error makes hyper abort the connection,
+            // so the client sees a failed download rather than a short one.
+            let _ = tx.blocking_send(Err(std::io::Error::other(format!("{err:#}"))));
+        }
+    });
 
-    let content_type = mime_guess::from_path(&file_path).first_or_octet_stream();
-    let mut response = Response::new(Body::from(bytes));
-    *response.status_mut() = StatusCode::OK;
-    response.headers_mut().insert(
+    let chunks = futures_util::stream::poll_fn(move |cx| rx.poll_recv(cx));
+    let mut response = Response::new(Body::from_stream(chunks));
+    let response_headers = response.headers_mut();
+    response_headers.insert(
         header::CONTENT_TYPE,
-        HeaderValue::from_str(content_type.as_ref())
-            .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream")),
-    );
-    response.headers_mut().insert(
-        header::ETAG,
-        HeaderValue::from_str(&etag).unwrap_or_else(|_| HeaderValue::from_static("\"invalid\"")),
+        HeaderValue::from_static("application/gzip"),
     );
-    if let Ok(modified) = metadata.modified() {
-        let last_modified = httpdate::fmt_http_date(modified);
-        if let Ok(v) = HeaderValue::from_str(&last_modified) {
-            response.headers_mut().insert(header::LAST_MODIFIED, v);
-        }
+    if let Ok(v) = HeaderValue::from_str(&etag) {
+        response_headers.insert(header::ETAG, v);
+    }
+    if let Ok(v) = HeaderValue::from_str(&format!("attachment; filename=\"{filename}\"")) {
+        response_headers.insert(header::CONTENT_DISPOSITION, v);
     }
-
     response
 }
 
-#[cfg(test)]
-mod tests {
-    use std::{sync::Arc, time::Duration};
+fn snapshot_failed_response() -> Response {
+    error_response(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "snapshot_failed",
+        "failed reading the synced commit",
+    )
+}
 
-    use axum::{
-        body::to_bytes,
-        http::{Request, StatusCode, header},
-    };
-    use tempfile::tempdir;
-    use tokio::sync::RwLock;
-    use tower::ServiceExt;
+/// Feeds `/snapshot.tar.gz`'s body from the blocking task building it.
+struct ChunkSender(mpsc::Sender<std::io::Result<Bytes>>);
 
-    use crate::{config::AppConfig, sync::SyncStatus};
+impl Write for ChunkSender {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
 
-    use super::{AppState, router};
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
 
-    #[tokio::test]
-    async fn file_serving_reflects_file_update_without_restart() {
-        let temp = tempdir().expect("temp dir");
-        let mirror = temp.path().join("repo");
-        std::fs::create_dir_all(&mirror).expect("create repo dir");
-        std::fs::write(mirror.join("a.txt"), "one").expect("write file");
+/// Build of this binary, as opposed to `/meta` which describes the synced repo.
+async fn version() -> impl IntoResponse {
+    Json(VersionResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("REPO_SYNC_GIT_COMMIT"),
+        built_at: env!("REPO_SYNC_BUILD_TIMESTAMP")
+            .parse::<i64>()
+            .ok()
+            .and_then(|seconds| chrono::DateTime::from_timestamp(seconds, 0)),
+    })
+}
 
-        let state = AppState {
-            config: AppConfig {
-                git_repo_url: "https://github.com/org/repo.git".to_string(),
-                git_branch: "main".to_string(),
-                git_sync_interval_seconds: 30,
-                git_token: None,
-                mirror_dir: mirror,
-                serve_subdir: None,
-                http_bind_addr: "127.0.0.1:0".to_string(),
-                max_path_length: 512,
-                max_file_size_bytes: 1024 * 1024,
-            },
-            status: Arc::new(RwLock::new(SyncStatus::default())),
-        };
-        let app = router(state);
+/// `ROBOTS_TXT`, so crawlers get an answer instead of a logged 404.
+async fn robots_txt(State(state): State<AppState>) -> Response {
+    let mut response = state.live_config.load().robots_txt.clone().into_response();
+    response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(STATIC_ASSET_CACHE_CONTROL),
+    );
+    response
+}
+
+/// No icon, but an empty answer browsers may cache instead of retrying.
+async fn favicon() -> Response {
+    let mut response = StatusCode::NO_CONTENT.into_response();
+    response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(STATIC_ASSET_CACHE_CONTROL),
+    );
+    response
+}
+
+async fn meta(State(state): State<AppState>, Query(query): Query<MetaQuery>) -> impl IntoResponse {
+    let git = state.config.sync_mode == SyncMode::Git;
+    if git && matches!(query.refresh.as_deref(), Some("1" | "true")) {
+        refresh_remote_tip(&state.live_config.load(), state.status.clone()).await;
+    }
+    let status = state.status.read().await.clone();
+    let mut branches = BTreeMap::new();
+    if git {
+        branches.insert(state.config.git_branch.clone(), status.current_sha.clone());
+    }
+    for (branch, branch_state) in state.branches.iter() {
+        let sha = branch_state.status.read().await.current_sha.clone();
+        branches.insert(branch.clone(), sha);
+    }
+    Json(MetaResponse {
+        synced_repo_url: git.then(|| state.config.git_repo_url.clone()),
+        branch: git.then(|| state.config.git_branch.clone()),
+        serve_root: state.config.serve_root().to_string_lossy().to_string(),
+        sync_interval_seconds: state.config.git_sync_interval_seconds,
+        now: Utc::now(),
+        sync: status,
+        branches,
+    })
+}
+
+/// Methods every read-only route answers.
+const READ_METHODS: &str = "GET, HEAD, OPTIONS";
+
+/// Answers `OPTIONS` on every read-only route.
+async fn allowed_methods() -> Response {
+    allowed(READ_METHODS)
+}
+
+fn allowed(methods: &'static str) -> Response {
+    (StatusCode::NO_CONTENT, [(header::ALLOW, methods)]).into_response()
+}
+
+async fn maintenance(State(state): State<AppState>) -> Json<MaintenanceResponse> {
+    Json(MaintenanceResponse {
+        maintenance: state.maintenance.load(Ordering::Relaxed),
+    })
+}
+
+/// Turns maintenance mode on or off.
+async fn set_maintenance(
+    State(state): State<AppState>,
+    Json(request): Json<MaintenanceRequest>,
+) -> Json<MaintenanceResponse> {
+    let was = state.maintenance.swap(request.enabled, Ordering::Relaxed);
+    if was != request.enabled {
+        info!(
+            "maintenance mode {}",
+            if request.enabled { "on" } else { "off" }
+        );
+    }
+    Json(MaintenanceResponse {
+        maintenance: request.enabled,
+    })
+}
+
+/// Forgets every cached strong `ETag`, for when files changed behind the
+/// cache's back in a way their length and mtime don't show.
+async fn purge_cache(State(state): State<AppState>) -> Json<PurgeCacheResponse> {
+    let purged = state.digests.clear();
+    info!("purged {purged} cached digests");
+    Json(PurgeCacheResponse { purged })
+}
+
+/// Repacks the primary branch's mirror (see `object_db::gc`) and records the
+/// result in the sync status.
+async fn gc(State(state): State<AppState>) -> Response {
+    if state.config.sync_mode == SyncMode::None {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            "no git mirror with SYNC_MODE=none",
+        );
+    }
+    let mirror_dir = state.config.mirror_dir.clone();
+    let started = Instant::now();
+    let result = task::spawn_blocking(move || object_db::gc(&mirror_dir))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result);
+    match result {
+        Ok(object_db) => {
+            let last_gc_at = Utc::now();
+            {
+                let mut status = state.status.write().await;
+                status.object_db = Some(object_db);
+                status.last_gc_at = Some(last_gc_at);
+            }
+            info!(
+                "gc finished in {}ms: {} bytes in {} packs",
+                started.elapsed().as_millis(),
+                object_db.size_bytes,
+                object_db.pack_count
+            );
+            Json(GcResponse {
+                object_db,
+                last_gc_at,
+            })
+            .into_response()
+        }
+        Err(err) if error_kind(&err) == SyncErrorKind::MirrorLocked => {
+            error_response(StatusCode::CONFLICT, "mirror_locked", &err.to_string())
+        }
+        Err(err) => {
+            warn!("gc failed: {err:#}");
+            error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "gc_failed",
+                &format!("{err:#}"),
+            )
+        }
+    }
+}
+
+/// 503 for serving routes while maintenance mode is on.
+fn maintenance_response() -> Response {
+    let mut response = error_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "maintenance",
+        "down for maintenance",
+    );
+    response.headers_mut().insert(
+        header::RETRY_AFTER,
+        HeaderValue::from(MAINTENANCE_RETRY_AFTER_SECONDS),
+    );
+    response
+}
+
+async fn openapi(
+    State(state): State<AppState>,
+    Extension(endpoints): Extension<Arc<[String]>>,
+) -> impl IntoResponse {
+    let mount_prefixes: Vec<String> = state
+        .config
+        .serve_mounts
+        .iter()
+        .map(|mount| mount.prefix.clone())
+        .collect();
+    Json(openapi::document(&endpoints, &mount_prefixes))
+}
+
+async fn sync_metrics(state: &AppState) -> SyncMetrics {
+    let status = state.status.read().await;
+    SyncMetrics {
+        sha_changes: status.sha_changes,
+        seconds_since_last_change: status
+            .last_change_at
+            .map(|at| (Utc::now() - at).num_milliseconds() as f64 / 1000.0),
+    }
+}
+
+async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let sync = sync_metrics(&state).await;
+    (
+        [(
+            header::CONTENT_TYPE,
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        )],
+        state.request_metrics.render(&sync),
+    )
+}
+
+/// The `/metrics` values as JSON.
+async fn stats(State(state): State<AppState>) -> impl IntoResponse {
+    let sync = sync_metrics(&state).await;
+    Json(state.request_metrics.stats(sync))
+}
+
+async fn meta_history(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.history.newest_first())
+}
+
+/// How far the mirror trails the remote tip last seen by a sync or
+/// `?refresh=true`. Counting needs the tip's commit locally, which a tip
+/// pushed since the last fetch isn't; those counts are then `null`.
+async fn meta_drift(
+    State(state): State<AppState>,
+    Query(query): Query<MetaQuery>,
+) -> impl IntoResponse {
+    let git = state.config.sync_mode == SyncMode::Git;
+    if git && matches!(query.refresh.as_deref(), Some("1" | "true")) {
+        refresh_remote_tip(&state.live_config.load(), state.status.clone()).await;
+    }
+    let (synced_sha, remote_tip_sha) = {
+        let status = state.status.read().await;
+        (status.current_sha.clone(), status.remote_tip_sha.clone())
+    };
+    let behind = match (
+        synced_sha.as_deref().and_then(parse_oid),
+        remote_tip_sha.as_deref().and_then(parse_oid),
+    ) {
+        (Some(synced), Some(remote)) if synced == remote => Some((0, 0)),
+        (Some(synced), Some(remote)) => {
+            let repo_dir = state.config.mirror_dir.clone();
+            match task::spawn_blocking(move || commits_behind(&repo_dir, synced, remote)).await {
+                Ok(Ok(behind)) => behind,
+                Ok(Err(err)) => {
+                    warn!("drift check failed: {err:#}");
+                    None
+                }
+                Err(_) => None,
+            }
+        }
+        _ => None,
+    };
+    Json(DriftResponse {
+        synced_sha,
+        remote_tip_sha,
+        behind_commits: behind.map(|(commits, _)| commits),
+        behind_seconds: behind.map(|(_, seconds)| seconds),
+    })
+}
+
+async fn meta_logs() -> impl IntoResponse {
+    Json(log_buffer::installed().map_or_else(Vec::new, LogBuffer::newest_first))
+}
+
+/// Paths changed between two commits in the mirror, by default from
+/// `previous_sha` to `current_sha`. Only paths some mount serves are listed,
+/// less those `.repo-sync-ignore` hides.
+async fn diff(State(state): State<AppState>, Query(query): Query<DiffQuery>) -> Response {
+    let (from, to, current_sha) = {
+        let status = state.status.read().await;
+        (
+            query.from.or_else(|| status.previous_sha.clone()),
+            query.to.or_else(|| status.current_sha.clone()),
+            status.current_sha.clone(),
+        )
+    };
+    let (Some(from), Some(to)) = (from, to) else {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            "no previous and current snapshot to diff",
+        );
+    };
+    let (Some(from), Some(to)) = (parse_oid(&from), parse_oid(&to)) else {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_sha",
+            "from and to must be full hex SHAs",
+        );
+    };
+    let result = task::spawn_blocking(move || {
+        let serve_root = state.config.serve_root();
+        let subdir = state.config.serve_subdir.clone().unwrap_or_default();
+        let mounted: Vec<PathBuf> = state
+            .config
+            .serve_mounts
+            .iter()
+            .flat_map(|mount| state.config.mount_roots(mount))
+            .filter_map(|root| {
+                root.strip_prefix(&state.config.mirror_dir)
+                    .ok()
+                    .map(FsPath::to_path_buf)
+            })
+            .collect();
+        let visible = |path: &FsPath| {
+            mounted.iter().any(|root| path.starts_with(root))
+                && !state.serve_ignore.is_ignored(
+                    &serve_root,
+                    current_sha.as_deref(),
+                    path.strip_prefix(&subdir).unwrap_or(path),
+                )
+        };
+        diff_commits(
+            &state.config.mirror_dir,
+            from,
+            to,
+            visible,
+            MAX_DIFF_ENTRIES,
+        )
+    })
+    .await;
+    match result {
+        Ok(Ok(Some(diff))) => Json(diff).into_response(),
+        Ok(Ok(None)) => error_response(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            "commit not found in mirror",
+        ),
+        Ok(Err(err)) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "diff_failed",
+            &format!("{err:#}"),
+        ),
+        Err(_) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "diff_failed",
+            "diff task failed",
+        ),
+    }
+}
+
+fn parse_oid(sha: &str) -> Option<Oid> {
+    is_object_id(sha).then(|| Oid::from_str(sha).ok()).flatten()
+}
+
+async fn get_file(
+    State(state): State<AppState>,
+    Extension(mount): Extension<ServeMount>,
+    Query(query): Query<FileQuery>,
+    uri: Uri,
+    method: Method,
+    headers: HeaderMap,
+) -> Response {
+    let Some(path) = wildcard_path(uri.path(), &mount.prefix) else {
+        return error_response(StatusCode::NOT_FOUND, "not_found", "file not found");
+    };
+    serve_mount_path(&state, &mount, &path, &uri, method, headers, &query).await
+}
+
+async fn get_branch_file(
+    State(state): State<AppState>,
+    Extension(mount): Extension<ServeMount>,
+    Query(query): Query<FileQuery>,
+    uri: Uri,
+    method: Method,
+    headers: HeaderMap,
+) -> Response {
+    let Some((branch, path)) = uri
+        .path()
+        .strip_prefix("/b/")
+        .and_then(|rest| rest.split_once('/'))
+        .and_then(|(branch, rest)| {
+            let branch = percent_decode_str(branch).decode_utf8().ok()?;
+            let path = wildcard_path(&format!("/{rest}"), &mount.prefix)?;
+            Some((branch.into_owned(), path))
+        })
+    else {
+        return error_response(StatusCode::NOT_FOUND, "not_found", "file not found");
+    };
+    let Some(branch_state) = state.branch(&branch) else {
+        return error_response(StatusCode::NOT_FOUND, "unknown_branch", "unknown branch");
+    };
+    serve_mount_path(branch_state, &mount, &path, &uri, method, headers, &query).await
+}
+
+/// Percent-decodes what follows `prefix/` in `uri_path` into OS bytes. Unlike
+/// axum's `Path` extractor this doesn't require UTF-8, so files whose names use
+/// legacy encodings on disk can still be requested.
+fn wildcard_path(uri_path: &str, prefix: &str) -> Option<PathBuf> {
+    let raw = uri_path.strip_prefix(prefix)?.strip_prefix('/')?;
+    let bytes: Vec<u8> = percent_decode_str(raw).collect();
+    Some(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+/// The mount whose prefix `path` (a request path without its leading slash)
+/// starts with, and the rest of `path` below it.
+fn mounted_path<'a>(state: &'a AppState, path: &FsPath) -> Option<(&'a ServeMount, PathBuf)> {
+    state.config.serve_mounts.iter().find_map(|mount| {
+        let rest = path
+            .strip_prefix(mount.prefix.trim_start_matches('/'))
+            .ok()?;
+        Some((mount, rest.to_path_buf()))
+    })
+}
+
+/// `resolve_under_root` of `path` in each of `roots`, against their canonical
+/// forms cached for the current snapshot, off the runtime since it resolves
+/// symlinks on disk. Fails if the path escapes any of them.
+async fn resolve_request_paths(
+    state: &AppState,
+    roots: Vec<PathBuf>,
+    path: PathBuf,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let tree = state.status.read().await.current_sha.clone();
+    let canonical_roots = state.canonical_roots.clone();
+    task::spawn_blocking(move || {
+        roots
+            .iter()
+            .map(|root| {
+                let canonical_root = canonical_roots.get(root, tree.as_deref());
+                resolve_under_root(root, canonical_root.as_deref(), &path)
+            })
+            .collect()
+    })
+    .await?
+}
+
+/// The first of the per-layer `paths` that exists, else the top layer's.
+async fn first_existing(mut paths: Vec<PathBuf>) -> PathBuf {
+    for (index, path) in paths.iter().enumerate() {
+        if fs::symlink_metadata(path).await.is_ok() {
+            return paths.swap_remove(index);
+        }
+    }
+    paths.swap_remove(0)
+}
+
+/// A trailing slash (or the mount root itself) asks for a directory only;
+/// without one `path` may name a file or a directory.
+async fn serve_mount_path(
+    state: &AppState,
+    mount: &ServeMount,
+    path: &FsPath,
+    uri: &Uri,
+    method: Method,
+    headers: HeaderMap,
+    query: &FileQuery,
+) -> Response {
+    if state.maintenance.load(Ordering::Relaxed) {
+        return maintenance_response();
+    }
+    if path.as_os_str().len() > state.live_config.load().max_path_length {
+        return error_response(StatusCode::URI_TOO_LONG, "path_too_long", "path too long");
+    }
+
+    let sha = state.status.read().await.current_sha.clone();
+    if let Some(sha) = &sha
+        && headers
+            .get(X_IF_REPO_SHA)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|client_sha| client_sha.trim().eq_ignore_ascii_case(sha))
+    {
+        return not_modified(None);
+    }
+    if let Some(target) = state
+        .redirects
+        .target(&state.config.serve_root(), sha.as_deref(), path)
+    {
+        let status = StatusCode::from_u16(state.live_config.load().redirect_status)
+            .unwrap_or(StatusCode::MOVED_PERMANENTLY);
+        return redirect_within_mount(uri, &mount.prefix, &target, status);
+    }
+
+    let timeout = Duration::from_millis(state.live_config.load().serve_gate_timeout_ms);
+    let Ok(_checkout_done) = tokio::time::timeout(timeout, state.serve_gate.read()).await else {
+        return sync_in_progress_response();
+    };
+
+    let mount_roots = state.config.mount_roots(mount);
+    let layers = match resolve_request_paths(state, mount_roots, path.to_path_buf()).await {
+        Ok(layers) => layers,
+        Err(err) => return invalid_path(state, &err, "file not found"),
+    };
+    let file_path = first_existing(layers.clone()).await;
+
+    if is_ignored(state, &file_path).await {
+        return error_response(StatusCode::NOT_FOUND, "not_found", "file not found");
+    }
+    if let Some(sha) = deleted_in(state, &file_path).await {
+        return error_response(
+            StatusCode::GONE,
+            "gone",
+            &format!("file was deleted in {sha}"),
+        );
+    }
+
+    let directory_request =
+        path.as_os_str().is_empty() || path.as_os_str().as_bytes().ends_with(b"/");
+    let metadata = fs::metadata(&file_path).await.ok();
+    let is_dir = metadata.as_ref().is_some_and(Metadata::is_dir);
+    if directory_request && metadata.is_some() && !is_dir {
+        return error_response(StatusCode::NOT_FOUND, "not_found", "not a directory");
+    }
+    if is_dir {
+        if !directory_request
+            && state.config.dir_trailing_slash_redirect
+            && state.config.dir_request_mode != DirRequestMode::NotFound
+        {
+            return redirect_to_directory(uri);
+        }
+        return match state.config.dir_request_mode {
+            DirRequestMode::NotFound => {
+                error_response(StatusCode::NOT_FOUND, "not_found", "not a file")
+            }
+            DirRequestMode::List => {
+                let mut dirs = Vec::new();
+                for layer in layers {
+                    if fs::metadata(&layer).await.is_ok_and(|m| m.is_dir()) {
+                        dirs.push(layer);
+                    }
+                }
+                list_directory(state, &dirs, query.recursive()).await
+            }
+            DirRequestMode::Index => match index_file(state, &file_path).await {
+                Some(index) => serve_file(state, index, method, headers, query).await,
+                None => error_response(StatusCode::NOT_FOUND, "not_found", "no index file"),
+            },
+        };
+    }
+
+    serve_file(state, file_path, method, headers, query).await
+}
+
+/// 308 to `uri` with a trailing slash, keeping the query.
+fn redirect_to_directory(uri: &Uri) -> Response {
+    let location = match uri.query() {
+        Some(query) => format!("{}/?{query}", uri.path()),
+        None => format!("{}/", uri.path()),
+    };
+    let mut response = StatusCode::PERMANENT_REDIRECT.into_response();
+    if let Ok(v) = HeaderValue::from_str(&location) {
+        response.headers_mut().insert(header::LOCATION, v);
+    }
+    response
+}
+
+/// Characters escaped in each segment of a redirect target.
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// `status` to `target` (a path within the mount) under the same mount prefix,
+/// and branch, as `uri`, keeping the query.
+fn redirect_within_mount(uri: &Uri, prefix: &str, target: &str, status: StatusCode) -> Response {
+    let path = uri.path();
+    let base = path
+        .strip_prefix("/b/")
+        .and_then(|rest| rest.find('/'))
+        .map(|end| &path[..end + "/b/".len()])
+        .filter(|base| path[base.len()..].starts_with(&format!("{prefix}/")))
+        .unwrap_or_default();
+    let target = target
+        .split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+    let location = match uri.query() {
+        Some(query) => format!("{base}{prefix}/{target}?{query}"),
+        None => format!("{base}{prefix}/{target}"),
+    };
+    let mut response = status.into_response();
+    if let Ok(v) = HeaderValue::from_str(&location) {
+        response.headers_mut().insert(header::LOCATION, v);
+    }
+    response
+}
+
+/// The commit that deleted `path`, if the last SHA change removed it less
+/// than `GONE_WINDOW_SECONDS` ago and nothing has taken its place since.
+async fn deleted_in(state: &AppState, path: &FsPath) -> Option<String> {
+    let window = state.live_config.load().gone_window_seconds;
+    if window == 0 {
+        return None;
+    }
+    let deletions = state.status.read().await.deletions.clone()?;
+    let relative = path.strip_prefix(&state.config.mirror_dir).ok()?;
+    if !deletions.paths.contains(relative)
+        || (Utc::now() - deletions.at)
+            .to_std()
+            .is_ok_and(|age| age > Duration::from_secs(window))
+        || fs::symlink_metadata(path).await.is_ok()
+    {
+        return None;
+    }
+    Some(deletions.sha.clone())
+}
+
+/// Whether `path` is hidden by `.repo-sync-ignore`.
+async fn is_ignored(state: &AppState, path: &FsPath) -> bool {
+    let serve_root = state.config.serve_root();
+    let Ok(relative) = path.strip_prefix(&serve_root) else {
+        return false;
+    };
+    let sha = state.status.read().await.current_sha.clone();
+    state
+        .serve_ignore
+        .is_ignored(&serve_root, sha.as_deref(), relative)
+}
+
+/// First of `INDEX_FILES` in `dir` that is a regular, non-ignored file.
+async fn index_file(state: &AppState, dir: &FsPath) -> Option<PathBuf> {
+    for name in INDEX_FILES {
+        let candidate = dir.join(name);
+        if fs::metadata(&candidate).await.is_ok_and(|m| m.is_file())
+            && !is_ignored(state, &candidate).await
+        {
+            return Some(candidate);
+        }
+    }
+    None
+}
+
+/// 503 for a request that waited `SERVE_GATE_TIMEOUT_MS` for a checkout.
+fn sync_in_progress_response() -> Response {
+    let mut response = error_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "sync_in_progress",
+        "mirror is being updated",
+    );
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(1));
+    response
+}
+
+/// Every entry below a mounted directory, addressed by its file route path
+/// (`/list-stream/files/docs` for `/files/docs`), as newline-delimited JSON
+/// `{name, kind, size}` objects, written as the walk proceeds so neither side
+/// holds the whole listing. Follows the recursive listing's rules for ignored
+/// paths, overlays and `MAX_TRAVERSAL_DEPTH`, but in walk order rather than
+/// sorted. The serve gate is held while the directory is resolved, but not
+/// while streaming: a slow client would otherwise hold off every sync, so a
+/// checkout landing mid-stream shows in later lines.
+async fn list_stream(State(state): State<AppState>, uri: Uri) -> Response {
+    if state.maintenance.load(Ordering::Relaxed) {
+        return maintenance_response();
+    }
+    let Some((mount, path)) =
+        wildcard_path(uri.path(), "/list-stream").and_then(|path| mounted_path(&state, &path))
+    else {
+        return error_response(StatusCode::NOT_FOUND, "not_found", "directory not found");
+    };
+    if path.as_os_str().len() > state.live_config.load().max_path_length {
+        return error_response(StatusCode::URI_TOO_LONG, "path_too_long", "path too long");
+    }
+    let gate = state.serve_gate.clone();
+    let timeout = Duration::from_millis(state.live_config.load().serve_gate_timeout_ms);
+    let Ok(_checkout_done) = tokio::time::timeout(timeout, gate.read()).await else {
+        return sync_in_progress_response();
+    };
+
+    let layers = match resolve_request_paths(&state, state.config.mount_roots(mount), path).await {
+        Ok(layers) => layers,
+        Err(err) => return invalid_path(&state, &err, "directory not found"),
+    };
+    if is_ignored(&state, &first_existing(layers.clone()).await).await {
+        return error_response(StatusCode::NOT_FOUND, "not_found", "directory not found");
+    }
+    let mut dirs = Vec::new();
+    for layer in layers {
+        if fs::metadata(&layer).await.is_ok_and(|m| m.is_dir()) {
+            dirs.push(layer);
+        }
+    }
+    if dirs.is_empty() {
+        return error_response(StatusCode::NOT_FOUND, "not_found", "not a directory");
+    }
+
+    let (reader, writer) = tokio::io::duplex(LIST_STREAM_BUFFER_BYTES);
+    tokio::spawn(write_listing_stream(state, dirs, writer));
+    let mut response = Response::new(Body::from_stream(ReaderStream::new(reader)));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/x-ndjson"),
+    );
+    response.extensions_mut().insert(RouteClass::List);
+    response
+}
+
+/// The walk behind `list_stream`. Names are only remembered when there are
+/// overlay layers to merge; it stops early if the client goes away.
+async fn write_listing_stream(state: AppState, dirs: Vec<PathBuf>, mut writer: DuplexStream) {
+    let max_depth = state.live_config.load().max_traversal_depth;
+    let mut seen: Option<HashMap<String, bool>> = (dirs.len() > 1).then(HashMap::new);
+    for dir in dirs {
+        let mut pending = vec![(dir, String::new(), 1)];
+        while let Some((path, prefix, depth)) = pending.pop() {
+            let Ok(mut read_dir) = fs::read_dir(&path).await else {
+                continue;
+            };
+            while let Ok(Some(child)) = read_dir.next_entry().await {
+                let Some((mut entry, child_path)) = visible_entry(&state, &child).await else {
+                    continue;
+                };
+                entry.name = format!("{prefix}{}", entry.name);
+                // Never a symlink, so the walk can't leave the mount.
+                let is_dir = matches!(entry.kind, EntryKind::Dir);
+                let previous = seen
+                    .as_ref()
+                    .and_then(|seen| seen.get(&entry.name).copied());
+                // A file in a higher layer hides a directory of the same name.
+                let mut truncated = false;
+                if is_dir && previous != Some(false) {
+                    if depth < max_depth {
+                        pending.push((child_path, format!("{}/", entry.name), depth + 1));
+                    } else {
+                        truncated = true;
+                    }
+                }
+                if previous.is_some() {
+                    continue;
+                }
+                if let Some(seen) = &mut seen {
+                    seen.insert(entry.name.clone(), is_dir);
+                }
+                let Ok(mut line) = serde_json::to_vec(&StreamedEntry { entry, truncated }) else {
+                    continue;
+                };
+                line.push(b'\n');
+                if writer.write_all(&line).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Lists `dirs` as JSON, sorted by name. Recursive listings walk at most `MAX_TRAVERSAL_DEPTH`
+/// levels below each dir, naming entries by their path relative to it.
+/// `dirs` are the same directory in each overlay layer: their entries are
+/// merged, and where a name appears in several the first layer's entry wins.
+async fn list_directory(state: &AppState, dirs: &[PathBuf], recursive: bool) -> Response {
+    let mut response = list_directory_json(state, dirs, recursive)
+        .await
+        .unwrap_or_else(|| {
+            error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "read_failed",
+                "failed to read directory",
+            )
+        });
+    response.extensions_mut().insert(RouteClass::List);
+    response
+}
+
+/// The listing body for `list_directory`, or `None` when no dir can be read.
+async fn list_directory_json(
+    state: &AppState,
+    dirs: &[PathBuf],
+    recursive: bool,
+) -> Option<Response> {
+    let max_depth = state.live_config.load().max_traversal_depth;
+    let mut entries = Vec::new();
+    // Names listed so far, and whether each is a directory.
+    let mut seen: HashMap<String, bool> = HashMap::new();
+    let mut truncated = false;
+    let mut readable = false;
+    for dir in dirs {
+        let mut pending = vec![(dir.clone(), String::new(), 1)];
+        while let Some((path, prefix, depth)) = pending.pop() {
+            let children = match read_dir_entries(state, &path).await {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            readable |= depth == 1;
+            for (mut entry, child_path) in children {
+                entry.name = format!("{prefix}{}", entry.name);
+                let is_dir = matches!(entry.kind, EntryKind::Dir);
+                // A file in a higher layer hides a directory of the same name.
+                // `Dir` is never a symlink, so the walk stays inside `dir`.
+                let shadowed = seen.get(&entry.name).is_some_and(|&dir| !dir);
+                if recursive && is_dir && !shadowed {
+                    if depth < max_depth {
+                        pending.push((child_path, format!("{}/", entry.name), depth + 1));
+                    } else {
+                        truncated = true;
+                    }
+                }
+                if !seen.contains_key(&entry.name) {
+                    seen.insert(entry.name.clone(), is_dir);
+                    entries.push(entry);
+                }
+            }
+        }
+    }
+    if !readable {
+        return None;
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    if !recursive {
+        return Some(Json(entries).into_response());
+    }
+    Some(Json(RecursiveListing { entries, truncated }).into_response())
+}
+
+/// Immediate children of `dir`, in no particular order. Ignored paths, the
+/// `.git` directory and anything that isn't a regular file or directory are
+/// omitted.
+async fn read_dir_entries(
+    state: &AppState,
+    dir: &FsPath,
+) -> std::io::Result<Vec<(DirEntry, PathBuf)>> {
+    let mut read_dir = fs::read_dir(dir).await?;
+    let mut entries = Vec::new();
+    while let Ok(Some(entry)) = read_dir.next_entry().await {
+        entries.extend(visible_entry(state, &entry).await);
+    }
+    Ok(entries)
+}
+
+/// `entry` as listed, or `None` for `.git`, ignored paths and anything that
+/// isn't a regular file or directory. Symlinks are not followed, so neither a
+/// listing nor a walk built on it can reach outside the root.
+async fn visible_entry(state: &AppState, entry: &fs::DirEntry) -> Option<(DirEntry, PathBuf)> {
+    let name = entry.file_name().to_string_lossy().to_string();
+    if name == ".git" || is_ignored(state, &entry.path()).await {
+        return None;
+    }
+    let metadata = entry.metadata().await.ok()?;
+    let (kind, size) = if metadata.is_file() {
+        (EntryKind::File, Some(metadata.len()))
+    } else if metadata.is_dir() {
+        (EntryKind::Dir, None)
+    } else {
+        return None;
+    };
+    Some((DirEntry { name, kind, size }, entry.path()))
+}
+
+async fn serve_file(
+    state: &AppState,
+    file_path: PathBuf,
+    method: Method,
+    headers: HeaderMap,
+    query: &FileQuery,
+) -> Response {
+    let started = Instant::now();
+    let metadata = match fs::metadata(&file_path).await {
+        Ok(v) => v,
+        Err(_) => {
+            return error_response(StatusCode::NOT_FOUND, "not_found", "file not found");
+        }
+    };
+
+    // Only regular files are served: FIFOs, sockets and devices could block or
+    // stream forever on read.
+    if !metadata.file_type().is_file() {
+        return error_response(StatusCode::NOT_FOUND, "not_found", "not a file");
+    }
+    let config = &state.config;
+    let (max_file_size_bytes, ranges_enabled, server_timing) = {
+        let live_config = state.live_config.load();
+        (
+            live_config.max_file_size_for(&file_path),
+            live_config.range_requests_enabled,
+            live_config.server_timing,
+        )
+    };
+    if metadata.len() > max_file_size_bytes {
+        return error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "too_large",
+            &format!("file exceeds max size of {max_file_size_bytes} bytes"),
+        );
+    }
+
+    // Serving a `.br` or `.gz` sibling makes the response depend on Accept-Encoding, and
+    // its validator must describe the compressed bytes actually sent. Likewise
+    // rendered markdown depends on Accept.
+    let markdown = config.render_markdown && is_markdown(&file_path);
+    let vary = match (config.precompressed_assets, markdown) {
+        (true, true) => Some("accept-encoding, accept"),
+        (true, false) => Some("accept-encoding"),
+        (false, true) => Some("accept"),
+        (false, false) => None,
+    };
+    if markdown && !query.download() && !query.raw() && prefers_html(&headers) {
+        return serve_rendered_markdown(state, &file_path, &metadata, &headers, vary).await;
+    }
+    let (body_path, metadata, content_encoding) =
+        match precompressed_sibling(config, max_file_size_bytes, &file_path, &headers).await {
+            Some((encoded_path, encoded_metadata, encoding)) => {
+                (encoded_path, encoded_metadata, Some(encoding.name()))
+            }
+            None => (file_path.clone(), metadata, None),
+        };
+    let mut timing = ServerTiming {
+        metadata: started.elapsed(),
+        ..ServerTiming::default()
+    };
+
+    // Weak validators come from metadata alone and strong ones may already be
+    // cached, so conditional hits and HEAD requests can skip reading the file.
+    let known_etag = match config.etag_mode {
+        EtagMode::Weak => Some(weak_etag(&metadata)),
+        EtagMode::Strong => state.digests.get(&body_path, &metadata),
+    };
+    if let Some(etag) = &known_etag
+        && if_none_match(&headers, etag)
+    {
+        return not_modified(vary);
+    }
+
+    let (etag, bytes) = match known_etag {
+        Some(etag) if method == Method::HEAD => (etag, None),
+        known_etag => {
+            let read_started = Instant::now();
+            let bytes = match read_file(state, &body_path).await {
+                Ok(v) => v,
+                Err(_) => {
+                    return error_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "read_failed",
+                        "failed to read file",
+                    );
+                }
+            };
+            timing.read = Some(read_started.elapsed());
+            let etag = match known_etag {
+                Some(etag) => etag,
+                None => {
+                    let hash_started = Instant::now();
+                    let etag = strong_etag(config.hash_algorithm, &bytes);
+                    timing.hash = Some(hash_started.elapsed());
+                    state
+                        .digests
+                        .insert(body_path.clone(), &metadata, etag.clone());
+                    if if_none_match(&headers, &etag) {
+                        return not_modified(vary);
+                    }
+                    etag
+                }
+            };
+            (etag, Some(bytes))
+        }
+    };
+
+    let last_modified = last_modified(state, &file_path, &metadata).await;
+
+    let (status, body, content_length, content_range) = match bytes {
+        None => (StatusCode::OK, Vec::new(), metadata.len(), None),
+        Some(bytes) => {
+            // A stale If-Range validator means the client's partial copy is from
+            // an older version, so it gets the full current body instead.
+            let total_len = bytes.len() as u64;
+            let range = headers
+                .get(header::RANGE)
+                .filter(|_| ranges_enabled)
+                .and_then(|v| v.to_str().ok())
+                .filter(|_| {
+                    headers
+                        .get(header::IF_RANGE)
+                        .and_then(|v| v.to_str().ok())
+                        .is_none_or(|if_range| {
+                            if_range_matches(if_range, &etag, last_modified.as_deref())
+                        })
+                })
+                .map_or(ByteRange::Ignored, |value| parse_range(value, total_len));
+            match range {
+                ByteRange::Satisfiable { start, end } => (
+                    StatusCode::PARTIAL_CONTENT,
+                    bytes[start as usize..=end as usize].to_vec(),
+                    end - start + 1,
+                    Some(format!("bytes {start}-{end}/{total_len}")),
+                ),
+                ByteRange::Unsatisfiable => {
+                    let mut response = StatusCode::RANGE_NOT_SATISFIABLE.into_response();
+                    if let Ok(v) = HeaderValue::from_str(&format!("bytes */{total_len}")) {
+                        response.headers_mut().insert(header::CONTENT_RANGE, v);
+                    }
+                    return response;
+                }
+                ByteRange::Ignored => (StatusCode::OK, bytes, total_len, None),
+            }
+        }
+    };
+
+    let mut content_type = content_type(config, &file_path);
+    if config.mime_sniffing
+        && content_type == "application/octet-stream"
+        && type_override(config, &file_path).is_none()
+        && let Some(sniffed) = sniff_file(&file_path).await
+    {
+        content_type = sniffed.to_string();
+    }
+    let content_length = HeaderValue::from(content_length);
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(header::CONTENT_LENGTH, content_length);
+    response.headers_mut().insert(
+        header::ACCEPT_RANGES,
+        HeaderValue::from_static(if ranges_enabled { "bytes" } else { "none" }),
+    );
+    if let Some(content_encoding) = content_encoding {
+        response.headers_mut().insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static(content_encoding),
+        );
+    }
+    if let Some(vary) = vary {
+        response
+            .headers_mut()
+            .insert(header::VARY, HeaderValue::from_static(vary));
+    }
+    if let Some(content_range) = content_range
+        && let Ok(v) = HeaderValue::from_str(&content_range)
+    {
+        response.headers_mut().insert(header::CONTENT_RANGE, v);
+    }
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(&content_type)
+            .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream")),
+    );
+    response.headers_mut().insert(
+        header::ETAG,
+        HeaderValue::from_str(&etag).unwrap_or_else(|_| HeaderValue::from_static("\"invalid\"")),
+    );
+    if let Some(last_modified) = last_modified
+        && let Ok(v) = HeaderValue::from_str(&last_modified)
+    {
+        response.headers_mut().insert(header::LAST_MODIFIED, v);
+    }
+    if server_timing && let Ok(v) = HeaderValue::from_str(&timing.header_value()) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static("server-timing"), v);
+    }
+    if query.download() {
+        let filename = file_path
+            .file_name()
+            .map(|name| sanitize_filename(&name.to_string_lossy()))
+            .unwrap_or_else(|| "download".to_string());
+        if let Ok(v) = HeaderValue::from_str(&format!("attachment; filename=\"{filename}\"")) {
+            response
+                .headers_mut()
+                .insert(header::CONTENT_DISPOSITION, v);
+        }
+    }
+
+    response
+}
+
+/// Phases of a file response for `SERVER_TIMING`; `None` for a phase that
+/// was skipped (a HEAD with a known validator, or a cached digest).
+#[derive(Default)]
+struct ServerTiming {
+    /// Stat of the file and any precompressed sibling.
+    metadata: Duration,
+    read: Option<Duration>,
+    hash: Option<Duration>,
+}
+
+impl ServerTiming {
+    fn header_value(&self) -> String {
+        [
+            ("metadata", Some(self.metadata)),
+            ("read", self.read),
+            ("hash", self.hash),
+        ]
+        .into_iter()
+        .filter_map(|(name, duration)| {
+            duration.map(|d| format!("{name};dur={:.3}", d.as_secs_f64() * 1000.0))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+/// `CONTENT_TYPE_OVERRIDES` entry for the file's extension, else `mime_guess`.
+fn content_type(config: &AppConfig, file_path: &FsPath) -> String {
+    type_override(config, file_path)
+        .cloned()
+        .unwrap_or_else(|| {
+            mime_guess::from_path(file_path)
+                .first_or_octet_stream()
+                .to_string()
+        })
+}
+
+fn type_override<'a>(config: &'a AppConfig, file_path: &FsPath) -> Option<&'a String> {
+    file_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| config.content_type_overrides.get(&ext.to_ascii_lowercase()))
+}
+
+/// Reads the identity file's first `SNIFF_LEN` bytes for `MIME_SNIFFING`,
+/// separately from the body since that may be precompressed, ranged or not
+/// read at all for a `HEAD`.
+async fn sniff_file(file_path: &FsPath) -> Option<&'static str> {
+    let file = fs::File::open(file_path).await.ok()?;
+    let mut prefix = Vec::with_capacity(SNIFF_LEN);
+    file.take(SNIFF_LEN as u64 + 1)
+        .read_to_end(&mut prefix)
+        .await
+        .ok()?;
+    let complete = prefix.len() <= SNIFF_LEN;
+    prefix.truncate(SNIFF_LEN);
+    sniff(&prefix, complete)
+}
+
+/// The first `<file>.br` or `<file>.gz` next to `file_path`, in the client's
+/// order of preference among `COMPRESSION_ALGORITHMS`, that is a regular file
+/// within the size limit. `None` means the identity file is served.
+async fn precompressed_sibling(
+    config: &AppConfig,
+    max_file_size_bytes: u64,
+    file_path: &FsPath,
+    headers: &HeaderMap,
+) -> Option<(PathBuf, Metadata, ContentEncoding)> {
+    if !config.precompressed_assets {
+        return None;
+    }
+    for encoding in preferred_encodings(&config.compression_algorithms, headers) {
+        let mut encoded_path = file_path.as_os_str().to_owned();
+        encoded_path.push(encoding.extension());
+        let encoded_path = PathBuf::from(encoded_path);
+        if let Ok(metadata) = fs::metadata(&encoded_path).await
+            && metadata.file_type().is_file()
+            && metadata.len() <= max_file_size_bytes
+        {
+            return Some((encoded_path, metadata, encoding));
+        }
+    }
+    None
+}
+
+/// `allowed` encodings the client accepts, highest q-value first. A coding
+/// the client doesn't name takes the `*` weight, or is refused without one;
+/// ties keep the `allowed` order.
+fn preferred_encodings(allowed: &[ContentEncoding], headers: &HeaderMap) -> Vec<ContentEncoding> {
+    let mut weights: HashMap<String, f32> = HashMap::new();
+    for coding in headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+    {
+        let mut params = coding.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default().to_ascii_lowercase();
+        if name.is_empty() {
+            continue;
+        }
+        let q = params
+            .find_map(|param| param.strip_prefix("q="))
+            .map_or(Some(1.0), |q| q.parse::<f32>().ok())
+            .unwrap_or(0.0);
+        weights.insert(name, q);
+    }
+    let wildcard = weights.get("*").copied().unwrap_or(0.0);
+    let mut accepted: Vec<(ContentEncoding, f32)> = allowed
+        .iter()
+        .map(|&encoding| {
+            let q = weights.get(encoding.name()).copied().unwrap_or(wildcard);
+            (encoding, q)
+        })
+        .filter(|&(_, q)| q > 0.0)
+        .collect();
+    accepted.sort_by(|a, b| b.1.total_cmp(&a.1));
+    accepted.into_iter().map(|(encoding, _)| encoding).collect()
+}
+
+fn not_modified(vary: Option<&'static str>) -> Response {
+    let mut response = StatusCode::NOT_MODIFIED.into_response();
+    if let Some(vary) = vary {
+        response
+            .headers_mut()
+            .insert(header::VARY, HeaderValue::from_static(vary));
+    }
+    response
+}
+
+async fn last_modified(
+    state: &AppState,
+    file_path: &FsPath,
+    metadata: &Metadata,
+) -> Option<String> {
+    let modified = match state.config.last_modified_source {
+        LastModifiedSource::Mtime => metadata.modified().ok(),
+        LastModifiedSource::Commit => commit_modified(state, file_path).await,
+    };
+    modified.map(httpdate::fmt_http_date)
+}
+
+/// Whether the client wants the rendered page: it accepts `text/html` and
+/// doesn't ask for `text/markdown`.
+fn prefers_html(headers: &HeaderMap) -> bool {
+    lists_media_type(headers, "text/html") && !lists_media_type(headers, "text/markdown")
+}
+
+/// Whether `Accept` names `media_type` explicitly (wildcards don't count).
+fn lists_media_type(headers: &HeaderMap, media_type: &str) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| {
+            v.split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .eq_ignore_ascii_case(media_type)
+        })
+}
+
+/// Serves a markdown file rendered as an HTML page. Its ETag is the source's
+/// with an `-html` suffix, so the raw and rendered variants never validate
+/// against each other. Ranges and `.gz` siblings only apply to the raw file.
+async fn serve_rendered_markdown(
+    state: &AppState,
+    file_path: &FsPath,
+    metadata: &Metadata,
+    headers: &HeaderMap,
+    vary: Option<&'static str>,
+) -> Response {
+    let config = &state.config;
+    let known_etag = match config.etag_mode {
+        EtagMode::Weak => Some(weak_etag(metadata)),
+        EtagMode::Strong => state.digests.get(file_path, metadata),
+    };
+    if let Some(etag) = &known_etag
+        && if_none_match(headers, &html_etag(etag))
+    {
+        return not_modified(vary);
+    }
+
+    let bytes = match read_file(state, file_path).await {
+        Ok(v) => v,
+        Err(_) => {
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "read_failed",
+                "failed to read file",
+            );
+        }
+    };
+    let etag = html_etag(&match known_etag {
+        Some(etag) => etag,
+        None => {
+            let etag = strong_etag(config.hash_algorithm, &bytes);
+            state
+                .digests
+                .insert(file_path.to_path_buf(), metadata, etag.clone());
+            etag
+        }
+    });
+    if if_none_match(headers, &etag) {
+        return not_modified(vary);
+    }
+
+    let title = file_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let page = render_page(&String::from_utf8_lossy(&bytes), &title);
+    let mut response = Response::new(Body::from(page));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    if let Ok(v) = HeaderValue::from_str(&etag) {
+        response.headers_mut().insert(header::ETAG, v);
+    }
+    if let Some(vary) = vary {
+        response
+            .headers_mut()
+            .insert(header::VARY, HeaderValue::from_static(vary));
+    }
+    if let Some(last_modified) = last_modified(state, file_path, metadata).await
+        && let Ok(v) = HeaderValue::from_str(&last_modified)
+    {
+        response.headers_mut().insert(header::LAST_MODIFIED, v);
+    }
+    response
+}
+
+/// `"abc"` -> `"abc-html"`, `W/"abc"` -> `W/"abc-html"`.
+fn html_etag(etag: &str) -> String {
+    format!("{}-html\"", etag.strip_suffix('"').unwrap_or(etag))
+}
+
+async fn read_file(state: &AppState, file_path: &FsPath) -> std::io::Result<Vec<u8>> {
+    state.files_read.fetch_add(1, Ordering::Relaxed);
+    fs::read(file_path).await
+}
+
+/// Time of the last commit touching `file_path` at the current SHA, memoized
+/// per SHA since walking history is expensive.
+async fn commit_modified(state: &AppState, file_path: &FsPath) -> Option<SystemTime> {
+    let sha = state.status.read().await.current_sha.clone()?;
+    let relative = file_path.strip_prefix(&state.config.mirror_dir).ok()?;
+    let time = match state.commit_times.get(&sha, relative) {
+        Some(time) => time,
+        None => {
+            let repo_dir = state.config.mirror_dir.clone();
+            let lookup_sha = sha.clone();
+            let lookup_path = relative.to_path_buf();
+            let time = task::spawn_blocking(move || {
+                last_commit_time(&repo_dir, &lookup_sha, &lookup_path)
+            })
+            .await
+            .ok()?
+            .ok()?;
+            state
+                .commit_times
+                .insert(&sha, relative.to_path_buf(), time);
+            time
+        }
+    };
+    time.map(SystemTime::from)
+}
+
+/// JSON error body; the `ErrorResponse` is also kept as a response extension
+/// so `negotiate_errors` can re-render it as HTML for browsers.
+fn error_response(status: StatusCode, code: &'static str, message: &str) -> Response {
+    let error = ErrorResponse {
+        code,
+        error: message.to_string(),
+    };
+    let mut response = (status, Json(error.clone())).into_response();
+    response.extensions_mut().insert(error);
+    response
+}
+
+/// 403 for a path the path guard rejected, or with `HIDE_FORBIDDEN` the
+/// endpoint's usual 404 so probes can't tell rejected paths from missing ones.
+fn invalid_path(state: &AppState, err: &anyhow::Error, not_found_message: &str) -> Response {
+    info!("rejected request path: {err:#}");
+    if state.live_config.load().hide_forbidden {
+        error_response(StatusCode::NOT_FOUND, "not_found", not_found_message)
+    } else {
+        error_response(StatusCode::FORBIDDEN, "invalid_path", "invalid path")
+    }
+}
+
+async fn not_found() -> Response {
+    error_response(StatusCode::NOT_FOUND, "not_found", "no such endpoint")
+}
+
+/// Gives the bare 413 of `RequestBodyLimitLayer` and 504 of `TimeoutLayer`
+/// the usual JSON error body.
+async fn middleware_errors(response: Response) -> Response {
+    if response.extensions().get::<ErrorResponse>().is_some() {
+        return response;
+    }
+    match response.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "body_too_large",
+            "request body too large",
+        ),
+        StatusCode::GATEWAY_TIMEOUT => error_response(
+            StatusCode::GATEWAY_TIMEOUT,
+            "request_timeout",
+            "request took too long to handle",
+        ),
+        _ => response,
+    }
+}
+
+/// Replaces JSON error bodies with a minimal HTML page for clients asking for
+/// `text/html` but not `application/json`, i.e. browsers. Status codes and
+/// other headers are kept.
+async fn negotiate_errors(request: Request, next: Next) -> Response {
+    let html = lists_media_type(request.headers(), "text/html")
+        && !lists_media_type(request.headers(), "application/json");
+    let response = next.run(request).await;
+    let Some(error) = html
+        .then(|| response.extensions().get::<ErrorResponse>().cloned())
+        .flatten()
+    else {
+        return response;
+    };
+    let (mut parts, _) = response.into_parts();
+    let title = format!(
+        "{} {}",
+        parts.status.as_u16(),
+        parts.status.canonical_reason().unwrap_or_default()
+    );
+    let body = format!(
+        "<h1>{}</h1>\n<p>{}</p>\n",
+        escape_html(&title),
+        escape_html(&error.error)
+    );
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(html_page(&title, &body)))
+}
+
+fn weak_etag(metadata: &Metadata) -> String {
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since_epoch| since_epoch.as_nanos());
+    format!("W/\"{}-{mtime}\"", metadata.len())
+}
+
+fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|client_etag| client_etag == etag)
+}
+
+/// Longest filename put in `Content-Disposition`; longer names keep their
+/// start and extension.
+const MAX_DOWNLOAD_FILENAME_CHARS: usize = 128;
+
+/// Longest extension kept when clamping a filename.
+const MAX_KEPT_EXTENSION_CHARS: usize = 16;
+
+/// Restricts a filename to printable ASCII without quotes or backslashes so it
+/// can be embedded in a quoted `Content-Disposition` parameter, clamped to
+/// `MAX_DOWNLOAD_FILENAME_CHARS`.
+fn sanitize_filename(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    if name.len() <= MAX_DOWNLOAD_FILENAME_CHARS {
+        return name;
+    }
+    let extension = name
+        .rfind('.')
+        .map(|dot| &name[dot..])
+        .filter(|extension| extension.len() <= MAX_KEPT_EXTENSION_CHARS)
+        .unwrap_or("");
+    format!(
+        "{}{extension}",
+        &name[..MAX_DOWNLOAD_FILENAME_CHARS - extension.len()]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use axum::{
+        body::to_bytes,
+        http::{Request, StatusCode, header},
+    };
+    use tempfile::tempdir;
+    use tokio::sync::RwLock;
+    use tower::ServiceExt;
+
+    use crate::{
+        config::{AppConfig, ContentEncoding, DirRequestMode, EtagMode, HashAlgorithm, ServeMount},
+        sync::{SyncStatus, serve_gate},
+    };
+
+    use std::sync::atomic::Ordering;
+
+    use super::{
+        AppState, HEALTH_CHECK_CACHE, MAX_HEALTH_CHECK_OUTPUT, admin_router, public_router, router,
+    };
+
+    fn get_request(uri: &str) -> Request<axum::body::Body> {
+        Request::builder()
+            .uri(uri)
+            .body(axum::body::Body::empty())
+            .expect("request")
+    }
+
+    fn test_config(mirror: std::path::PathBuf) -> AppConfig {
+        AppConfig {
+            git_repo_url: "https://github.com/org/repo.git".to_string(),
+            mirror_dir: mirror,
+            http_bind_addr: "127.0.0.1:0".to_string(),
+            max_file_size_bytes: 1024 * 1024,
+            ..AppConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn file_serving_reflects_file_update_without_restart() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a.txt"), "one").expect("write file");
+
+        let state = AppState::new(
+            test_config(mirror),
+            Arc::new(RwLock::new(SyncStatus::default())),
+        );
+        let app = router(state);
+
+        let first = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/files/a.txt")
+                    .body(axum::body::Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response");
+        assert_eq!(first.status(), StatusCode::OK);
+        let first_body = to_bytes(first.into_body(), usize::MAX)
+            .await
+            .expect("read body");
+        assert_eq!(first_body.as_ref(), b"one");
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        std::fs::write(temp.path().join("repo").join("a.txt"), "two").expect("rewrite file");
+
+        let second = app
+            .oneshot(
+                Request::builder()
+                    .uri("/files/a.txt")
+                    .body(axum::body::Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response");
+        assert_eq!(second.status(), StatusCode::OK);
+        let second_body = to_bytes(second.into_body(), usize::MAX)
+            .await
+            .expect("read body");
+        assert_eq!(second_body.as_ref(), b"two");
+    }
+
+    #[tokio::test]
+    async fn responds_not_modified_when_etag_matches() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a.txt"), "same").expect("write file");
+
+        let state = AppState::new(
+            test_config(mirror),
+            Arc::new(RwLock::new(SyncStatus::default())),
+        );
+        let app = router(state);
+
+        let first = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/files/a.txt")
+                    .body(axum::body::Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response");
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = first
+            .headers()
+            .get(header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .expect("etag")
+            .to_string();
+
+        let second = app
+            .oneshot(
+                Request::builder()
+                    .uri("/files/a.txt")
+                    .header(header::IF_NONE_MATCH, etag)
+                    .body(axum::body::Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response");
+        assert_eq!(second.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[tokio::test]
+    async fn blake3_etags_are_stable_and_match() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a.txt"), "same").expect("write file");
+
+        let app = router(AppState::new(
+            AppConfig {
+                hash_algorithm: HashAlgorithm::Blake3,
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+        let get = |etag: Option<&str>| {
+            let mut request = Request::builder().uri("/files/a.txt");
+            if let Some(etag) = etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            request.body(axum::body::Body::empty()).expect("request")
+        };
+
+        let expected = format!("\"{}\"", blake3::hash(b"same").to_hex());
+        for _ in 0..2 {
+            let response = app.clone().oneshot(get(None)).await.expect("response");
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                response
+                    .headers()
+                    .get(header::ETAG)
+                    .and_then(|v| v.to_str().ok()),
+                Some(expected.as_str())
+            );
+        }
+        let response = app.oneshot(get(Some(&expected))).await.expect("response");
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[tokio::test]
+    async fn serves_precompressed_sibling_when_gzip_accepted() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("data.json"), "{\"plain\":true}").expect("write plain");
+        std::fs::write(mirror.join("data.json.gz"), b"\x1f\x8bcompressed").expect("write gz");
+        std::fs::write(mirror.join("other.json"), "{}").expect("write plain only");
+
+        let app = router(AppState::new(
+            AppConfig {
+                precompressed_assets: true,
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+        let get = |uri: &str, accept_encoding: Option<&str>| {
+            let mut request = Request::builder().uri(uri);
+            if let Some(accept_encoding) = accept_encoding {
+                request = request.header(header::ACCEPT_ENCODING, accept_encoding);
+            }
+            request.body(axum::body::Body::empty()).expect("request")
+        };
+
+        let hit = app
+            .clone()
+            .oneshot(get("/files/data.json", Some("br, gzip;q=0.8")))
+            .await
+            .expect("response");
+        assert_eq!(hit.status(), StatusCode::OK);
+        assert_eq!(hit.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(hit.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(hit.headers()[header::VARY], "accept-encoding");
+        let gz_etag = hit.headers()[header::ETAG].clone();
+        let body = axum::body::to_bytes(hit.into_body(), usize::MAX)
+            .await
+            .expect("body");
+        assert_eq!(&body[..], b"\x1f\x8bcompressed");
+
+        for (uri, accept_encoding, expected) in [
+            ("/files/data.json", None, "{\"plain\":true}"),
+            ("/files/data.json", Some("gzip;q=0"), "{\"plain\":true}"),
+            ("/files/other.json", Some("gzip"), "{}"),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(get(uri, accept_encoding))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+            assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+            assert_eq!(response.headers()[header::VARY], "accept-encoding");
+            assert_ne!(response.headers()[header::ETAG], gz_etag);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("body");
+            assert_eq!(body, expected, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn negotiates_brotli_over_gzip_by_q_value() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("app.js"), "plain").expect("write plain");
+        std::fs::write(mirror.join("app.js.gz"), "gzipped").expect("write gz");
+        std::fs::write(mirror.join("app.js.br"), "brotli").expect("write br");
+
+        let get = |app: axum::Router, accept_encoding: Option<&'static str>| async move {
+            let mut request = Request::builder().uri("/files/app.js");
+            if let Some(accept_encoding) = accept_encoding {
+                request = request.header(header::ACCEPT_ENCODING, accept_encoding);
+            }
+            let response = app
+                .oneshot(request.body(axum::body::Body::empty()).expect("request"))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::VARY], "accept-encoding");
+            let encoding = response
+                .headers()
+                .get(header::CONTENT_ENCODING)
+                .map(|v| v.to_str().expect("ascii").to_string());
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("body");
+            (encoding, String::from_utf8(body.to_vec()).expect("utf-8"))
+        };
+        let served =
+            |encoding: Option<&str>, body: &str| (encoding.map(str::to_string), body.to_string());
+
+        let app = router(AppState::new(
+            AppConfig {
+                precompressed_assets: true,
+                ..test_config(mirror.clone())
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+        for (accept_encoding, expected) in [
+            (Some("gzip, deflate, br"), served(Some("br"), "brotli")),
+            (Some("gzip;q=1, br;q=0.5"), served(Some("gzip"), "gzipped")),
+            (Some("gzip"), served(Some("gzip"), "gzipped")),
+            (Some("*;q=0.1, br;q=0"), served(Some("gzip"), "gzipped")),
+            (Some("identity"), served(None, "plain")),
+            (None, served(None, "plain")),
+        ] {
+            assert_eq!(
+                get(app.clone(), accept_encoding).await,
+                expected,
+                "{accept_encoding:?}"
+            );
+        }
+
+        let gzip_only = router(AppState::new(
+            AppConfig {
+                precompressed_assets: true,
+                compression_algorithms: vec![ContentEncoding::Gzip],
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+        assert_eq!(
+            get(gzip_only, Some("br, gzip;q=0.5")).await,
+            served(Some("gzip"), "gzipped")
+        );
+    }
+
+    #[tokio::test]
+    async fn recursive_listing_stops_at_max_traversal_depth() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        // Depth is counted from the requested directory, so `outer/` itself
+        // doesn't use up any of the limit.
+        std::fs::create_dir_all(mirror.join("outer/docs/a/b/c")).expect("create dirs");
+        std::fs::write(mirror.join("outer/docs/top.txt"), "top").expect("write top");
+        std::fs::write(mirror.join("outer/docs/a/b/mid.txt"), "mid").expect("write mid");
+        std::fs::write(mirror.join("outer/docs/a/b/c/deep.txt"), "deep").expect("write deep");
+        let app = |max_traversal_depth| {
+            router(AppState::new(
+                AppConfig {
+                    dir_request_mode: DirRequestMode::List,
+                    max_traversal_depth,
+                    ..test_config(mirror.clone())
+                },
+                Arc::new(RwLock::new(SyncStatus::default())),
+            ))
+        };
+        let listing = |response: axum::response::Response| async move {
+            assert_eq!(response.status(), StatusCode::OK);
+            let body: serde_json::Value = serde_json::from_slice(
+                &axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .expect("body"),
+            )
+            .expect("json body");
+            let names: Vec<String> = body["entries"]
+                .as_array()
+                .expect("entries")
+                .iter()
+                .map(|e| e["name"].as_str().expect("name").to_string())
+                .collect();
+            (names, body["truncated"].as_bool().expect("truncated"))
+        };
+
+        let response = app(3)
+            .oneshot(get_request("/files/outer/docs?recursive=1"))
+            .await
+            .expect("response");
+        let (names, truncated) = listing(response).await;
+        assert_eq!(names, ["a", "a/b", "a/b/c", "a/b/mid.txt", "top.txt"]);
+        assert!(truncated);
+
+        let response = app(4)
+            .oneshot(get_request("/files/outer/docs?recursive=1"))
+            .await
+            .expect("response");
+        let (names, truncated) = listing(response).await;
+        assert!(names.contains(&"a/b/c/deep.txt".to_string()));
+        assert!(!truncated);
+    }
+
+    #[tokio::test]
+    async fn list_stream_writes_one_ndjson_line_per_entry() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(mirror.join("big/nested/deeper")).expect("create dirs");
+        std::fs::create_dir_all(mirror.join(".git")).expect("create git dir");
+        for i in 0..500 {
+            std::fs::write(mirror.join(format!("big/{i}.json")), "{}").expect("write file");
+        }
+        std::fs::write(mirror.join("big/nested/deeper/leaf.txt"), "leaf").expect("write leaf");
+        std::fs::write(mirror.join("top.txt"), "top").expect("write top");
+        let app = |dir_request_mode, max_traversal_depth| {
+            router(AppState::new(
+                AppConfig {
+                    dir_request_mode,
+                    max_traversal_depth,
+                    ..test_config(mirror.clone())
+                },
+                Arc::new(RwLock::new(SyncStatus::default())),
+            ))
+        };
+        let lines = |response: axum::response::Response| async move {
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                "application/x-ndjson"
+            );
+            let body = to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("read body");
+            String::from_utf8(body.to_vec())
+                .expect("utf-8 body")
+                .lines()
+                .map(|line| serde_json::from_str(line).expect("json line"))
+                .collect::<Vec<serde_json::Value>>()
+        };
+
+        let response = app(DirRequestMode::List, 32)
+            .oneshot(get_request("/list-stream/files"))
+            .await
+            .expect("response");
+        let entries = lines(response).await;
+        // 500 files, big/, nested/, deeper/, leaf.txt and top.txt; no .git.
+        assert_eq!(entries.len(), 505);
+        assert!(
+            entries
+                .iter()
+                .any(|e| e["name"] == "big/nested/deeper/leaf.txt")
+        );
+        assert!(entries.iter().all(|e| e.get("truncated").is_none()));
+
+        let response = app(DirRequestMode::List, 1)
+            .oneshot(get_request("/list-stream/files/big"))
+            .await
+            .expect("response");
+        let entries = lines(response).await;
+        assert_eq!(entries.len(), 501);
+        let nested = entries
+            .iter()
+            .find(|e| e["name"] == "nested")
+            .expect("nested listed");
+        assert_eq!(nested["kind"], "dir");
+        assert_eq!(nested["truncated"], true);
+
+        let response = app(DirRequestMode::List, 32)
+            .oneshot(get_request("/list-stream/files/top.txt"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = app(DirRequestMode::NotFound, 32)
+            .oneshot(get_request("/list-stream/files"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn listings_leave_out_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        let outside = temp.path().join("outside");
+        std::fs::create_dir_all(mirror.join("docs")).expect("create docs dir");
+        std::fs::create_dir_all(outside.join("secretdir")).expect("create outside dir");
+        std::fs::write(mirror.join("docs/a.txt"), "a").expect("write file");
+        std::fs::write(outside.join("secretdir/passwords.txt"), "pw").expect("write file");
+        symlink(&outside, mirror.join("docs/escape")).expect("dir symlink");
+        symlink(
+            outside.join("secretdir/passwords.txt"),
+            mirror.join("docs/pw.txt"),
+        )
+        .expect("file symlink");
+        let app = router(AppState::new(
+            AppConfig {
+                dir_request_mode: DirRequestMode::List,
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+        let body = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(get_request(uri)).await.expect("response");
+                assert_eq!(response.status(), StatusCode::OK, "{uri}");
+                let body = to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .expect("read body");
+                String::from_utf8(body.to_vec()).expect("utf-8 body")
+            }
+        };
+
+        let listing = body("/files/docs/").await;
+        assert!(listing.contains("a.txt"), "{listing}");
+        assert!(
+            !listing.contains("escape") && !listing.contains("pw.txt"),
+            "{listing}"
+        );
+        let recursive = body("/files/docs/?recursive=1").await;
+        assert!(recursive.contains("a.txt"), "{recursive}");
+        assert!(!recursive.contains("passwords"), "{recursive}");
+        let streamed = body("/list-stream/files").await;
+        assert!(streamed.contains("docs/a.txt"), "{streamed}");
+        assert!(!streamed.contains("passwords"), "{streamed}");
+    }
+
+    #[tokio::test]
+    async fn list_stream_stays_inside_mounts() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(mirror.join("public/docs")).expect("create dirs");
+        std::fs::create_dir_all(mirror.join("private")).expect("create dirs");
+        std::fs::write(mirror.join("public/docs/a.txt"), "a").expect("write file");
+        std::fs::write(mirror.join("private/secret.txt"), "s").expect("write file");
+        let app = router(AppState::new(
+            AppConfig {
+                dir_request_mode: DirRequestMode::List,
+                serve_mounts: vec![ServeMount {
+                    prefix: "/files".to_string(),
+                    subdir: "public".into(),
+                }],
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+        let get = |uri: &'static str| {
+            let app = app.clone();
+            async move { app.oneshot(get_request(uri)).await.expect("response") }
+        };
+
+        let response = get("/list-stream/files").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("read body");
+        let body = String::from_utf8(body.to_vec()).expect("utf-8 body");
+        assert!(body.contains("docs/a.txt"));
+        assert!(!body.contains("secret"));
+        for uri in [
+            "/list-stream",
+            "/list-stream/private",
+            "/list-stream/public",
+            "/list-stream/files/../private",
+        ] {
+            assert_ne!(get(uri).await.status(), StatusCode::OK, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn renders_markdown_for_html_clients_and_keeps_raw_variant() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("README.md"), "# Title\n\nSome *text*.\n").expect("write md");
+        std::fs::write(mirror.join("notes.txt"), "# not markdown").expect("write txt");
+        let app = router(AppState::new(
+            AppConfig {
+                render_markdown: true,
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+        let get = |uri: &str, accept: &str, if_none_match: Option<&str>| {
+            let mut request = Request::builder().uri(uri).header(header::ACCEPT, accept);
+            if let Some(etag) = if_none_match {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            request.body(axum::body::Body::empty()).expect("request")
+        };
+        let html_accept = "text/html,application/xhtml+xml,*/*;q=0.8";
+
+        let rendered = app
+            .clone()
+            .oneshot(get("/files/README.md", html_accept, None))
+            .await
+            .expect("response");
+        assert_eq!(rendered.status(), StatusCode::OK);
+        assert_eq!(
+            rendered.headers()[header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(rendered.headers()[header::VARY], "accept");
+        let rendered_etag = rendered.headers()[header::ETAG]
+            .to_str()
+            .expect("etag")
+            .to_string();
+        let body = to_bytes(rendered.into_body(), usize::MAX)
+            .await
+            .expect("read body");
+        let body = String::from_utf8(body.to_vec()).expect("utf-8 body");
+        assert!(body.contains("<h1>Title</h1>"));
+        assert!(body.contains("<em>text</em>"));
+
+        for (uri, accept) in [
+            ("/files/README.md?raw=1", html_accept),
+            ("/files/README.md", "text/markdown, text/html"),
+        ] {
+            let raw = app
+                .clone()
+                .oneshot(get(uri, accept, None))
+                .await
+                .expect("response");
+            assert_eq!(raw.status(), StatusCode::OK, "{uri}");
+            assert_eq!(raw.headers()[header::VARY], "accept");
+            assert_ne!(raw.headers()[header::ETAG], rendered_etag.as_str());
+            let body = to_bytes(raw.into_body(), usize::MAX)
+                .await
+                .expect("read body");
+            assert_eq!(body.as_ref(), b"# Title\n\nSome *text*.\n", "{uri}");
+        }
+
+        let revalidated = app
+            .clone()
+            .oneshot(get("/files/README.md", html_accept, Some(&rendered_etag)))
+            .await
+            .expect("response");
+        assert_eq!(revalidated.status(), StatusCode::NOT_MODIFIED);
+        let raw_with_html_etag = app
+            .clone()
+            .oneshot(get(
+                "/files/README.md?raw=1",
+                html_accept,
+                Some(&rendered_etag),
+            ))
+            .await
+            .expect("response");
+        assert_eq!(raw_with_html_etag.status(), StatusCode::OK);
+
+        let other = app
+            .oneshot(get("/files/notes.txt", html_accept, None))
+            .await
+            .expect("response");
+        assert!(other.headers().get(header::VARY).is_none());
+        let body = to_bytes(other.into_body(), usize::MAX)
+            .await
+            .expect("read body");
+        assert_eq!(body.as_ref(), b"# not markdown");
+    }
+
+    #[tokio::test]
+    async fn snapshot_reads_the_commit_before_answering() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        let repo = git2::Repository::init(&mirror).expect("init repo");
+        std::fs::write(mirror.join("a.txt"), "one").expect("write file");
+        let mut index = repo.index().expect("index");
+        index
+            .add_path(std::path::Path::new("a.txt"))
+            .expect("stage file");
+        let tree = repo
+            .find_tree(index.write_tree().expect("write tree"))
+            .expect("tree");
+        let signature = git2::Signature::now("test", "test@example.com").expect("signature");
+        let sha = repo
+            .commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+            .expect("commit");
+        let app = |sha: String| {
+            router(AppState::new(
+                test_config(mirror.clone()),
+                Arc::new(RwLock::new(SyncStatus {
+                    current_sha: Some(sha),
+                    ..SyncStatus::default()
+                })),
+            ))
+        };
+
+        let response = app(sha.to_string())
+            .oneshot(get_request("/snapshot.tar.gz"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("read body");
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(body.as_ref()));
+        let paths: Vec<String> = archive
+            .entries()
+            .expect("entries")
+            .map(|entry| {
+                entry
+                    .expect("entry")
+                    .path()
+                    .expect("path")
+                    .display()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(paths, ["files/a.txt"]);
+
+        let response = app("0123456789abcdef0123456789abcdef01234567".to_string())
+            .oneshot(get_request("/snapshot.tar.gz"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(response.headers().get(header::ETAG).is_none());
+    }
+
+    #[tokio::test]
+    async fn diff_lists_only_served_paths() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        let repo = git2::Repository::init(&mirror).expect("init repo");
+        let signature = git2::Signature::now("test", "test@example.com").expect("signature");
+        let commit = |files: &[(&str, &str)], parents: &[&git2::Commit]| {
+            let mut index = repo.index().expect("index");
+            for (path, contents) in files {
+                let full = mirror.join(path);
+                std::fs::create_dir_all(full.parent().expect("parent")).expect("create dir");
+                std::fs::write(&full, contents).expect("write file");
+                index
+                    .add_path(std::path::Path::new(path))
+                    .expect("stage file");
+            }
+            index.write().expect("write index");
+            let tree = repo
+                .find_tree(index.write_tree().expect("write tree"))
+                .expect("tree");
+            repo.commit(Some("HEAD"), &signature, &signature, "sync", &tree, parents)
+                .expect("commit")
+        };
+        let from = commit(
+            &[
+                ("site/.repo-sync-ignore", "hidden.txt\n"),
+                ("site/public/a.txt", "one"),
+                ("site/private.txt", "secret"),
+                ("outside.txt", "x"),
+            ],
+            &[],
+        );
+        let parent = repo.find_commit(from).expect("commit");
+        let to = commit(
+            &[
+                ("site/public/a.txt", "two!"),
+                ("site/public/b.txt", "new"),
+                ("site/public/hidden.txt", "shh"),
+                ("site/private.txt", "still secret"),
+                ("outside.txt", "y"),
+            ],
+            &[&parent],
+        );
+        let app = admin_router(AppState::new(
+            AppConfig {
+                serve_subdir: Some("site".into()),
+                serve_mounts: vec![ServeMount {
+                    prefix: "/files".to_string(),
+                    subdir: "public".into(),
+                }],
+                ..test_config(mirror.clone())
+            },
+            Arc::new(RwLock::new(SyncStatus {
+                previous_sha: Some(from.to_string()),
+                current_sha: Some(to.to_string()),
+                ..SyncStatus::default()
+            })),
+        ));
+
+        let response = app.oneshot(get_request("/diff")).await.expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(
+            &to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("read body"),
+        )
+        .expect("json body");
+        assert_eq!(
+            body["changes"],
+            serde_json::json!([
+                {
+                    "path": "site/public/a.txt",
+                    "change": "modified",
+                    "old_size": 3,
+                    "new_size": 4,
+                    "size_delta": 1,
+                },
+                {
+                    "path": "site/public/b.txt",
+                    "change": "added",
+                    "old_size": null,
+                    "new_size": 3,
+                    "size_delta": 3,
+                },
+            ])
+        );
+        assert_eq!(body["truncated"], false);
+    }
+
+    #[tokio::test]
+    async fn x_if_repo_sha_answers_304_while_the_commit_is_current() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a.txt"), "one").expect("write file");
+        let sha = "0123456789abcdef0123456789abcdef01234567";
+        let app = router(AppState::new(
+            test_config(mirror),
+            Arc::new(RwLock::new(SyncStatus {
+                current_sha: Some(sha.to_string()),
+                ..SyncStatus::default()
+            })),
+        ));
+        let with_sha = |uri: &str, client_sha: &str| {
+            Request::builder()
+                .uri(uri)
+                .header("x-if-repo-sha", client_sha)
+                .body(axum::body::Body::empty())
+                .expect("request")
+        };
+
+        let response = app
+            .clone()
+            .oneshot(with_sha("/files/a.txt", sha))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        // The filesystem isn't consulted, so even a missing file is unchanged.
+        let response = app
+            .clone()
+            .oneshot(with_sha("/files/missing.txt", &sha.to_uppercase()))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = app
+            .clone()
+            .oneshot(with_sha(
+                "/files/a.txt",
+                "fedcba9876543210fedcba9876543210fedcba98",
+            ))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("read body");
+        assert_eq!(&body[..], b"one");
+
+        let response = app
+            .oneshot(get_request("/files/a.txt"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn serves_robots_txt_and_an_empty_favicon() {
+        let temp = tempdir().expect("temp dir");
+        let app = |robots_txt: &str| {
+            router(AppState::new(
+                AppConfig {
+                    robots_txt: robots_txt.to_string(),
+                    ..test_config(temp.path().to_path_buf())
+                },
+                Arc::new(RwLock::new(SyncStatus::default())),
+            ))
+        };
+
+        let response = app(crate::config::DEFAULT_ROBOTS_TXT)
+            .oneshot(get_request("/robots.txt"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(
+            response.headers()[header::CONTENT_TYPE]
+                .to_str()
+                .expect("ascii")
+                .starts_with("text/plain")
+        );
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("read body");
+        assert_eq!(&body[..], b"User-agent: *\nDisallow: /\n");
+
+        let response = app("User-agent: *\nAllow: /\n")
+            .oneshot(get_request("/robots.txt"))
+            .await
+            .expect("response");
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("read body");
+        assert_eq!(&body[..], b"User-agent: *\nAllow: /\n");
+
+        let response = app(crate::config::DEFAULT_ROBOTS_TXT)
+            .oneshot(get_request("/favicon.ico"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(response.headers().contains_key(header::CACHE_CONTROL));
+    }
+
+    #[tokio::test]
+    async fn version_reports_crate_version_and_build() {
+        let temp = tempdir().expect("temp dir");
+        let app = router(AppState::new(
+            test_config(temp.path().to_path_buf()),
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+
+        let response = app
+            .oneshot(get_request("/version"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("body"),
+        )
+        .expect("json body");
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert!(body["git_commit"].as_str().is_some_and(|c| !c.is_empty()));
+        assert!(body["built_at"].is_string());
+    }
+
+    #[tokio::test]
+    async fn directory_requests_follow_dir_request_mode() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(mirror.join("docs/guides")).expect("create dirs");
+        std::fs::create_dir_all(mirror.join("empty")).expect("create empty dir");
+        std::fs::write(mirror.join("docs/index.json"), "{\"index\":true}").expect("write index");
+        std::fs::write(mirror.join("docs/notes.txt"), "notes").expect("write notes");
+        std::fs::write(mirror.join("docs/secret.txt"), "secret").expect("write secret");
+        std::fs::write(mirror.join(".repo-sync-ignore"), "secret.txt\n").expect("write ignore");
+        let app = |mode| {
+            router(AppState::new(
+                AppConfig {
+                    dir_request_mode: mode,
+                    ..test_config(mirror.clone())
+                },
+                Arc::new(RwLock::new(SyncStatus::default())),
+            ))
+        };
+
+        let response = app(DirRequestMode::NotFound)
+            .oneshot(get_request("/files/docs"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = app(DirRequestMode::List)
+            .oneshot(get_request("/files/docs"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("body"),
+        )
+        .expect("json body");
+        assert_eq!(
+            body,
+            serde_json::json!([
+                {"name": "guides", "kind": "dir"},
+                {"name": "index.json", "kind": "file", "size": 14},
+                {"name": "notes.txt", "kind": "file", "size": 5},
+            ])
+        );
+
+        let index = app(DirRequestMode::Index);
+        let response = index
+            .clone()
+            .oneshot(get_request("/files/docs"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body");
+        assert_eq!(body, "{\"index\":true}");
+        let response = index
+            .clone()
+            .oneshot(get_request("/files/empty"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = index
+            .oneshot(get_request("/files/docs/../../.."))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn trailing_slash_asks_for_a_directory() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(mirror.join("docs")).expect("create dirs");
+        std::fs::write(mirror.join("docs/index.json"), "{}").expect("write index");
+        std::fs::write(mirror.join("a.txt"), "a").expect("write file");
+        let app = |redirect| {
+            router(AppState::new(
+                AppConfig {
+                    dir_request_mode: DirRequestMode::Index,
+                    dir_trailing_slash_redirect: redirect,
+                    ..test_config(mirror.clone())
+                },
+                Arc::new(RwLock::new(SyncStatus::default())),
+            ))
+        };
+
+        for redirect in [false, true] {
+            let app = app(redirect);
+            let status = |uri: &'static str| {
+                let app = app.clone();
+                async move {
+                    app.oneshot(get_request(uri))
+                        .await
+                        .expect("response")
+                        .status()
+                }
+            };
+            assert_eq!(status("/files/a.txt").await, StatusCode::OK);
+            assert_eq!(status("/files/a.txt/").await, StatusCode::NOT_FOUND);
+            assert_eq!(status("/files/docs/").await, StatusCode::OK);
+            assert_eq!(status("/files/missing/").await, StatusCode::NOT_FOUND);
+            let expected = if redirect {
+                StatusCode::PERMANENT_REDIRECT
+            } else {
+                StatusCode::OK
+            };
+            assert_eq!(status("/files/docs").await, expected);
+        }
+
+        let response = app(true)
+            .oneshot(get_request("/files/docs?raw=1"))
+            .await
+            .expect("response");
+        assert_eq!(
+            response
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|v| v.to_str().ok()),
+            Some("/files/docs/?raw=1")
+        );
+    }
+
+    #[tokio::test]
+    async fn serves_non_utf8_filenames() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join(OsStr::from_bytes(b"caf\xe9.txt")), "latin1")
+            .expect("write file");
+
+        let state = AppState::new(
+            test_config(mirror),
+            Arc::new(RwLock::new(SyncStatus::default())),
+        );
+        let app = router(state);
+
+        let response = app
+            .clone()
+            .oneshot(get_request("/files/caf%E9.txt"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("read body");
+        assert_eq!(body.as_ref(), b"latin1");
+
+        let exists = app
+            .clone()
+            .oneshot(get_request("/exists/files/caf%E9.txt"))
+            .await
+            .expect("response");
+        assert_eq!(exists.status(), StatusCode::OK);
+
+        let escape = app
+            .oneshot(get_request("/files/..%2F..%2Fcaf%E9.txt"))
+            .await
+            .expect("response");
+        assert_eq!(escape.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn exists_reports_files_dirs_and_missing_paths() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(mirror.join("docs")).expect("create docs dir");
+        std::fs::write(mirror.join("docs/a.json"), "{\"a\":1}").expect("write file");
+        std::fs::write(mirror.join("docs/hidden.json"), "{}").expect("write hidden");
+        std::fs::write(mirror.join(".repo-sync-ignore"), "hidden.json\n").expect("write ignore");
+        let status = SyncStatus {
+            current_sha: Some("abc123".to_string()),
+            ..SyncStatus::default()
+        };
+        let state = AppState::new(test_config(mirror), Arc::new(RwLock::new(status)));
+        let app = router(state.clone());
+
+        for (uri, expected) in [
+            (
+                "/exists/files/docs/a.json",
+                serde_json::json!({"exists": true, "is_dir": false, "size": 7, "sha": "abc123"}),
+            ),
+            (
+                "/exists/files/docs",
+                serde_json::json!({"exists": true, "is_dir": true, "size": null, "sha": "abc123"}),
+            ),
+            (
+                "/exists/files/docs/missing.json",
+                serde_json::json!({"exists": false, "is_dir": false, "size": null, "sha": "abc123"}),
+            ),
+            (
+                "/exists/files/docs/hidden.json",
+                serde_json::json!({"exists": false, "is_dir": false, "size": null, "sha": "abc123"}),
+            ),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(get_request(uri))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+            let body: serde_json::Value = serde_json::from_slice(
+                &axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .expect("body"),
+            )
+            .expect("json body");
+            assert_eq!(body, expected, "{uri}");
+        }
+
+        let response = app
+            .clone()
+            .oneshot(get_request("/exists/files/../../etc/passwd"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(state.files_read.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn exists_only_sees_mounted_paths() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(mirror.join("public")).expect("create dirs");
+        std::fs::create_dir_all(mirror.join("private")).expect("create dirs");
+        std::fs::write(mirror.join("public/a.txt"), "a").expect("write file");
+        std::fs::write(mirror.join("private/secret.txt"), "s").expect("write file");
+        let app = router(AppState::new(
+            AppConfig {
+                serve_mounts: vec![ServeMount {
+                    prefix: "/files".to_string(),
+                    subdir: "public".into(),
+                }],
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+
+        let response = app
+            .clone()
+            .oneshot(get_request("/exists/files/a.txt"))
+            .await
+            .expect("response");
+        let body: serde_json::Value = serde_json::from_slice(
+            &to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("body"),
+        )
+        .expect("json body");
+        assert_eq!(body["exists"], true);
+        for uri in [
+            "/exists/private/secret.txt",
+            "/exists/public/a.txt",
+            "/exists/files/../private/secret.txt",
+        ] {
+            let response = app
+                .clone()
+                .oneshot(get_request(uri))
+                .await
+                .expect("response");
+            assert_ne!(response.status(), StatusCode::OK, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn admin_routes_are_partitioned_from_public_routes() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a.txt"), "one").expect("write file");
+
+        let state = AppState::new(
+            test_config(mirror),
+            Arc::new(RwLock::new(SyncStatus::default())),
+        );
+        let public = public_router(state.clone());
+        let admin = admin_router(state);
+
+        for uri in ["/", "/health", "/files/a.txt"] {
+            let response = public
+                .clone()
+                .oneshot(get_request(uri))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), StatusCode::OK, "public {uri}");
+            let response = admin
+                .clone()
+                .oneshot(get_request(uri))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "admin {uri}");
+        }
+
+        let response = public
+            .oneshot(get_request("/meta"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = admin.oneshot(get_request("/meta")).await.expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn file_requests_wait_out_a_checkout() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a.txt"), "one").expect("write file");
+        let gate = serve_gate(&mirror);
+        let app = router(AppState::new(
+            test_config(mirror.clone()),
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+
+        // Mid-checkout the file is briefly missing; the request must not see that.
+        let checkout = gate.write().await;
+        std::fs::remove_file(mirror.join("a.txt")).expect("remove file");
+        let request = tokio::spawn(app.clone().oneshot(get_request("/files/a.txt")));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!request.is_finished());
+        std::fs::write(mirror.join("a.txt"), "two").expect("rewrite file");
+        drop(checkout);
+        let response = request.await.expect("join").expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("read body");
+        assert_eq!(&body[..], b"two");
+
+        let app = router(AppState::new(
+            AppConfig {
+                serve_gate_timeout_ms: 20,
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+        let _checkout = gate.write().await;
+        let response = app
+            .clone()
+            .oneshot(get_request("/files/a.txt"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+        let response = app.oneshot(get_request("/health")).await.expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn slow_handlers_time_out_with_504() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a.txt"), "one").expect("write file");
+        let app = router(AppState::new(
+            AppConfig {
+                request_timeout_seconds: Some(1),
+                ..test_config(mirror.clone())
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+
+        // A file request stuck behind a long checkout is a slow handler.
+        let gate = serve_gate(&mirror);
+        let checkout = gate.write().await;
+        let response = app
+            .clone()
+            .oneshot(get_request("/files/a.txt"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        let body: serde_json::Value = serde_json::from_slice(
+            &to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("read body"),
+        )
+        .expect("json body");
+        assert_eq!(body["code"], "request_timeout");
+        drop(checkout);
+
+        let response = app
+            .oneshot(get_request("/files/a.txt"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn disabled_routes_answer_not_found() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a.txt"), "one").expect("write file");
+        let app = router(AppState::new(
+            AppConfig {
+                disabled_routes: vec!["/metrics".to_string(), "/exists".to_string()],
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+
+        for (uri, status) in [
+            ("/metrics", StatusCode::NOT_FOUND),
+            ("/exists/files/a.txt", StatusCode::NOT_FOUND),
+            ("/files/a.txt", StatusCode::OK),
+            ("/health", StatusCode::OK),
+            ("/version", StatusCode::OK),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(get_request(uri))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), status, "{uri}");
+        }
+
+        let response = app.oneshot(get_request("/")).await.expect("response");
+        let body: serde_json::Value = serde_json::from_slice(
+            &to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("read body"),
+        )
+        .expect("json body");
+        let endpoints = body["endpoints"].as_array().expect("endpoints");
+        assert!(!endpoints.contains(&serde_json::json!("/metrics")));
+        assert!(!endpoints.contains(&serde_json::json!("/exists/*path")));
+    }
+
+    #[tokio::test]
+    async fn maintenance_mode_answers_serving_routes_with_503() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a.txt"), "one").expect("write file");
+        let app = router(AppState::new(
+            AppConfig {
+                maintenance_mode: true,
+                admin_token: Some("t0ken".to_string()),
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+        let json = |response: axum::response::Response| async move {
+            serde_json::from_slice::<serde_json::Value>(
+                &to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .expect("read body"),
+            )
+            .expect("json body")
+        };
+        let set_maintenance = |enabled: bool| {
+            Request::builder()
+                .method(axum::http::Method::POST)
+                .uri("/admin/maintenance")
+                .header(header::AUTHORIZATION, "Bearer t0ken")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(axum::body::Body::from(format!("{{\"enabled\":{enabled}}}")))
+                .expect("request")
+        };
+
+        for uri in ["/files/a.txt", "/files/missing.txt", "/snapshot.tar.gz"] {
+            let response = app
+                .clone()
+                .oneshot(get_request(uri))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE, "{uri}");
+            assert_eq!(response.headers()[header::RETRY_AFTER], "30", "{uri}");
+            assert_eq!(json(response).await["code"], "maintenance", "{uri}");
+        }
+        let health = app
+            .clone()
+            .oneshot(get_request("/health"))
+            .await
+            .expect("response");
+        assert_eq!(health.status(), StatusCode::OK);
+        assert_eq!(json(health).await["status"], "maintenance");
+        let meta = app
+            .clone()
+            .oneshot(get_request("/meta"))
+            .await
+            .expect("response");
+        assert_eq!(meta.status(), StatusCode::OK);
+
+        let response = app
+            .clone()
+            .oneshot(set_maintenance(false))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json(response).await["maintenance"], false);
+        let file = app
+            .clone()
+            .oneshot(get_request("/files/a.txt"))
+            .await
+            .expect("response");
+        assert_eq!(file.status(), StatusCode::OK);
+        let health = app
+            .clone()
+            .oneshot(get_request("/health"))
+            .await
+            .expect("response");
+        assert_eq!(json(health).await["status"], "ok");
+
+        app.clone()
+            .oneshot(set_maintenance(true))
+            .await
+            .expect("response");
+        let status = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/maintenance")
+                    .header(header::AUTHORIZATION, "Bearer t0ken")
+                    .body(axum::body::Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response");
+        assert_eq!(json(status).await["maintenance"], true);
+        let file = app
+            .clone()
+            .oneshot(get_request("/files/a.txt"))
+            .await
+            .expect("response");
+        assert_eq!(file.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let options = app
+            .oneshot(
+                Request::builder()
+                    .method(axum::http::Method::OPTIONS)
+                    .uri("/admin/maintenance")
+                    .body(axum::body::Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response");
+        assert_eq!(options.headers()[header::ALLOW], "GET, HEAD, POST, OPTIONS");
+    }
+
+    #[tokio::test]
+    async fn admin_routes_need_a_token_or_their_own_listener() {
+        let temp = tempdir().expect("temp dir");
+        let state = |admin_token: Option<&str>| {
+            AppState::new(
+                AppConfig {
+                    admin_token: admin_token.map(str::to_string),
+                    ..test_config(temp.path().to_path_buf())
+                },
+                Arc::new(RwLock::new(SyncStatus::default())),
+            )
+        };
+        // Whether the request reached the handler, whatever it answered.
+        let outcome = |app: axum::Router, uri: &'static str, token: Option<&'static str>| async move {
+            let method = if uri == "/meta/logs" {
+                axum::http::Method::GET
+            } else {
+                axum::http::Method::POST
+            };
+            let mut request = Request::builder().method(method).uri(uri);
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+            }
+            let request = request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(axum::body::Body::from("{\"enabled\":false}"))
+                .expect("request");
+            let response = app.oneshot(request).await.expect("response");
+            let status = response.status();
+            let body = to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("read body");
+            if status == StatusCode::UNAUTHORIZED {
+                "unauthorized"
+            } else if String::from_utf8_lossy(&body).contains("no such endpoint") {
+                "unmounted"
+            } else {
+                "handled"
+            }
+        };
+
+        for uri in [
+            "/meta/logs",
+            "/admin/maintenance",
+            "/admin/gc",
+            "/admin/purge-cache",
+        ] {
+            assert_eq!(
+                outcome(router(state(None)), uri, None).await,
+                "unmounted",
+                "{uri}"
+            );
+            assert_eq!(
+                outcome(admin_router(state(None)), uri, None).await,
+                "handled",
+                "{uri}"
+            );
+            let app = router(state(Some("s3cret")));
+            assert_eq!(
+                outcome(app.clone(), uri, None).await,
+                "unauthorized",
+                "{uri}"
+            );
+            assert_eq!(
+                outcome(app.clone(), uri, Some("guess")).await,
+                "unauthorized",
+                "{uri}"
+            );
+            assert_eq!(outcome(app, uri, Some("s3cret")).await, "handled", "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn redirects_json_sends_matched_paths_to_their_targets() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(mirror.join("new")).expect("create repo dir");
+        std::fs::write(mirror.join("new/data.json"), "{}").expect("write file");
+        std::fs::write(mirror.join("kept.json"), "kept").expect("write file");
+        std::fs::write(
+            mirror.join("redirects.json"),
+            r#"{"old/*.json": "new/data.json", "escape.json": "../kept.json"}"#,
+        )
+        .expect("write rules");
+        let app = |redirect_status| {
+            router(AppState::new(
+                AppConfig {
+                    redirect_status,
+                    ..test_config(mirror.clone())
+                },
+                Arc::new(RwLock::new(SyncStatus::default())),
+            ))
+        };
+
+        let response = app(301)
+            .oneshot(get_request("/files/old/a.json?v=1"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "/files/new/data.json?v=1"
+        );
+
+        let response = app(302)
+            .oneshot(get_request("/files/old/b.json"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(response.headers()[header::LOCATION], "/files/new/data.json");
+
+        let response = app(301)
+            .oneshot(get_request("/files/kept.json"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("read body");
+        assert_eq!(&body[..], b"kept");
+
+        let response = app(301)
+            .oneshot(get_request("/files/escape.json"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn health_check_command_decides_readiness() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        let script = temp.path().join("check.sh");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\nif [ -f marker ]; then echo marker present; else echo no marker; exit 1; fi\n",
+        )
+        .expect("write script");
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))
+            .expect("make script executable");
+        let synced = || {
+            Arc::new(RwLock::new(SyncStatus {
+                last_success_at: Some(chrono::Utc::now()),
+                ..SyncStatus::default()
+            }))
+        };
+        let readyz = |app: axum::Router| async move {
+            let response = app.oneshot(get_request("/readyz")).await.expect("response");
+            let status = response.status();
+            let body: serde_json::Value = serde_json::from_slice(
+                &to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .expect("read body"),
+            )
+            .expect("json body");
+            (status, body)
+        };
+
+        let app = router(AppState::new(
+            AppConfig {
+                health_check_command: Some(script.display().to_string()),
+                ..test_config(mirror.clone())
+            },
+            synced(),
+        ));
+        let (status, body) = readyz(app.clone()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["ready"], false);
+        assert_eq!(body["check_output"], "no marker");
+
+        // Probes within `HEALTH_CHECK_CACHE` reuse the last result.
+        std::fs::write(mirror.join("marker"), "").expect("write marker");
+        let (status, body) = readyz(app.clone()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["check_output"], "no marker");
+        tokio::time::sleep(HEALTH_CHECK_CACHE).await;
+        let (status, body) = readyz(app).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["check_output"], "marker present");
+
+        let chatty = router(AppState::new(
+            AppConfig {
+                health_check_command: Some("yes".to_string()),
+                ..test_config(mirror.clone())
+            },
+            synced(),
+        ));
+        let (status, body) = readyz(chatty).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            body["check_output"].as_str().expect("output").len(),
+            MAX_HEALTH_CHECK_OUTPUT
+        );
+
+        let slow = router(AppState::new(
+            AppConfig {
+                health_check_command: Some("sleep 5".to_string()),
+                health_check_timeout_ms: 50,
+                ..test_config(mirror.clone())
+            },
+            synced(),
+        ));
+        let (status, body) = readyz(slow).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(
+            body["check_output"]
+                .as_str()
+                .expect("output")
+                .contains("timed out")
+        );
+
+        // Sync readiness comes first; the command doesn't run before it.
+        let unsynced = router(AppState::new(
+            AppConfig {
+                health_check_command: Some(script.display().to_string()),
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+        let (status, body) = readyz(unsynced).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.get("check_output").is_none());
+    }
+
+    #[tokio::test]
+    async fn stats_counts_requests_as_json() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a.txt"), "one").expect("write file");
+        let app = router(AppState::new(
+            test_config(mirror),
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+
+        let stats = || async {
+            let response = app
+                .clone()
+                .oneshot(get_request("/stats"))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), StatusCode::OK);
+            serde_json::from_slice::<serde_json::Value>(
+                &to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .expect("read body"),
+            )
+            .expect("json body")
+        };
+        for expected in 1..=2 {
+            app.clone()
+                .oneshot(get_request("/files/a.txt"))
+                .await
+                .expect("response");
+            let body = stats().await;
+            let files = &body["requests"]["files"];
+            assert_eq!(files["count"], expected);
+            let buckets = files["duration_buckets"].as_array().expect("buckets");
+            assert_eq!(buckets.last().expect("+Inf bucket")["le"], "+Inf");
+            assert_eq!(buckets.last().expect("+Inf bucket")["count"], expected);
+            // Each earlier /stats request is counted once it has finished.
+            assert_eq!(body["requests"]["meta"]["count"], expected - 1);
+            assert_eq!(body["sha_changes"], 0);
+            assert!(body["seconds_since_last_change"].is_null());
+        }
+    }
+
+    #[tokio::test]
+    async fn metrics_exposes_latency_histograms_by_route_class() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(mirror.join("docs")).expect("create dirs");
+        std::fs::write(mirror.join("a.txt"), "one").expect("write file");
+        let app = router(AppState::new(
+            AppConfig {
+                dir_request_mode: DirRequestMode::List,
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+
+        for uri in ["/files/a.txt", "/files/docs", "/health", "/readyz"] {
+            app.clone()
+                .oneshot(get_request(uri))
+                .await
+                .expect("response");
+        }
+        let response = app
+            .oneshot(get_request("/metrics"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(
+            response.headers()[header::CONTENT_TYPE]
+                .to_str()
+                .expect("content type")
+                .starts_with("application/openmetrics-text")
+        );
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("read body");
+        let text = String::from_utf8(body.to_vec()).expect("utf-8 body");
+
+        let name = "repo_sync_request_duration_seconds";
+        assert!(text.starts_with(&format!("# TYPE {name} histogram\n")));
+        for (route, count) in [("files", 1), ("list", 1), ("health", 2)] {
+            assert!(
+                text.contains(&format!(
+                    "{name}_bucket{{route=\"{route}\",le=\"+Inf\"}} {count}\n"
+                )),
+                "{route}: {text}"
+            );
+            assert!(text.contains(&format!("{name}_sum{{route=\"{route}\"}} ")));
+            assert!(text.contains(&format!("{name}_count{{route=\"{route}\"}} {count}\n")));
+        }
+        assert!(!text.contains("a.txt"));
+        assert!(text.ends_with("# EOF\n"));
+    }
+
+    #[tokio::test]
+    async fn content_type_overrides_take_precedence_over_mime_guess() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("events.NDJson"), "{}\n{}\n").expect("write ndjson");
+        std::fs::write(mirror.join("data.json"), "{}").expect("write json");
+        let app = router(AppState::new(
+            AppConfig {
+                content_type_overrides: std::collections::BTreeMap::from([(
+                    "ndjson".to_string(),
+                    "application/x-ndjson".to_string(),
+                )]),
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+
+        for (uri, expected) in [
+            ("/files/events.NDJson", "application/x-ndjson"),
+            ("/files/data.json", "application/json"),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(get_request(uri))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+            assert_eq!(response.headers()[header::CONTENT_TYPE], expected, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn content_disposition_only_when_download_requested() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a\"b.json"), "{}").expect("write file");
+
+        let state = AppState::new(
+            test_config(mirror),
+            Arc::new(RwLock::new(SyncStatus::default())),
+        );
+        let app = router(state);
+
+        let inline = app
+            .clone()
+            .oneshot(get_request("/files/a%22b.json"))
+            .await
+            .expect("response");
+        assert_eq!(inline.status(), StatusCode::OK);
+        assert!(inline.headers().get(header::CONTENT_DISPOSITION).is_none());
+
+        let download = app
+            .oneshot(get_request("/files/a%22b.json?download=1"))
+            .await
+            .expect("response");
+        assert_eq!(download.status(), StatusCode::OK);
+        assert_eq!(
+            download
+                .headers()
+                .get(header::CONTENT_DISPOSITION)
+                .and_then(|v| v.to_str().ok()),
+            Some("attachment; filename=\"a_b.json\"")
+        );
+    }
+
+    #[test]
+    fn sanitize_filename_strips_header_unsafe_characters() {
+        assert_eq!(super::sanitize_filename("ok-name.txt"), "ok-name.txt");
+        assert_eq!(super::sanitize_filename("a\r\nb\\c\"é"), "a__b_c__");
+
+        let long = format!("{}.json", "x".repeat(300));
+        let clamped = super::sanitize_filename(&long);
+        assert_eq!(clamped.len(), super::MAX_DOWNLOAD_FILENAME_CHARS);
+        assert!(clamped.ends_with("x.json"));
+        let no_short_extension = format!("archive.{}", "y".repeat(300));
+        assert_eq!(
+            super::sanitize_filename(&no_short_extension).len(),
+            super::MAX_DOWNLOAD_FILENAME_CHARS
+        );
+    }
+
+    #[tokio::test]
+    async fn downloads_of_awkward_filenames_get_valid_headers() {
+        let temp = tempdir().expect("temp dir");
+        let control = "tab\there\x01\nname.txt";
+        let long = format!("{}.csv", "n".repeat(250));
+        std::fs::write(temp.path().join(control), "one").expect("write control name");
+        std::fs::write(temp.path().join(&long), "two").expect("write long name");
+        let app = router(AppState::new(
+            test_config(temp.path().to_path_buf()),
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+
+        for (uri, content_type, filename) in [
+            (
+                "/files/tab%09here%01%0Aname.txt?download=1".to_string(),
+                "text/plain",
+                "tab_here__name.txt".to_string(),
+            ),
+            (
+                format!("/files/{long}?download=1"),
+                "text/csv",
+                format!("{}.csv", "n".repeat(super::MAX_DOWNLOAD_FILENAME_CHARS - 4)),
+            ),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(get_request(&uri))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+            assert!(
+                response.headers()[header::CONTENT_TYPE]
+                    .to_str()
+                    .expect("ascii")
+                    .starts_with(content_type)
+            );
+            assert_eq!(
+                response.headers()[header::CONTENT_DISPOSITION],
+                format!("attachment; filename=\"{filename}\"").as_str()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn index_reports_status_and_mounted_endpoints() {
+        let temp = tempdir().expect("temp dir");
+        let status = SyncStatus {
+            current_sha: Some("abc123".to_string()),
+            ..SyncStatus::default()
+        };
+        let state = AppState::new(
+            AppConfig {
+                admin_token: Some("t0ken".to_string()),
+                ..test_config(temp.path().to_path_buf())
+            },
+            Arc::new(RwLock::new(status)),
+        );
+
+        let response = router(state.clone())
+            .oneshot(get_request("/"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(
+            &to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("read body"),
+        )
+        .expect("json body");
+        assert_eq!(body["current_sha"], "abc123");
+        assert_eq!(
+            body["endpoints"],
+            serde_json::json!([
+                "/health",
+                "/readyz",
+                "/.well-known/health",
+                "/version",
+                "/robots.txt",
+                "/favicon.ico",
+                "/exists/*path",
+                "/snapshot.tar.gz",
+                "/files/*path",
+                "/meta",
+                "/meta/history",
+                "/meta/drift",
+                "/metrics",
+                "/stats",
+                "/diff",
+                "/meta/logs",
+                "/admin/maintenance",
+                "/admin/gc",
+                "/admin/purge-cache",
+                "/openapi.json"
+            ])
+        );
+
+        let response = public_router(state)
+            .oneshot(get_request("/"))
+            .await
+            .expect("response");
+        let body: serde_json::Value = serde_json::from_slice(
+            &to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("read body"),
+        )
+        .expect("json body");
+        assert_eq!(
+            body["endpoints"],
+            serde_json::json!([
+                "/health",
+                "/readyz",
+                "/.well-known/health",
+                "/version",
+                "/robots.txt",
+                "/favicon.ico",
+                "/exists/*path",
+                "/snapshot.tar.gz",
+                "/files/*path",
+                "/openapi.json"
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn well_known_health_follows_health_json_format() {
+        let temp = tempdir().expect("temp dir");
+        let status = Arc::new(RwLock::new(SyncStatus::default()));
+        let state = AppState::new(test_config(temp.path().to_path_buf()), status.clone());
+        let check = || async {
+            let response = router(state.clone())
+                .oneshot(get_request("/.well-known/health"))
+                .await
+                .expect("response");
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                "application/health+json"
+            );
+            let code = response.status();
+            let body: serde_json::Value = serde_json::from_slice(
+                &to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .expect("read body"),
+            )
+            .expect("json body");
+            (code, body)
+        };
+
+        let (code, body) = check().await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "fail");
+        assert_eq!(body["checks"]["git:sync"][0]["status"], "fail");
+        assert!(body["checks"]["git:sync"][0]["observedValue"].is_null());
+        assert_eq!(body["checks"]["disk:space"][0]["status"], "pass");
+        assert_eq!(body["checks"]["disk:space"][0]["observedUnit"], "bytes");
+        assert!(body["checks"]["disk:space"][0]["observedValue"].is_u64());
+        assert!(body["checks"]["disk:space"][0]["time"].is_string());
+
+        {
+            let mut write = status.write().await;
+            write.current_sha = Some("abc123".to_string());
+            write.last_success_at = Some(chrono::Utc::now());
+            write.last_attempt_at = write.last_success_at;
+        }
+        let (code, body) = check().await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body["status"], "pass");
+        assert_eq!(body["checks"]["git:sync"][0]["observedUnit"], "s");
+        assert!(body["checks"]["git:sync"][0]["observedValue"].is_u64());
+        assert!(body["checks"]["git:sync"][0]["time"].is_string());
+
+        {
+            let mut write = status.write().await;
+            write.consecutive_failures = 1;
+            write.last_error = Some("fetch failed".to_string());
+        }
+        let (code, body) = check().await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body["status"], "warn");
+        assert_eq!(body["checks"]["git:sync"][0]["output"], "fetch failed");
+    }
+
+    #[tokio::test]
+    async fn openapi_documents_mounted_routes_and_options_lists_methods() {
+        let temp = tempdir().expect("temp dir");
+        let state = AppState::new(
+            AppConfig {
+                disabled_routes: vec!["/diff".to_string()],
+                ..test_config(temp.path().to_path_buf())
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        );
+
+        let response = router(state.clone())
+            .oneshot(get_request("/openapi.json"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        let doc: serde_json::Value = serde_json::from_slice(
+            &to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("read body"),
+        )
+        .expect("json body");
+        assert!(doc["openapi"].as_str().expect("version").starts_with("3."));
+        for path in ["/health", "/meta", "/files/{path}", "/exists/{path}"] {
+            assert!(doc["paths"][path]["get"].is_object(), "{path}");
+        }
+        assert!(doc["paths"]["/diff"].is_null());
+        assert_eq!(
+            doc["paths"]["/files/{path}"]["get"]["responses"]["404"]["content"]["application/json"]
+                ["schema"]["$ref"],
+            "#/components/schemas/Error"
+        );
+        for schema in ["Health", "Meta", "Error"] {
+            assert!(doc["components"]["schemas"][schema].is_object(), "{schema}");
+        }
+
+        let response = public_router(state.clone())
+            .oneshot(get_request("/openapi.json"))
+            .await
+            .expect("response");
+        let doc: serde_json::Value = serde_json::from_slice(
+            &to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("read body"),
+        )
+        .expect("json body");
+        assert!(doc["paths"]["/meta"].is_null());
+
+        let response = router(state)
+            .oneshot(
+                Request::builder()
+                    .method(axum::http::Method::OPTIONS)
+                    .uri("/files/a.txt")
+                    .body(axum::body::Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()[header::ALLOW], "GET, HEAD, OPTIONS");
+    }
+
+    #[tokio::test]
+    async fn serves_empty_regular_file() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("empty.txt"), "").expect("write file");
+
+        let state = AppState::new(
+            test_config(mirror),
+            Arc::new(RwLock::new(SyncStatus::default())),
+        );
+        let response = router(state)
+            .oneshot(get_request("/files/empty.txt"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok()),
+            Some("0")
+        );
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .expect("etag");
+        assert_eq!(
+            etag,
+            "\"e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855\""
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn rejects_fifo_under_mirror() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        let status = std::process::Command::new("mkfifo")
+            .arg(mirror.join("pipe"))
+            .status()
+            .expect("run mkfifo");
+        assert!(status.success());
+
+        let state = AppState::new(
+            test_config(mirror),
+            Arc::new(RwLock::new(SyncStatus::default())),
+        );
+        let response = router(state)
+            .oneshot(get_request("/files/pipe"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn weak_etag_tracks_mtime_and_is_stable() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        let file = mirror.join("a.txt");
+        std::fs::write(&file, "same").expect("write file");
+
+        let state = AppState::new(
+            AppConfig {
+                etag_mode: EtagMode::Weak,
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        );
+        let app = router(state);
+        let etag_of = |response: &axum::response::Response| {
+            response
+                .headers()
+                .get(header::ETAG)
+                .and_then(|v| v.to_str().ok())
+                .expect("etag")
+                .to_string()
+        };
 
         let first = app
             .clone()
-            .oneshot(
-                Request::builder()
-                    .uri("/files/a.txt")
-                    .body(axum::body::Body::empty())
-                    .expect("request"),
-            )
+            .oneshot(get_request("/files/a.txt"))
+            .await
+            .expect("response");
+        let first_etag = etag_of(&first);
+        assert!(first_etag.starts_with("W/\"4-"));
+
+        let second = app
+            .clone()
+            .oneshot(get_request("/files/a.txt"))
+            .await
+            .expect("response");
+        assert_eq!(etag_of(&second), first_etag);
+
+        let not_modified = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/files/a.txt")
+                    .header(header::IF_NONE_MATCH, &first_etag)
+                    .body(axum::body::Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response");
+        assert_eq!(not_modified.status(), StatusCode::NOT_MODIFIED);
+
+        std::fs::File::options()
+            .write(true)
+            .open(&file)
+            .expect("open file")
+            .set_modified(std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000))
+            .expect("set mtime");
+        let touched = app
+            .oneshot(get_request("/files/a.txt"))
+            .await
+            .expect("response");
+        assert_ne!(etag_of(&touched), first_etag);
+    }
+
+    #[tokio::test]
+    async fn mounts_serve_isolated_subdirectories() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(mirror.join("public")).expect("create public dir");
+        std::fs::create_dir_all(mirror.join("schemas")).expect("create schemas dir");
+        std::fs::write(mirror.join("public/a.txt"), "public").expect("write public file");
+        std::fs::write(mirror.join("schemas/s.json"), "{}").expect("write schema file");
+        std::fs::write(mirror.join("secret.txt"), "secret").expect("write root file");
+
+        let state = AppState::new(
+            AppConfig {
+                serve_mounts: vec![
+                    ServeMount {
+                        prefix: "/files".to_string(),
+                        subdir: "public".into(),
+                    },
+                    ServeMount {
+                        prefix: "/schemas".to_string(),
+                        subdir: "schemas".into(),
+                    },
+                ],
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        );
+        let app = router(state);
+
+        let status_of = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                app.oneshot(get_request(uri))
+                    .await
+                    .expect("response")
+                    .status()
+            }
+        };
+
+        assert_eq!(status_of("/files/a.txt").await, StatusCode::OK);
+        assert_eq!(status_of("/schemas/s.json").await, StatusCode::OK);
+        assert_eq!(status_of("/files/s.json").await, StatusCode::NOT_FOUND);
+        assert_eq!(status_of("/schemas/a.txt").await, StatusCode::NOT_FOUND);
+        assert_eq!(
+            status_of("/schemas/../public/a.txt").await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status_of("/files/%2E%2E/secret.txt").await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn overlay_layers_serve_first_match_and_merge_listings() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(mirror.join("site/docs")).expect("create site dir");
+        std::fs::create_dir_all(mirror.join("base/docs")).expect("create base dir");
+        std::fs::write(mirror.join("site/docs/shared.txt"), "site").expect("write site file");
+        std::fs::write(mirror.join("base/docs/shared.txt"), "base").expect("write base file");
+        std::fs::write(mirror.join("base/docs/only-base.txt"), "base").expect("write base file");
+        std::fs::write(mirror.join("site/docs/only-site.txt"), "site").expect("write site file");
+        std::fs::write(mirror.join("secret.txt"), "secret").expect("write root file");
+
+        let app = router(AppState::new(
+            AppConfig {
+                serve_overlay: vec!["site".into(), "base".into()],
+                dir_request_mode: DirRequestMode::List,
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+        let body_of = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(get_request(uri)).await.expect("response");
+                assert_eq!(response.status(), StatusCode::OK, "{uri}");
+                axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .expect("body")
+            }
+        };
+
+        assert_eq!(&body_of("/files/docs/shared.txt").await[..], b"site");
+        assert_eq!(&body_of("/files/docs/only-base.txt").await[..], b"base");
+        let listing: serde_json::Value =
+            serde_json::from_slice(&body_of("/files/docs").await).expect("json body");
+        let entries = listing.as_array().expect("entries");
+        let names: Vec<&str> = entries
+            .iter()
+            .map(|e| e["name"].as_str().expect("name"))
+            .collect();
+        assert_eq!(names, ["only-base.txt", "only-site.txt", "shared.txt"]);
+
+        let response = app
+            .oneshot(get_request("/files/../secret.txt"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn serves_schema_index_next_to_a_schemas_mount() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(mirror.join("schemas")).expect("create schemas dir");
+        std::fs::write(
+            mirror.join("schemas/user.json"),
+            r#"{"$id": "https://example.com/user"}"#,
+        )
+        .expect("write schema");
+        std::fs::write(
+            mirror.join("schemas/pet.yml"),
+            "$id: https://example.com/pet
+",
+        )
+        .expect("write schema");
+
+        let app = router(AppState::new(
+            AppConfig {
+                serve_mounts: vec![ServeMount {
+                    prefix: "/schemas".to_string(),
+                    subdir: "".into(),
+                }],
+                schema_index: true,
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+
+        let response = app
+            .clone()
+            .oneshot(get_request("/schemas/index.json"))
             .await
             .expect("response");
-        assert_eq!(first.status(), StatusCode::OK);
-        let first_body = to_bytes(first.into_body(), usize::MAX)
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("body"),
+        )
+        .expect("json body");
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "https://example.com/pet": "schemas/pet.yml",
+                "https://example.com/user": "schemas/user.json",
+            })
+        );
+
+        let response = app
+            .oneshot(get_request("/schemas/schemas/user.json"))
             .await
-            .expect("read body");
-        assert_eq!(first_body.as_ref(), b"one");
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+    }
 
-        tokio::time::sleep(Duration::from_millis(10)).await;
-        std::fs::write(temp.path().join("repo").join("a.txt"), "two").expect("rewrite file");
+    #[tokio::test]
+    async fn error_responses_carry_machine_readable_codes() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(mirror.join("dir")).expect("create repo dir");
+        std::fs::write(mirror.join("big.txt"), "0123456789").expect("write file");
 
-        let second = app
-            .oneshot(
-                Request::builder()
-                    .uri("/files/a.txt")
-                    .body(axum::body::Body::empty())
-                    .expect("request"),
+        let state = AppState::new(
+            AppConfig {
+                max_path_length: 32,
+                max_file_size_bytes: 4,
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        );
+        let app = router(state);
+
+        let long_path = format!("/files/{}", "a".repeat(64));
+        let cases = [
+            (
+                long_path.as_str(),
+                StatusCode::URI_TOO_LONG,
+                "path_too_long",
+            ),
+            (
+                "/files/../etc/passwd",
+                StatusCode::FORBIDDEN,
+                "invalid_path",
+            ),
+            ("/files/missing.txt", StatusCode::NOT_FOUND, "not_found"),
+            ("/files/dir", StatusCode::NOT_FOUND, "not_found"),
+            ("/files/big.txt", StatusCode::PAYLOAD_TOO_LARGE, "too_large"),
+        ];
+        for (uri, status, code) in cases {
+            let response = app
+                .clone()
+                .oneshot(get_request(uri))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), status, "{uri}");
+            let body: serde_json::Value = serde_json::from_slice(
+                &to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .expect("read body"),
             )
+            .expect("json body");
+            assert_eq!(body["code"], code, "{uri}");
+            assert!(body["error"].is_string(), "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn hide_forbidden_answers_traversal_like_a_missing_file() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+
+        for hide_forbidden in [false, true] {
+            let app = router(AppState::new(
+                AppConfig {
+                    hide_forbidden,
+                    ..test_config(mirror.clone())
+                },
+                Arc::new(RwLock::new(SyncStatus::default())),
+            ));
+            let mut answers = Vec::new();
+            for uri in ["/files/../etc/passwd", "/files/missing.txt"] {
+                let response = app
+                    .clone()
+                    .oneshot(get_request(uri))
+                    .await
+                    .expect("response");
+                let status = response.status();
+                let body = to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .expect("read body");
+                answers.push((status, body));
+            }
+            let (traversal, missing) = (&answers[0], &answers[1]);
+            assert_eq!(missing.0, StatusCode::NOT_FOUND);
+            if hide_forbidden {
+                assert_eq!(traversal, missing);
+            } else {
+                assert_eq!(traversal.0, StatusCode::FORBIDDEN);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn max_file_size_overrides_apply_per_extension() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("model.bin"), "0123456789").expect("write file");
+        std::fs::write(mirror.join("data.json"), "0123456789").expect("write file");
+        std::fs::write(mirror.join("notes.txt"), "0123456789").expect("write file");
+        let app = router(AppState::new(
+            AppConfig {
+                max_file_size_bytes: 8,
+                max_file_size_overrides: std::collections::BTreeMap::from([
+                    ("bin".to_string(), 64),
+                    ("json".to_string(), 4),
+                ]),
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+
+        let response = app
+            .clone()
+            .oneshot(get_request("/files/model.bin"))
             .await
             .expect("response");
-        assert_eq!(second.status(), StatusCode::OK);
-        let second_body = to_bytes(second.into_body(), usize::MAX)
+        assert_eq!(response.status(), StatusCode::OK);
+
+        for (uri, limit) in [("/files/data.json", 4), ("/files/notes.txt", 8)] {
+            let response = app
+                .clone()
+                .oneshot(get_request(uri))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE, "{uri}");
+            let body: serde_json::Value = serde_json::from_slice(
+                &to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .expect("read body"),
+            )
+            .expect("json body");
+            assert_eq!(body["code"], "too_large");
+            assert_eq!(
+                body["error"],
+                format!("file exceeds max size of {limit} bytes"),
+                "{uri}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_request_bodies_over_limit() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a.txt"), "hello").expect("write file");
+        let app = router(AppState::new(
+            AppConfig {
+                max_request_body_bytes: 16,
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+        let with_body = |len: usize| {
+            Request::builder()
+                .uri("/files/a.txt")
+                .header(header::CONTENT_LENGTH, len)
+                .body(axum::body::Body::from(vec![b'x'; len]))
+                .expect("request")
+        };
+
+        let response = app
+            .clone()
+            .oneshot(with_body(1024 * 1024))
             .await
-            .expect("read body");
-        assert_eq!(second_body.as_ref(), b"two");
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = serde_json::from_slice(
+            &to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("read body"),
+        )
+        .expect("json body");
+        assert_eq!(body["code"], "body_too_large");
+
+        let response = app.oneshot(with_body(8)).await.expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
     }
 
     #[tokio::test]
-    async fn responds_not_modified_when_etag_matches() {
+    async fn error_bodies_are_html_for_browsers_and_json_otherwise() {
         let temp = tempdir().expect("temp dir");
         let mirror = temp.path().join("repo");
         std::fs::create_dir_all(&mirror).expect("create repo dir");
-        std::fs::write(mirror.join("a.txt"), "same").expect("write file");
+        let app = router(AppState::new(
+            test_config(mirror),
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+        let request = |uri: &str, accept: Option<&str>| {
+            let mut request = Request::builder().uri(uri);
+            if let Some(accept) = accept {
+                request = request.header(header::ACCEPT, accept);
+            }
+            request.body(axum::body::Body::empty()).expect("request")
+        };
+        let browser = "text/html,application/xhtml+xml,*/*;q=0.8";
+
+        for uri in ["/files/missing.txt", "/no/such/route"] {
+            let response = app
+                .clone()
+                .oneshot(request(uri, Some(browser)))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                "text/html; charset=utf-8"
+            );
+            let body = to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("read body");
+            let body = String::from_utf8(body.to_vec()).expect("utf-8 body");
+            assert!(body.contains("<h1>404 Not Found</h1>"), "{body}");
+        }
+
+        for accept in [None, Some("*/*"), Some("application/json, text/html")] {
+            let response = app
+                .clone()
+                .oneshot(request("/files/missing.txt", accept))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            let body: serde_json::Value = serde_json::from_slice(
+                &to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .expect("read body"),
+            )
+            .expect("json body");
+            assert_eq!(body["code"], "not_found", "{accept:?}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unreadable_file_reports_read_failed() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        let file = mirror.join("locked.txt");
+        std::fs::write(&file, "locked").expect("write file");
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o000))
+            .expect("chmod file");
+        if std::fs::read(&file).is_ok() {
+            // Running with CAP_DAC_OVERRIDE (e.g. as root); permissions can't block reads.
+            return;
+        }
 
-        let state = AppState {
-            config: AppConfig {
-                git_repo_url: "https://github.com/org/repo.git".to_string(),
-                git_branch: "main".to_string(),
-                git_sync_interval_seconds: 30,
-                git_token: None,
-                mirror_dir: mirror,
-                serve_subdir: None,
-                http_bind_addr: "127.0.0.1:0".to_string(),
-                max_path_length: 512,
-                max_file_size_bytes: 1024 * 1024,
+        let state = AppState::new(
+            test_config(mirror),
+            Arc::new(RwLock::new(SyncStatus::default())),
+        );
+        let response = router(state)
+            .oneshot(get_request("/files/locked.txt"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body: serde_json::Value = serde_json::from_slice(
+            &to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("read body"),
+        )
+        .expect("json body");
+        assert_eq!(body["code"], "read_failed");
+    }
+
+    #[tokio::test]
+    async fn repo_sync_ignore_hides_matching_files() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(mirror.join("notes")).expect("create notes dir");
+        std::fs::write(mirror.join(".repo-sync-ignore"), "notes/\n").expect("write ignore");
+        std::fs::write(mirror.join("notes/internal.md"), "secret").expect("write ignored");
+        std::fs::write(mirror.join("public.json"), "{}").expect("write sibling");
+
+        let state = AppState::new(
+            test_config(mirror),
+            Arc::new(RwLock::new(SyncStatus::default())),
+        );
+        let app = router(state);
+
+        for (uri, status) in [
+            ("/files/notes/internal.md", StatusCode::NOT_FOUND),
+            ("/files/.repo-sync-ignore", StatusCode::NOT_FOUND),
+            ("/files/public.json", StatusCode::OK),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(get_request(uri))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), status, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn allowed_cidrs_reject_other_clients_except_probes_on_health() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a.txt"), "one").expect("write file");
+
+        let app = router(AppState::new(
+            AppConfig {
+                allowed_cidrs: vec!["10.0.0.0/8".parse().expect("net")],
+                health_allowed_cidrs: vec!["192.0.2.0/24".parse().expect("net")],
+                trust_forwarded: true,
+                ..test_config(mirror)
             },
-            status: Arc::new(RwLock::new(SyncStatus::default())),
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+        let status = |uri: &'static str, ip: &'static str| {
+            let app = app.clone();
+            async move {
+                app.oneshot(
+                    Request::builder()
+                        .uri(uri)
+                        .header("x-forwarded-for", ip)
+                        .body(axum::body::Body::empty())
+                        .expect("request"),
+                )
+                .await
+                .expect("response")
+                .status()
+            }
         };
+
+        assert_eq!(status("/files/a.txt", "10.1.2.3").await, StatusCode::OK);
+        assert_eq!(
+            status("/files/a.txt", "::ffff:10.1.2.3").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status("/files/a.txt", "198.51.100.1").await,
+            StatusCode::FORBIDDEN
+        );
+        // Only the entry the proxy appended counts, not one the client sent.
+        assert_eq!(
+            status("/files/a.txt", "10.1.2.3, 198.51.100.1").await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status("/files/a.txt", "198.51.100.1, 10.1.2.3").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status("/files/a.txt", "192.0.2.10").await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(status("/health", "192.0.2.10").await, StatusCode::OK);
+        assert_eq!(
+            status("/health", "198.51.100.1").await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn rate_limit_returns_429_after_burst_except_for_health() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a.txt"), "one").expect("write file");
+
+        let state = AppState::new(
+            AppConfig {
+                rate_limit_rps: Some(0.01),
+                rate_limit_burst: 2,
+                trust_forwarded: true,
+                ..test_config(mirror)
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        );
         let app = router(state);
+        let from_client = |uri: &str, ip: &str| {
+            Request::builder()
+                .uri(uri)
+                .header("x-forwarded-for", ip)
+                .body(axum::body::Body::empty())
+                .expect("request")
+        };
 
-        let first = app
+        for _ in 0..2 {
+            let response = app
+                .clone()
+                .oneshot(from_client("/files/a.txt", "198.51.100.1"))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let limited = app
             .clone()
-            .oneshot(
-                Request::builder()
-                    .uri("/files/a.txt")
-                    .body(axum::body::Body::empty())
-                    .expect("request"),
-            )
+            .oneshot(from_client("/files/a.txt", "198.51.100.1"))
             .await
             .expect("response");
-        assert_eq!(first.status(), StatusCode::OK);
-        let etag = first
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(limited.headers().get(header::RETRY_AFTER).is_some());
+
+        // Prepending a made-up address doesn't buy a fresh bucket.
+        let spoofed = app
+            .clone()
+            .oneshot(from_client("/files/a.txt", "203.0.113.50, 198.51.100.1"))
+            .await
+            .expect("response");
+        assert_eq!(spoofed.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let health = app
+            .clone()
+            .oneshot(from_client("/health", "198.51.100.1"))
+            .await
+            .expect("response");
+        assert_eq!(health.status(), StatusCode::OK);
+
+        let other_client = app
+            .clone()
+            .oneshot(from_client("/files/a.txt", "198.51.100.2"))
+            .await
+            .expect("response");
+        assert_eq!(other_client.status(), StatusCode::OK);
+
+        // Without a peer address or X-Forwarded-For, requests share a bucket.
+        for expected in [
+            StatusCode::OK,
+            StatusCode::OK,
+            StatusCode::TOO_MANY_REQUESTS,
+        ] {
+            let response = app
+                .clone()
+                .oneshot(get_request("/files/a.txt"))
+                .await
+                .expect("response");
+            assert_eq!(response.status(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_ranges_and_honors_if_range() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a.txt"), "0123456789").expect("write file");
+
+        let state = AppState::new(
+            test_config(mirror.clone()),
+            Arc::new(RwLock::new(SyncStatus::default())),
+        );
+        let app = router(state);
+        let ranged = |if_range: Option<&str>| {
+            let mut builder = Request::builder()
+                .uri("/files/a.txt")
+                .header(header::RANGE, "bytes=2-5");
+            if let Some(if_range) = if_range {
+                builder = builder.header(header::IF_RANGE, if_range);
+            }
+            builder.body(axum::body::Body::empty()).expect("request")
+        };
+
+        let partial = app.clone().oneshot(ranged(None)).await.expect("response");
+        assert_eq!(partial.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            partial
+                .headers()
+                .get(header::CONTENT_RANGE)
+                .and_then(|v| v.to_str().ok()),
+            Some("bytes 2-5/10")
+        );
+        let etag = partial
             .headers()
             .get(header::ETAG)
             .and_then(|v| v.to_str().ok())
             .expect("etag")
             .to_string();
+        let body = to_bytes(partial.into_body(), usize::MAX)
+            .await
+            .expect("read body");
+        assert_eq!(body.as_ref(), b"2345");
 
-        let second = app
-            .oneshot(
-                Request::builder()
-                    .uri("/files/a.txt")
-                    .header(header::IF_NONE_MATCH, etag)
-                    .body(axum::body::Body::empty())
-                    .expect("request"),
-            )
+        let matching = app
+            .clone()
+            .oneshot(ranged(Some(&etag)))
             .await
             .expect("response");
-        assert_eq!(second.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(matching.status(), StatusCode::PARTIAL_CONTENT);
+
+        std::fs::write(mirror.join("a.txt"), "abcdefghijkl").expect("rewrite file");
+        let stale = app.oneshot(ranged(Some(&etag))).await.expect("response");
+        assert_eq!(stale.status(), StatusCode::OK);
+        assert!(stale.headers().get(header::CONTENT_RANGE).is_none());
+        let body = to_bytes(stale.into_body(), usize::MAX)
+            .await
+            .expect("read body");
+        assert_eq!(body.as_ref(), b"abcdefghijkl");
+    }
+
+    #[tokio::test]
+    async fn mime_sniffing_types_extensionless_files_by_content() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("manifest"), r#"{"version": 1}"#).expect("write json");
+        std::fs::write(mirror.join("blob"), b"\x7fELF\x02\x01\x01\0\0").expect("write blob");
+        let app = |mime_sniffing| {
+            router(AppState::new(
+                AppConfig {
+                    mime_sniffing,
+                    ..test_config(mirror.clone())
+                },
+                Arc::new(RwLock::new(SyncStatus::default())),
+            ))
+        };
+        let content_type = |app: axum::Router, path: &'static str| async move {
+            let response = app.oneshot(get_request(path)).await.expect("response");
+            assert_eq!(response.status(), StatusCode::OK);
+            response.headers()[header::CONTENT_TYPE]
+                .to_str()
+                .expect("ascii")
+                .to_string()
+        };
+
+        assert_eq!(
+            content_type(app(true), "/files/manifest").await,
+            "application/json"
+        );
+        assert_eq!(
+            content_type(app(true), "/files/blob").await,
+            "application/octet-stream"
+        );
+        assert_eq!(
+            content_type(app(false), "/files/manifest").await,
+            "application/octet-stream"
+        );
+    }
+
+    #[tokio::test]
+    async fn purge_cache_drops_cached_digests() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a.txt"), "one").expect("write file");
+        let app = router(AppState::new(
+            AppConfig {
+                server_timing: true,
+                etag_mode: EtagMode::Strong,
+                admin_token: Some("t0ken".to_string()),
+                ..test_config(mirror.clone())
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        ));
+        let hashed = |response: &axum::response::Response| {
+            response.headers()["server-timing"]
+                .to_str()
+                .expect("ascii")
+                .contains("hash;")
+        };
+        let purge = || {
+            Request::builder()
+                .method(axum::http::Method::POST)
+                .uri("/admin/purge-cache")
+                .header(header::AUTHORIZATION, "Bearer t0ken")
+                .body(axum::body::Body::empty())
+                .expect("request")
+        };
+
+        let response = app
+            .clone()
+            .oneshot(get_request("/files/a.txt"))
+            .await
+            .expect("response");
+        assert!(hashed(&response));
+        let response = app
+            .clone()
+            .oneshot(get_request("/files/a.txt"))
+            .await
+            .expect("response");
+        assert!(!hashed(&response), "second request hits the cache");
+
+        let response = app.clone().oneshot(purge()).await.expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(
+            &to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("read body"),
+        )
+        .expect("json body");
+        assert_eq!(body["purged"], 1);
+
+        let response = app
+            .clone()
+            .oneshot(get_request("/files/a.txt"))
+            .await
+            .expect("response");
+        assert!(hashed(&response), "purged digest is computed again");
+        let response = app.oneshot(purge()).await.expect("response");
+        let body: serde_json::Value = serde_json::from_slice(
+            &to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("read body"),
+        )
+        .expect("json body");
+        assert_eq!(body["purged"], 1);
+    }
+
+    #[tokio::test]
+    async fn server_timing_breaks_file_responses_into_phases() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a.txt"), "one").expect("write file");
+        let app = |server_timing| {
+            router(AppState::new(
+                AppConfig {
+                    server_timing,
+                    etag_mode: EtagMode::Strong,
+                    ..test_config(mirror.clone())
+                },
+                Arc::new(RwLock::new(SyncStatus::default())),
+            ))
+        };
+        let phases = |response: &axum::response::Response| -> Vec<(String, f64)> {
+            response.headers()["server-timing"]
+                .to_str()
+                .expect("ascii")
+                .split(", ")
+                .map(|metric| {
+                    let (name, duration) = metric.split_once(";dur=").expect("dur param");
+                    (name.to_string(), duration.parse().expect("milliseconds"))
+                })
+                .collect()
+        };
+
+        let enabled = app(true);
+        let first = enabled
+            .clone()
+            .oneshot(get_request("/files/a.txt"))
+            .await
+            .expect("response");
+        assert_eq!(first.status(), StatusCode::OK);
+        let first = phases(&first);
+        let names: Vec<&str> = first.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["metadata", "read", "hash"]);
+        assert!(first.iter().all(|(_, ms)| *ms >= 0.0));
+
+        // The digest is cached now, so nothing is hashed.
+        let second = enabled
+            .oneshot(get_request("/files/a.txt"))
+            .await
+            .expect("response");
+        let names: Vec<String> = phases(&second).into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["metadata", "read"]);
+
+        let disabled = app(false)
+            .oneshot(get_request("/files/a.txt"))
+            .await
+            .expect("response");
+        assert!(disabled.headers().get("server-timing").is_none());
+    }
+
+    #[tokio::test]
+    async fn range_toggle_controls_partial_responses_and_advertisement() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("a.txt"), "0123456789").expect("write file");
+        let ranged = || {
+            Request::builder()
+                .uri("/files/a.txt")
+                .header(header::RANGE, "bytes=2-5")
+                .body(axum::body::Body::empty())
+                .expect("request")
+        };
+
+        for (enabled, status, accept_ranges, body) in [
+            (true, StatusCode::PARTIAL_CONTENT, "bytes", &b"2345"[..]),
+            (false, StatusCode::OK, "none", &b"0123456789"[..]),
+        ] {
+            let mut config = test_config(mirror.clone());
+            config.range_requests_enabled = enabled;
+            let app = router(AppState::new(
+                config,
+                Arc::new(RwLock::new(SyncStatus::default())),
+            ));
+            let response = app.oneshot(ranged()).await.expect("response");
+            assert_eq!(response.status(), status);
+            assert_eq!(
+                response
+                    .headers()
+                    .get(header::ACCEPT_RANGES)
+                    .and_then(|v| v.to_str().ok()),
+                Some(accept_ranges)
+            );
+            assert_eq!(
+                response.headers().get(header::CONTENT_RANGE).is_some(),
+                enabled
+            );
+            let bytes = to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("read body");
+            assert_eq!(bytes.as_ref(), body);
+        }
+    }
+
+    #[tokio::test]
+    async fn head_uses_metadata_or_cached_digest_without_reading() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("repo");
+        std::fs::create_dir_all(&mirror).expect("create repo dir");
+        std::fs::write(mirror.join("big.bin"), vec![7u8; 512 * 1024]).expect("write file");
+        let head = |uri: &str| {
+            Request::builder()
+                .method("HEAD")
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .expect("request")
+        };
+
+        let weak = AppState::new(
+            AppConfig {
+                etag_mode: EtagMode::Weak,
+                ..test_config(mirror.clone())
+            },
+            Arc::new(RwLock::new(SyncStatus::default())),
+        );
+        let response = router(weak.clone())
+            .oneshot(head("/files/big.bin"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok()),
+            Some("524288")
+        );
+        assert!(response.headers().get(header::LAST_MODIFIED).is_some());
+        assert!(response.headers().get(header::ETAG).is_some());
+        assert_eq!(weak.files_read.load(Ordering::Relaxed), 0);
+
+        let strong = AppState::new(
+            test_config(mirror),
+            Arc::new(RwLock::new(SyncStatus::default())),
+        );
+        let app = router(strong.clone());
+        let first = app
+            .clone()
+            .oneshot(head("/files/big.bin"))
+            .await
+            .expect("response");
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(strong.files_read.load(Ordering::Relaxed), 1);
+        let second = app.oneshot(head("/files/big.bin")).await.expect("response");
+        assert_eq!(second.status(), StatusCode::OK);
+        assert_eq!(
+            second.headers().get(header::ETAG),
+            first.headers().get(header::ETAG)
+        );
+        assert_eq!(strong.files_read.load(Ordering::Relaxed), 1);
     }
 }
@@ -0,0 +1,219 @@
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    io::Write,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+use flate2::{Compression, GzBuilder};
+use git2::{ObjectType, Oid, Repository, TreeWalkMode, TreeWalkResult};
+
+/// Git's mode for an executable blob; everything else is written as 0644.
+const EXECUTABLE_MODE: i32 = 0o100755;
+
+/// Git's mode for a symlink, stored as a blob holding the target.
+const SYMLINK_MODE: i32 = 0o120000;
+
+/// One directory of a snapshot: the merged contents of `layers` (paths in the
+/// repo, earlier layers shadowing later ones) archived under `archive_dir`.
+pub struct SnapshotTree {
+    pub archive_dir: PathBuf,
+    pub layers: Vec<PathBuf>,
+}
+
+/// The blobs of `trees` as committed in one SHA, resolved before anything is
+/// written so a missing repo or commit fails while the response can still
+/// say so. Files are read from the object database rather than the
+/// worktree, so a sync landing midway can't mix two commits.
+pub struct Snapshot {
+    repo: Repository,
+    /// Path in the repo, path in the archive, blob id and git filemode.
+    blobs: Vec<(PathBuf, PathBuf, Oid, i32)>,
+}
+
+impl Snapshot {
+    /// Collects the blobs of `trees` in `sha`. Layers missing from the
+    /// commit are skipped; symlinks and submodules are left out.
+    pub fn open(repo_dir: &Path, sha: Oid, trees: &[SnapshotTree]) -> Result<Self> {
+        let repo = Repository::open(repo_dir)
+            .with_context(|| format!("failed opening repo in {}", repo_dir.display()))?;
+        let root = repo
+            .find_commit(sha)
+            .and_then(|commit| commit.tree())
+            .with_context(|| format!("commit {sha} not found in mirror"))?;
+
+        let mut blobs = Vec::new();
+        for snapshot_tree in trees {
+            let mut seen = HashSet::new();
+            for layer in &snapshot_tree.layers {
+                let tree = if layer.as_os_str().is_empty() {
+                    root.clone()
+                } else {
+                    match root
+                        .get_path(layer)
+                        .ok()
+                        .and_then(|entry| repo.find_tree(entry.id()).ok())
+                    {
+                        Some(tree) => tree,
+                        None => continue,
+                    }
+                };
+                tree.walk(TreeWalkMode::PreOrder, |parent, entry| {
+                    if entry.kind() == Some(ObjectType::Blob) && entry.filemode() != SYMLINK_MODE {
+                        let mut path = PathBuf::from(parent);
+                        path.push(OsStr::from_bytes(entry.name_bytes()));
+                        if seen.insert(path.clone()) {
+                            blobs.push((
+                                layer.join(&path),
+                                snapshot_tree.archive_dir.join(&path),
+                                entry.id(),
+                                entry.filemode(),
+                            ));
+                        }
+                    }
+                    TreeWalkResult::Ok
+                })
+                .context("failed walking tree")?;
+            }
+        }
+        drop(root);
+        Ok(Self { repo, blobs })
+    }
+
+    /// Writes the snapshot as a gzipped tar to `out`. Entries are in tree
+    /// order with fixed owners and `mtime`, and the gzip header carries no
+    /// timestamp, so the same commit always produces the same bytes.
+    /// `include` sees each blob's path in the repo and its size.
+    pub fn write(
+        self,
+        mtime: u64,
+        include: impl Fn(&Path, u64) -> bool,
+        out: impl Write,
+    ) -> Result<()> {
+        let Self { repo, blobs } = self;
+        let odb = repo.odb().context("failed opening object database")?;
+        let mut archive = tar::Builder::new(GzBuilder::new().write(out, Compression::default()));
+        for (path, archive_path, id, filemode) in blobs {
+            let (size, _) = odb
+                .read_header(id)
+                .with_context(|| format!("failed reading {}", path.display()))?;
+            let size = size as u64;
+            if !include(&path, size) {
+                continue;
+            }
+            let blob = repo
+                .find_blob(id)
+                .with_context(|| format!("failed reading {}", path.display()))?;
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(size);
+            header.set_mode(if filemode == EXECUTABLE_MODE {
+                0o755
+            } else {
+                0o644
+            });
+            header.set_mtime(mtime);
+            header.set_uid(0);
+            header.set_gid(0);
+            archive
+                .append_data(&mut header, &archive_path, blob.content())
+                .with_context(|| format!("failed archiving {}", path.display()))?;
+        }
+        archive
+            .into_inner()
+            .and_then(|gzip| gzip.finish())
+            .map_err(|err| anyhow!("failed finishing snapshot: {err}"))?
+            .flush()
+            .context("failed flushing snapshot")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+    use git2::Signature;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn commit_files(repo: &Repository, files: &[(&str, &str)]) -> Oid {
+        let mut index = repo.index().expect("index");
+        for (path, contents) in files {
+            let full = repo.workdir().expect("workdir").join(path);
+            std::fs::create_dir_all(full.parent().expect("parent")).expect("create dir");
+            std::fs::write(&full, contents).expect("write file");
+            index.add_path(Path::new(path)).expect("stage file");
+        }
+        let tree = repo
+            .find_tree(index.write_tree().expect("write tree"))
+            .expect("tree");
+        let signature = Signature::now("test", "test@example.com").expect("signature");
+        repo.commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+            .expect("commit")
+    }
+
+    #[test]
+    fn archives_layers_deterministically_and_filters_entries() {
+        let temp = tempdir().expect("temp dir");
+        let repo = Repository::init(temp.path()).expect("init repo");
+        let sha = commit_files(
+            &repo,
+            &[
+                ("site/public/a.txt", "site a"),
+                ("base/public/a.txt", "a"),
+                ("base/public/nested/b.txt", "bb"),
+                ("base/public/big.bin", "0123456789"),
+                ("base/private.txt", "secret"),
+            ],
+        );
+
+        let snapshot = || {
+            let mut out = Vec::new();
+            Snapshot::open(
+                temp.path(),
+                sha,
+                &[SnapshotTree {
+                    archive_dir: PathBuf::from("files"),
+                    layers: vec![
+                        PathBuf::from("site/public"),
+                        PathBuf::from("missing/public"),
+                        PathBuf::from("base/public"),
+                    ],
+                }],
+            )
+            .and_then(|snapshot| snapshot.write(1, |_, size| size < 10, &mut out))
+            .expect("snapshot");
+            out
+        };
+        let first = snapshot();
+        assert_eq!(first, snapshot());
+
+        let mut tar = Vec::new();
+        GzDecoder::new(first.as_slice())
+            .read_to_end(&mut tar)
+            .expect("gunzip");
+        let mut archive = tar::Archive::new(tar.as_slice());
+        let entries: Vec<(String, String)> = archive
+            .entries()
+            .expect("entries")
+            .map(|entry| {
+                let mut entry = entry.expect("entry");
+                let path = entry.path().expect("path").display().to_string();
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents).expect("read entry");
+                (path, contents)
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("files/a.txt".to_string(), "site a".to_string()),
+                ("files/nested/b.txt".to_string(), "bb".to_string()),
+            ]
+        );
+    }
+}
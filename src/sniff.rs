@@ -0,0 +1,62 @@
+/// How much of a file `MIME_SNIFFING` looks at.
+pub const SNIFF_LEN: usize = 8192;
+
+/// Guesses a content type for a file whose extension says nothing, from its
+/// first bytes. `complete` says whether `prefix` is the whole file. Stays
+/// conservative: `None` (keep `application/octet-stream`) for anything empty,
+/// containing NUL or control bytes, or not UTF-8. JSON is only claimed when
+/// the whole file was read and parses.
+pub fn sniff(prefix: &[u8], complete: bool) -> Option<&'static str> {
+    let prefix = prefix.strip_prefix(b"\xef\xbb\xbf").unwrap_or(prefix);
+    let text = match std::str::from_utf8(prefix) {
+        Ok(text) => text,
+        // A multi-byte character cut off by the sniff window is still text.
+        Err(err) if !complete && err.error_len().is_none() && err.valid_up_to() > 0 => {
+            std::str::from_utf8(&prefix[..err.valid_up_to()]).ok()?
+        }
+        Err(_) => return None,
+    };
+    if text.trim().is_empty()
+        || text
+            .chars()
+            .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c'))
+    {
+        return None;
+    }
+    let looks_like_json = text.trim_start().starts_with(['{', '[']);
+    if complete && looks_like_json && serde_json::from_str::<serde_json::Value>(text).is_ok() {
+        return Some("application/json");
+    }
+    Some("text/plain; charset=utf-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_json_and_text_but_not_binary() {
+        assert_eq!(
+            sniff(br#"{"name": "value"}"#, true),
+            Some("application/json")
+        );
+        assert_eq!(sniff(b"[1, 2", true), Some("text/plain; charset=utf-8"));
+        assert_eq!(
+            sniff(br#"{"truncated": "#, false),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(
+            sniff(b"plain notes\n", true),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(
+            sniff("caf\u{e9}".as_bytes().split_last().unwrap().1, false),
+            Some("text/plain; charset=utf-8")
+        );
+
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0", true), None);
+        assert_eq!(sniff(b"text\0with nul", true), None);
+        assert_eq!(sniff(b"\xff\xfe\xfd", true), None);
+        assert_eq!(sniff(b"", true), None);
+    }
+}
@@ -1,85 +1,779 @@
-use std::{fs, path::Path, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::{CStr, CString, c_int},
+    fmt, fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        Arc, LazyLock, Mutex, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
 
 use anyhow::{Context, Result, anyhow};
+use arc_swap::ArcSwap;
 use chrono::{DateTime, Utc};
 use git2::{
-    AutotagOption, Cred, FetchOptions, RemoteCallbacks, Repository, ResetType, Status,
-    StatusOptions, build::RepoBuilder,
+    AutotagOption, Cred, FetchOptions, ObjectType, RemoteCallbacks, Repository, ResetType, Status,
+    StatusOptions, TreeWalkMode, TreeWalkResult,
+    build::{CheckoutBuilder, RepoBuilder},
 };
-use serde::Serialize;
+use nix::sys::statvfs::statvfs;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use tokio::{
-    sync::RwLock,
-    task,
+    sync::{RwLock, Semaphore},
+    task::{self, JoinHandle},
     time::{Duration, sleep},
 };
-use tracing::{error, info};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 
-use crate::config::AppConfig;
+use crate::{
+    config::{AppConfig, EtagMode, SyncMode},
+    diff::deleted_paths,
+    digest_cache::{DigestCache, PRECOMPUTE_BUDGET},
+    github_app::installation_token,
+    history::{SyncEvent, SyncHistory, SyncOutcomeKind},
+    log_buffer,
+    object_db::{ObjectDbStats, object_db_stats},
+};
 
 #[derive(Debug, Clone, Serialize, Default)]
 pub struct SyncStatus {
     pub current_sha: Option<String>,
     pub previous_sha: Option<String>,
+    /// First line of the synced commit's message, cut to `MAX_COMMIT_SUMMARY_CHARS`.
+    pub commit_summary: Option<String>,
+    pub commit_author: Option<String>,
+    pub commit_time: Option<DateTime<Utc>>,
     pub last_success_at: Option<DateTime<Utc>>,
+    /// When a sync last observed a SHA different from the one it replaced.
+    pub last_change_at: Option<DateTime<Utc>>,
+    /// SHA changes observed since the process started; the first sync only
+    /// counts when it moves off a SHA restored from disk.
+    pub sha_changes: u64,
     pub last_attempt_at: Option<DateTime<Utc>>,
+    /// When the sync loop wakes for the next attempt, jitter included. Passed
+    /// while that sync waits for a `SYNC_CONCURRENCY` slot or runs.
+    pub next_sync_at: Option<DateTime<Utc>>,
     pub last_error: Option<String>,
+    pub last_transfer: Option<TransferStats>,
+    pub last_timings: Option<SyncTimings>,
+    /// Fetch retries the last successful sync needed after transient errors.
+    pub fetch_retries: u32,
+    pub error_kind: Option<SyncErrorKind>,
+    pub remote_tip_sha: Option<String>,
+    pub behind: bool,
+    /// Failed syncs since the last success.
+    pub consecutive_failures: u32,
+    /// Problems found after an otherwise successful sync.
+    pub warnings: Vec<SyncWarning>,
+    /// Size of the mirror's `.git`, measured after each successful sync and gc.
+    pub object_db: Option<ObjectDbStats>,
+    pub last_gc_at: Option<DateTime<Utc>>,
+    /// Files removed by the last sync that changed the SHA.
+    #[serde(skip)]
+    pub deletions: Option<Arc<Deletions>>,
 }
 
-pub async fn sync_loop(config: AppConfig, status: Arc<RwLock<SyncStatus>>) {
-    loop {
-        if let Err(err) = sync_once(&config, status.clone()).await {
-            error!("sync loop error: {err:#}");
+/// Paths a sync removed, kept so requests for them can be told apart from
+/// requests for paths that never existed.
+#[derive(Debug, Default)]
+pub struct Deletions {
+    /// The commit that removed them.
+    pub sha: String,
+    pub at: DateTime<Utc>,
+    /// Relative to the repository root.
+    pub paths: HashSet<PathBuf>,
+}
+
+/// Configuration problem detected after a successful sync; the commit synced
+/// but the service can't serve it as configured.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncWarning {
+    /// `serve_root()` doesn't exist or isn't a directory in the synced tree.
+    ServeRootMissing,
+}
+
+/// Machine-readable classification of the last sync failure.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncErrorKind {
+    BranchMissing,
+    MirrorNotWritable,
+    MirrorLocked,
+    DiskLow,
+    /// The branch was rewritten and `GIT_REJECT_NON_FASTFORWARD` kept the
+    /// mirror on its current commit.
+    NonFastForward,
+    /// The new commit's files exceed `MAX_WORKTREE_BYTES`, so the mirror was
+    /// kept on its current commit.
+    WorktreeQuotaExceeded,
+    /// The remote has no commits yet. Not counted as a failure: there is
+    /// simply nothing to serve until the first push.
+    EmptyBranch,
+    Other,
+}
+
+/// Sync failure with a known cause, carried inside `anyhow::Error` so the
+/// kind survives added context.
+#[derive(Debug)]
+pub struct SyncError {
+    pub kind: SyncErrorKind,
+    pub message: String,
+}
+
+impl SyncError {
+    fn branch_missing(branch: &str) -> Self {
+        Self {
+            kind: SyncErrorKind::BranchMissing,
+            message: format!("branch '{branch}' no longer exists on origin"),
+        }
+    }
+
+    fn empty_branch(branch: &str) -> Self {
+        Self {
+            kind: SyncErrorKind::EmptyBranch,
+            message: format!("branch '{branch}' has no commits yet"),
+        }
+    }
+}
+
+/// Inside the mirror's `.git` directory, where it is neither served nor
+/// removed by `CLEAN_UNTRACKED`, and goes away with the clone it describes.
+const STATE_FILE: &str = ".git/repo-sync-state.json";
+
+/// The SHAs worth keeping across restarts.
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedState {
+    current_sha: String,
+    previous_sha: Option<String>,
+    #[serde(default)]
+    last_change_at: Option<DateTime<Utc>>,
+}
+
+impl SyncStatus {
+    /// Status seeded with the SHAs the previous process persisted for
+    /// `mirror_dir`, so the first sync after a restart still sets
+    /// `previous_sha`. A missing or unreadable state file starts empty.
+    pub fn restore(mirror_dir: &Path) -> Self {
+        let path = mirror_dir.join(STATE_FILE);
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(err) => {
+                warn!("failed reading {}: {err}", path.display());
+                return Self::default();
+            }
+        };
+        match serde_json::from_slice::<PersistedState>(&bytes) {
+            Ok(state)
+                if is_object_id(&state.current_sha)
+                    && state.previous_sha.as_deref().is_none_or(is_object_id) =>
+            {
+                Self {
+                    current_sha: Some(state.current_sha),
+                    previous_sha: state.previous_sha,
+                    last_change_at: state.last_change_at,
+                    ..Self::default()
+                }
+            }
+            Ok(_) => {
+                warn!("ignoring {}: not a valid SHA", path.display());
+                Self::default()
+            }
+            Err(err) => {
+                warn!("ignoring corrupt {}: {err}", path.display());
+                Self::default()
+            }
+        }
+    }
+}
+
+/// A full hex SHA-1 or SHA-256 object id.
+pub fn is_object_id(sha: &str) -> bool {
+    matches!(sha.len(), 40 | 64) && sha.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Writes the SHAs for `SyncStatus::restore`, via a rename so a crash
+/// mid-write never leaves a truncated file.
+fn save_state(mirror_dir: &Path, status: &SyncStatus) -> Result<()> {
+    let Some(current_sha) = status.current_sha.clone() else {
+        return Ok(());
+    };
+    let path = mirror_dir.join(STATE_FILE);
+    let tmp = path.with_extension("json.tmp");
+    let state = PersistedState {
+        current_sha,
+        previous_sha: status.previous_sha.clone(),
+        last_change_at: status.last_change_at,
+    };
+    fs::write(&tmp, serde_json::to_vec(&state)?)
+        .with_context(|| format!("failed writing {}", tmp.display()))?;
+    fs::rename(&tmp, &path).with_context(|| format!("failed replacing {}", path.display()))
+}
+
+/// Verifies that git can write into `mirror_dir`: its `.git` directory once
+/// cloned, otherwise the nearest existing ancestor the clone would be created in.
+/// Branch mirrors not cloned yet share that ancestor, so each probe gets its
+/// own name rather than racing another sync to create and remove one file.
+pub fn check_mirror_writable(mirror_dir: &Path) -> Result<()> {
+    let git_dir = mirror_dir.join(".git");
+    let probe_dir = if git_dir.is_dir() {
+        git_dir.as_path()
+    } else {
+        mirror_dir
+            .ancestors()
+            .find(|dir| dir.is_dir())
+            .unwrap_or(mirror_dir)
+    };
+    let probe = probe_dir.join(format!(
+        ".repo-sync-write-probe-{}-{}",
+        std::process::id(),
+        PROBE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::File::create(&probe)
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(|err| {
+            SyncError {
+                kind: SyncErrorKind::MirrorNotWritable,
+                message: format!("mirror_dir is not writable: {}: {err}", probe_dir.display()),
+            }
+            .into()
+        })
+}
+
+/// Prefix of the clone staging directories in `work_dir`. Only these are
+/// removed as leftovers, in case `WORK_DIR` holds anything else.
+const STAGING_PREFIX: &str = "clone-";
+
+static STAGING_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+static PROBE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Creates `work_dir`, removes staging directories left by an interrupted
+/// clone, and checks that it is writable and on the same filesystem as
+/// `mirror_dir`'s parent, since staged clones are moved into place by rename.
+pub fn prepare_work_dir(work_dir: &Path, mirror_dir: &Path) -> Result<()> {
+    fs::create_dir_all(work_dir)
+        .with_context(|| format!("failed creating work_dir {}", work_dir.display()))?;
+    let entries = fs::read_dir(work_dir)
+        .with_context(|| format!("failed reading work_dir {}", work_dir.display()))?;
+    for entry in entries.flatten() {
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(STAGING_PREFIX)
+        {
+            continue;
+        }
+        let path = entry.path();
+        info!("removing leftover clone staging dir {}", path.display());
+        fs::remove_dir_all(&path).with_context(|| format!("failed removing {}", path.display()))?;
+    }
+    let probe = work_dir.join(".repo-sync-write-probe");
+    fs::File::create(&probe)
+        .and_then(|_| fs::remove_file(&probe))
+        .with_context(|| format!("work_dir is not writable: {}", work_dir.display()))?;
+
+    let mirror_parent = mirror_dir
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mirror_parent = nearest_existing_dir(mirror_parent);
+    let device = |dir: &Path| {
+        fs::metadata(dir)
+            .map(|metadata| metadata.dev())
+            .with_context(|| format!("failed reading {}", dir.display()))
+    };
+    if device(work_dir)? != device(mirror_parent)? {
+        return Err(anyhow!(
+            "work_dir {} is on a different filesystem than {}; set WORK_DIR next to MIRROR_DIR",
+            work_dir.display(),
+            mirror_parent.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Refuses to sync when the filesystem holding `mirror_dir` has less than
+/// `min_free_bytes` available, since a fetch that runs out of space midway can
+/// leave the mirror broken. `available` reports free bytes for a directory.
+pub fn check_free_space(
+    mirror_dir: &Path,
+    min_free_bytes: u64,
+    available: impl Fn(&Path) -> std::io::Result<u64>,
+) -> Result<()> {
+    if min_free_bytes == 0 {
+        return Ok(());
+    }
+    let dir = nearest_existing_dir(mirror_dir);
+    let free = available(dir)
+        .with_context(|| format!("failed reading free space of {}", dir.display()))?;
+    if free < min_free_bytes {
+        return Err(SyncError {
+            kind: SyncErrorKind::DiskLow,
+            message: format!(
+                "only {free} bytes free on {}, need {min_free_bytes}; skipping sync",
+                dir.display()
+            ),
         }
-        sleep(Duration::from_secs(config.git_sync_interval_seconds)).await;
+        .into());
+    }
+    Ok(())
+}
+
+/// Bytes available to unprivileged users on the filesystem containing `dir`.
+/// `dir` or its closest existing ancestor, so free space can be read before
+/// the first clone creates the mirror.
+pub fn nearest_existing_dir(dir: &Path) -> &Path {
+    dir.ancestors().find(|dir| dir.is_dir()).unwrap_or(dir)
+}
+
+pub fn available_bytes(dir: &Path) -> std::io::Result<u64> {
+    let stat = statvfs(dir)?;
+    Ok(stat.blocks_available().saturating_mul(stat.fragment_size()))
+}
+
+/// Advisory lock on a mirror directory, released when dropped (including
+/// during unwinding) or when the process exits.
+pub struct MirrorLock {
+    file: fs::File,
+}
+
+impl Drop for MirrorLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
     }
 }
 
-pub async fn sync_once(config: &AppConfig, status: Arc<RwLock<SyncStatus>>) -> Result<()> {
+/// Lock file guarding `mirror_dir`. It lives next to the mirror rather than
+/// inside it so cleaning untracked files can't remove it.
+pub fn mirror_lock_path(mirror_dir: &Path) -> PathBuf {
+    let mut path = mirror_dir.as_os_str().to_owned();
+    path.push(".repo-sync.lock");
+    PathBuf::from(path)
+}
+
+static SERVE_GATES: LazyLock<Mutex<HashMap<PathBuf, Arc<RwLock<()>>>>> =
+    LazyLock::new(Mutex::default);
+
+/// In-process lock for `mirror_dir` that a sync holds for writing while it
+/// resets and cleans the worktree and file requests hold for reading, so a
+/// request waits out the checkout instead of seeing a half-updated tree.
+pub fn serve_gate(mirror_dir: &Path) -> Arc<RwLock<()>> {
+    SERVE_GATES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entry(mirror_dir.to_path_buf())
+        .or_default()
+        .clone()
+}
+
+/// Takes the lock on `mirror_dir` without blocking, failing with
+/// `SyncErrorKind::MirrorLocked` if another instance holds it.
+pub fn lock_mirror(mirror_dir: &Path) -> Result<MirrorLock> {
+    let path = mirror_lock_path(mirror_dir);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed creating parent dir {}", parent.display()))?;
+    }
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("failed opening lock file {}", path.display()))?;
+    match file.try_lock() {
+        Ok(()) => Ok(MirrorLock { file }),
+        Err(fs::TryLockError::WouldBlock) => Err(SyncError {
+            kind: SyncErrorKind::MirrorLocked,
+            message: "mirror locked by another instance".to_string(),
+        }
+        .into()),
+        Err(fs::TryLockError::Error(err)) => {
+            Err(anyhow::Error::new(err).context(format!("failed locking {}", path.display())))
+        }
+    }
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for SyncError {}
+
+/// The `SyncErrorKind` carried anywhere in `err`'s chain, else `Other`.
+pub fn error_kind(err: &anyhow::Error) -> SyncErrorKind {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<SyncError>())
+        .map_or(SyncErrorKind::Other, |err| err.kind)
+}
+
+/// Object transfer reported by git for the most recent clone or fetch.
+#[derive(Debug, Clone, Copy, Serialize, Default, PartialEq, Eq)]
+pub struct TransferStats {
+    pub received_objects: usize,
+    pub total_objects: usize,
+    pub received_bytes: usize,
+}
+
+/// Wall-clock split of the most recent successful sync: network (`fetch_ms`,
+/// including the initial clone) versus worktree updates (`checkout_ms`).
+#[derive(Debug, Clone, Copy, Serialize, Default, PartialEq, Eq)]
+pub struct SyncTimings {
+    pub fetch_ms: u64,
+    pub checkout_ms: u64,
+}
+
+struct SyncOutcome {
+    sha: String,
+    /// Files removed since the previously checked-out commit.
+    deleted: Vec<PathBuf>,
+    commit: Option<CommitInfo>,
+    transfer: TransferStats,
+    remote_tip: String,
+    timings: SyncTimings,
+    fetch_retries: u32,
+    object_db: Option<ObjectDbStats>,
+}
+
+/// Human context for the synced commit, surfaced in `/meta`.
+struct CommitInfo {
+    summary: String,
+    author: String,
+    time: Option<DateTime<Utc>>,
+}
+
+/// Longer summaries are cut so a runaway first line can't bloat `/meta`.
+const MAX_COMMIT_SUMMARY_CHARS: usize = 200;
+
+/// Spawns a task syncing `branch` (the primary branch when `None`) until
+/// `shutdown` is cancelled, re-reading the live config each cycle so reloaded
+/// settings apply from the next sync. Cancellation is only observed between
+/// syncs, so awaiting the returned handle lets an in-flight sync finish.
+/// Each sync first takes one of `sync_slots`, shared by every branch's loop,
+/// so at most `SYNC_CONCURRENCY` run at once.
+pub fn sync_loop(
+    live_config: Arc<ArcSwap<AppConfig>>,
+    branch: Option<String>,
+    status: Arc<RwLock<SyncStatus>>,
+    history: Arc<SyncHistory>,
+    digests: Arc<DigestCache>,
+    sync_slots: Arc<Semaphore>,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut precomputed_sha = None;
+        while !shutdown.is_cancelled() {
+            let config = match &branch {
+                Some(branch) => live_config.load().for_branch(branch),
+                None => AppConfig::clone(&live_config.load()),
+            };
+            let slot = tokio::select! {
+                () = shutdown.cancelled() => break,
+                slot = sync_slots.acquire() => slot,
+            };
+            let result = sync_once(&config, status.clone(), &history).await;
+            drop(slot);
+            match result {
+                Ok(()) => {
+                    let sha = status.read().await.current_sha.clone();
+                    if config.precompute_etags && sha != precomputed_sha {
+                        precompute_etags(&config, digests.clone()).await;
+                        precomputed_sha = sha;
+                    }
+                }
+                Err(err) => error!("sync loop error: {err:#}"),
+            }
+            let delay = sync_delay(
+                config.git_sync_interval_seconds,
+                config.git_sync_jitter_seconds,
+            );
+            status.write().await.next_sync_at = chrono::Duration::from_std(delay)
+                .ok()
+                .and_then(|delay| Utc::now().checked_add_signed(delay));
+            tokio::select! {
+                () = shutdown.cancelled() => {}
+                () = sleep(delay) => {}
+            }
+        }
+    })
+}
+
+/// Hashes the served tree into `digests` (see `DigestCache::precompute`)
+/// so first requests for its files don't have to.
+pub async fn precompute_etags(config: &AppConfig, digests: Arc<DigestCache>) {
+    if config.etag_mode != EtagMode::Strong {
+        return;
+    }
+    let root = config.serve_root();
+    let algorithm = config.hash_algorithm;
+    let max_bytes = config.etag_precompute_max_bytes;
+    let started = Instant::now();
+    match task::spawn_blocking(move || {
+        digests.precompute(&root, algorithm, max_bytes, PRECOMPUTE_BUDGET)
+    })
+    .await
+    {
+        Ok(hashed) => info!(
+            "precomputed {hashed} etags in {}ms",
+            started.elapsed().as_millis()
+        ),
+        Err(err) => warn!("etag precompute task failed: {err}"),
+    }
+}
+
+/// Shortest wait between syncs, however much jitter is configured.
+const MIN_SYNC_DELAY: Duration = Duration::from_secs(1);
+
+/// `interval_seconds` moved by a random amount within `±jitter_seconds`,
+/// never below `MIN_SYNC_DELAY`.
+fn sync_delay(interval_seconds: u64, jitter_seconds: u64) -> Duration {
+    let interval = Duration::from_secs(interval_seconds);
+    if jitter_seconds == 0 {
+        return interval;
+    }
+    let jitter = Duration::from_secs(jitter_seconds);
+    let low = interval.saturating_sub(jitter).max(MIN_SYNC_DELAY);
+    let high = interval.saturating_add(jitter).max(low);
+    rand::thread_rng().gen_range(low..=high)
+}
+
+pub async fn sync_once(
+    config: &AppConfig,
+    status: Arc<RwLock<SyncStatus>>,
+    history: &SyncHistory,
+) -> Result<()> {
+    let started_at = Utc::now();
     {
         let mut write = status.write().await;
-        write.last_attempt_at = Some(Utc::now());
+        write.last_attempt_at = Some(started_at);
+        if config.sync_mode == SyncMode::None {
+            // Nothing to fetch: the directory is served as-is, so the only
+            // thing to refresh is whether it is still there.
+            write.last_success_at = Some(started_at);
+            write.warnings = serve_root_warnings(config);
+            return Ok(());
+        }
     }
 
     let result = ensure_repo_synced(config).await;
+    let duration_ms = (Utc::now() - started_at).num_milliseconds().max(0) as u64;
+    history.record(match &result {
+        Ok(outcome) => SyncEvent {
+            branch: config.git_branch.clone(),
+            at: started_at,
+            outcome: SyncOutcomeKind::Success,
+            sha: Some(outcome.sha.clone()),
+            duration_ms,
+            error: None,
+        },
+        Err(err) => SyncEvent {
+            branch: config.git_branch.clone(),
+            at: started_at,
+            outcome: match error_kind(err) {
+                SyncErrorKind::EmptyBranch => SyncOutcomeKind::Empty,
+                _ => SyncOutcomeKind::Failure,
+            },
+            sha: None,
+            duration_ms,
+            error: Some(err.to_string()),
+        },
+    });
     match result {
-        Ok(sha) => {
+        Ok(SyncOutcome {
+            sha,
+            deleted,
+            commit,
+            transfer,
+            remote_tip,
+            timings,
+            fetch_retries,
+            object_db,
+        }) => {
             let mut write = status.write().await;
             if write.current_sha.as_deref() != Some(sha.as_str()) {
+                if write.current_sha.is_some() {
+                    write.sha_changes += 1;
+                }
+                write.last_change_at = Some(Utc::now());
                 write.previous_sha = write.current_sha.clone();
+                write.deletions = Some(Arc::new(Deletions {
+                    sha: sha.clone(),
+                    at: Utc::now(),
+                    paths: deleted.into_iter().collect(),
+                }));
             }
             write.current_sha = Some(sha.clone());
+            match commit {
+                Some(commit) => {
+                    write.commit_summary = Some(commit.summary);
+                    write.commit_author = Some(commit.author);
+                    write.commit_time = commit.time;
+                }
+                None => {
+                    write.commit_summary = None;
+                    write.commit_author = None;
+                    write.commit_time = None;
+                }
+            }
             write.last_success_at = Some(Utc::now());
             write.last_error = None;
-            info!("sync successful: {}", sha);
+            write.error_kind = None;
+            write.consecutive_failures = 0;
+            write.last_transfer = Some(transfer);
+            write.last_timings = Some(timings);
+            write.fetch_retries = fetch_retries;
+            write.object_db = object_db;
+            write.behind = remote_tip != sha;
+            write.remote_tip_sha = Some(remote_tip);
+            write.warnings = serve_root_warnings(config);
+            if let Err(err) = save_state(&config.mirror_dir, &write) {
+                warn!("failed persisting sync state: {err:#}");
+            }
+            info!(
+                "sync successful: {} (received {} objects, {} bytes)",
+                sha, transfer.received_objects, transfer.received_bytes
+            );
+            Ok(())
+        }
+        Err(err) if error_kind(&err) == SyncErrorKind::EmptyBranch => {
+            let mut write = status.write().await;
+            if write.error_kind != Some(SyncErrorKind::EmptyBranch) {
+                info!("{err}; waiting for the first commit");
+            }
+            write.last_error = Some(err.to_string());
+            write.error_kind = Some(SyncErrorKind::EmptyBranch);
             Ok(())
         }
         Err(err) => {
             let mut write = status.write().await;
             write.last_error = Some(err.to_string());
+            write.error_kind = Some(error_kind(&err));
+            write.consecutive_failures = write.consecutive_failures.saturating_add(1);
             Err(err)
         }
     }
 }
 
-async fn ensure_repo_synced(config: &AppConfig) -> Result<String> {
+fn serve_root_warnings(config: &AppConfig) -> Vec<SyncWarning> {
+    let serve_root = config.serve_root();
+    if serve_root.is_dir() {
+        return Vec::new();
+    }
+    warn!("serve root {} is missing", serve_root.display());
+    vec![SyncWarning::ServeRootMissing]
+}
+
+/// Re-reads the remote branch tip from the ref advertisement alone (no object
+/// download) and updates `remote_tip_sha`/`behind`. Failures are logged and
+/// leave the sync status untouched.
+pub async fn refresh_remote_tip(config: &AppConfig, status: Arc<RwLock<SyncStatus>>) {
+    let lookup_config = config.clone();
+    let result = task::spawn_blocking(move || remote_branch_tip(&lookup_config))
+        .await
+        .context("remote tip task join error")
+        .and_then(|result| result);
+    match result {
+        Ok(tip) => {
+            let mut write = status.write().await;
+            write.behind = write.current_sha.as_deref() != Some(tip.as_str());
+            write.remote_tip_sha = Some(tip);
+        }
+        Err(err) => warn!("remote tip check failed: {err:#}"),
+    }
+}
+
+/// Lists the remote's refs without a local repository and returns the OID of
+/// the configured branch.
+pub fn remote_branch_tip(config: &AppConfig) -> Result<String> {
+    if is_empty_local_repository(&config.git_repo_url) {
+        return Err(SyncError::empty_branch(&config.git_branch).into());
+    }
+    let mut remote = git2::Remote::create_detached(config.git_repo_url.as_str())
+        .context("git remote create failed")?;
+    let callbacks = build_remote_callbacks(config, Arc::new(Mutex::new(TransferStats::default())));
+    let connection = remote
+        .connect_auth(git2::Direction::Fetch, Some(callbacks), None)
+        .context("git ls-remote connect failed")?;
+    advertised_branch_tip(
+        connection.list().context("git ls-remote failed")?,
+        &config.git_branch,
+    )
+}
+
+/// Whether `url` names a repository on this machine that has no refs yet.
+/// libgit2's local transport advertises such a repository as a null ref list,
+/// which git2's `Remote::list` can't take, so it has to be caught up front.
+fn is_empty_local_repository(url: &str) -> bool {
+    let path = match url.strip_prefix("file://") {
+        Some(path) => path,
+        None if url.contains("://") => return false,
+        None => url,
+    };
+    Repository::open(path)
+        .and_then(|repo| Ok(repo.references()?.next().is_none()))
+        .unwrap_or(false)
+}
+
+fn advertised_branch_tip(heads: &[git2::RemoteHead<'_>], branch: &str) -> Result<String> {
+    let remote_ref = format!("refs/heads/{branch}");
+    if let Some(head) = heads.iter().find(|head| head.name() == remote_ref) {
+        return Ok(head.oid().to_string());
+    }
+    // A freshly initialized repository advertises no branches at all.
+    if heads
+        .iter()
+        .any(|head| head.name().starts_with("refs/heads/"))
+    {
+        Err(SyncError::branch_missing(branch).into())
+    } else {
+        Err(SyncError::empty_branch(branch).into())
+    }
+}
+
+async fn ensure_repo_synced(config: &AppConfig) -> Result<SyncOutcome> {
     let config = config.clone();
     task::spawn_blocking(move || ensure_repo_synced_blocking(&config))
         .await
         .context("sync task join error")?
 }
 
-fn ensure_repo_synced_blocking(config: &AppConfig) -> Result<String> {
+fn ensure_repo_synced_blocking(config: &AppConfig) -> Result<SyncOutcome> {
     let repo_url = config.git_repo_url.as_str();
     let mirror_dir = &config.mirror_dir;
     let branch = config.git_branch.as_str();
+    let transfer = Arc::new(Mutex::new(TransferStats::default()));
 
+    // A read-only volume otherwise surfaces as an opaque permission error from
+    // deep inside libgit2.
+    check_mirror_writable(mirror_dir)?;
+    check_free_space(mirror_dir, config.min_free_disk_bytes, available_bytes)?;
+    // Two instances resetting one worktree concurrently corrupt it.
+    let _lock = lock_mirror(mirror_dir)?;
+
+    let fetch_started = Instant::now();
     if !mirror_dir.join(".git").exists() {
         if let Some(parent) = mirror_dir.parent() {
             fs::create_dir_all(parent)
                 .with_context(|| format!("failed creating parent dir {}", parent.display()))?;
         }
         info!("cloning repository into {}", mirror_dir.display());
-        clone_repository(repo_url, mirror_dir, branch, config.git_token.as_deref())?;
+        clone_into_mirror(repo_url, mirror_dir, branch, config, transfer.clone()).map_err(
+            // libgit2 only reports a missing ref; the advertisement says
+            // whether the branch is gone or the repository is still empty.
+            |err| match remote_branch_tip(config) {
+                Err(probe) if error_kind(&probe) != SyncErrorKind::Other => probe,
+                _ => err,
+            },
+        )?;
     } else if !Path::new(mirror_dir).exists() {
         return Err(anyhow!(
             "mirror dir does not exist: {}",
@@ -90,28 +784,187 @@ fn ensure_repo_synced_blocking(config: &AppConfig) -> Result<String> {
     let repo = Repository::open(mirror_dir)
         .with_context(|| format!("failed opening repo in {}", mirror_dir.display()))?;
     set_origin_url(&repo, repo_url)?;
-    fetch_branch(&repo, branch, config.git_token.as_deref())?;
-    hard_reset_to_origin_branch(&repo, branch)?;
-    clean_untracked(&repo)?;
+    apply_git_config(&repo, &config.git_config_set)?;
+    let (remote_tip, fetch_retries) =
+        with_retries(config.git_fetch_retries, FETCH_RETRY_DELAY, || {
+            fetch_branch(
+                &repo,
+                branch,
+                config.git_mirror_clone,
+                config,
+                transfer.clone(),
+            )
+        });
+    let remote_tip = remote_tip?;
+    let fetch_ms = elapsed_ms(fetch_started);
+
+    if config.git_reject_non_fastforward {
+        check_fast_forward(&repo, branch, config.git_accept_rewrite_to.as_deref())?;
+    }
+    check_worktree_size(&repo, branch, config.max_worktree_bytes)?;
+
+    let previous_head = repo.head().ok().and_then(|head| head.target());
+    let checkout_started = Instant::now();
+    {
+        let gate = serve_gate(mirror_dir);
+        let _serving_paused = gate.blocking_write();
+        hard_reset_to_origin_branch(&repo, branch)?;
+        if config.clean_untracked {
+            clean_untracked(&repo)?;
+        }
+    }
+    let timings = SyncTimings {
+        fetch_ms,
+        checkout_ms: elapsed_ms(checkout_started),
+    };
 
     let head = repo.head().context("failed reading HEAD")?;
     let oid = head
         .target()
-        .ok_or_else(|| anyhow!("HEAD has no target commit"))?;
+        .ok_or_else(|| SyncError::empty_branch(branch))?;
     let sha = oid.to_string();
     if sha.is_empty() {
         return Err(anyhow!("empty commit sha after sync"));
     }
-    Ok(sha)
+    let deleted = match previous_head {
+        Some(previous) if previous != oid => {
+            deleted_paths(&repo, previous, oid, MAX_TRACKED_DELETIONS)
+                .inspect_err(|err| warn!("failed listing deleted paths: {err:#}"))
+                .unwrap_or_default()
+        }
+        _ => Vec::new(),
+    };
+    let commit = read_commit_info(&repo, oid)
+        .inspect_err(|err| warn!("failed reading commit {sha}: {err:#}"))
+        .ok();
+    let transfer = *transfer
+        .lock()
+        .map_err(|_| anyhow!("transfer stats lock poisoned"))?;
+    let object_db = object_db_stats(mirror_dir)
+        .inspect_err(|err| warn!("failed measuring object database: {err:#}"))
+        .ok();
+    Ok(SyncOutcome {
+        sha,
+        deleted,
+        commit,
+        transfer,
+        remote_tip,
+        timings,
+        fetch_retries,
+        object_db,
+    })
+}
+
+/// Most deleted paths remembered per sync; a bigger removal answers 404 for
+/// the rest.
+const MAX_TRACKED_DELETIONS: usize = 10_000;
+
+/// Pause between fetch attempts; long enough for a DNS or connection blip to
+/// clear, short enough not to stretch the sync noticeably.
+const FETCH_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Runs `op` until it succeeds, fails with a non-transient error, or has been
+/// retried `retries` times, sleeping `delay` between attempts. Returns the
+/// last result with the number of retries made.
+pub fn with_retries<T>(
+    retries: u32,
+    delay: Duration,
+    mut op: impl FnMut() -> Result<T>,
+) -> (Result<T>, u32) {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(err) if attempt < retries && is_transient(&err) => {
+                attempt += 1;
+                warn!("transient git error, retrying ({attempt}/{retries}): {err:#}");
+                std::thread::sleep(delay);
+            }
+            result => return (result, attempt),
+        }
+    }
+}
+
+/// Network (DNS, connect, timeout) and TLS failures from libgit2 that a
+/// retry may clear. Authentication, certificate and filesystem errors and
+/// missing repositories or refs never do.
+fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<git2::Error>())
+        .is_some_and(|err| {
+            matches!(err.class(), git2::ErrorClass::Net | git2::ErrorClass::Ssl)
+                && !matches!(
+                    err.code(),
+                    git2::ErrorCode::Auth
+                        | git2::ErrorCode::NotFound
+                        | git2::ErrorCode::Certificate
+                )
+        })
+}
+
+fn read_commit_info(repo: &Repository, oid: git2::Oid) -> Result<CommitInfo> {
+    let commit = repo.find_commit(oid).context("failed finding commit")?;
+    let summary = String::from_utf8_lossy(commit.summary_bytes().unwrap_or_default());
+    let summary = match summary.char_indices().nth(MAX_COMMIT_SUMMARY_CHARS) {
+        Some((end, _)) => format!("{}…", &summary[..end]),
+        None => summary.into_owned(),
+    };
+    let author = String::from_utf8_lossy(commit.author().name_bytes()).into_owned();
+    Ok(CommitInfo {
+        summary,
+        author,
+        time: DateTime::from_timestamp(commit.time().seconds(), 0),
+    })
+}
+
+fn elapsed_ms(started: Instant) -> u64 {
+    started.elapsed().as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+/// Clones into a fresh directory under `work_dir` and renames it into place,
+/// so an interrupted clone never leaves a partial mirror to be served. An
+/// existing `mirror_dir`, such as an empty volume mount, is cloned into
+/// directly since it can't be swapped.
+fn clone_into_mirror(
+    repo_url: &str,
+    mirror_dir: &Path,
+    branch: &str,
+    config: &AppConfig,
+    transfer: Arc<Mutex<TransferStats>>,
+) -> Result<()> {
+    if mirror_dir.exists() {
+        return clone_repository(repo_url, mirror_dir, branch, config, transfer);
+    }
+    let work_dir = &config.work_dir;
+    fs::create_dir_all(work_dir)
+        .with_context(|| format!("failed creating work_dir {}", work_dir.display()))?;
+    let staging = work_dir.join(format!(
+        "{STAGING_PREFIX}{}-{}",
+        std::process::id(),
+        STAGING_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let cloned = clone_repository(repo_url, &staging, branch, config, transfer).and_then(|()| {
+        fs::rename(&staging, mirror_dir).with_context(|| {
+            format!(
+                "failed moving {} to {}",
+                staging.display(),
+                mirror_dir.display()
+            )
+        })
+    });
+    if cloned.is_err() {
+        let _ = fs::remove_dir_all(&staging);
+    }
+    cloned
 }
 
 fn clone_repository(
     repo_url: &str,
     mirror_dir: &Path,
     branch: &str,
-    git_token: Option<&str>,
+    config: &AppConfig,
+    transfer: Arc<Mutex<TransferStats>>,
 ) -> Result<()> {
-    let callbacks = build_remote_callbacks(git_token);
+    let callbacks = build_remote_callbacks(config, transfer);
     let mut fetch_options = FetchOptions::new();
     fetch_options.remote_callbacks(callbacks);
     fetch_options.prune(git2::FetchPrune::On);
@@ -120,6 +973,12 @@ fn clone_repository(
     let mut builder = RepoBuilder::new();
     builder.branch(branch);
     builder.fetch_options(fetch_options);
+    // Leave the checkout to the reset that follows, after the size check.
+    if config.max_worktree_bytes > 0 {
+        let mut checkout = CheckoutBuilder::new();
+        checkout.dry_run();
+        builder.with_checkout(checkout);
+    }
     builder
         .clone(repo_url, mirror_dir)
         .with_context(|| format!("git clone failed for {}", mirror_dir.display()))?;
@@ -139,8 +998,39 @@ fn set_origin_url(repo: &Repository, repo_url: &str) -> Result<()> {
     Ok(())
 }
 
-fn fetch_branch(repo: &Repository, branch: &str, git_token: Option<&str>) -> Result<()> {
-    let callbacks = build_remote_callbacks(git_token);
+/// Writes `GIT_CONFIG_SET` into the repository's own config. Entries removed
+/// from the setting stay behind until the mirror is re-cloned.
+fn apply_git_config(repo: &Repository, entries: &BTreeMap<String, String>) -> Result<()> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let mut git_config = repo
+        .config()
+        .and_then(|config| config.open_level(git2::ConfigLevel::Local))
+        .context("failed opening repo config")?;
+    for (key, value) in entries {
+        git_config
+            .set_str(key, value)
+            .with_context(|| format!("failed setting git config {key}"))?;
+    }
+    Ok(())
+}
+
+/// Refspecs fetched by `GIT_MIRROR_CLONE`: every branch and tag, so history
+/// outside the served branch stays available locally.
+const MIRROR_REFSPECS: &[&str] = &[
+    "+refs/heads/*:refs/remotes/origin/*",
+    "+refs/tags/*:refs/tags/*",
+];
+
+fn fetch_branch(
+    repo: &Repository,
+    branch: &str,
+    all_refs: bool,
+    config: &AppConfig,
+    transfer: Arc<Mutex<TransferStats>>,
+) -> Result<String> {
+    let callbacks = build_remote_callbacks(config, transfer);
     let mut fetch_options = FetchOptions::new();
     fetch_options.remote_callbacks(callbacks);
     fetch_options.prune(git2::FetchPrune::On);
@@ -149,16 +1039,124 @@ fn fetch_branch(repo: &Repository, branch: &str, git_token: Option<&str>) -> Res
     let mut remote = repo
         .find_remote("origin")
         .context("git remote origin not found")?;
-    remote
-        .fetch(&[branch], Some(&mut fetch_options), None)
-        .with_context(|| format!("git fetch origin {branch} failed"))?;
+    if all_refs {
+        remote
+            .fetch(MIRROR_REFSPECS, Some(&mut fetch_options), None)
+            .context("git fetch origin (all refs) failed")?;
+    } else {
+        remote
+            .fetch(&[branch], Some(&mut fetch_options), None)
+            .with_context(|| format!("git fetch origin {branch} failed"))?;
+    }
+
+    // Fetching a refspec the remote no longer advertises succeeds silently, so
+    // check the advertisement explicitly to report a renamed/deleted branch.
+    if let Some(url) = remote.url()
+        && is_empty_local_repository(url)
+    {
+        return Err(SyncError::empty_branch(branch).into());
+    }
+    advertised_branch_tip(
+        remote.list().context("git ls-remote origin failed")?,
+        branch,
+    )
+}
+
+/// Fails unless `origin/<branch>` descends from the checked-out `HEAD` or is
+/// the `accept_rewrite_to` commit. A fresh clone has nothing to rewrite.
+fn check_fast_forward(
+    repo: &Repository,
+    branch: &str,
+    accept_rewrite_to: Option<&str>,
+) -> Result<()> {
+    let Some(head) = repo.head().ok().and_then(|head| head.target()) else {
+        return Ok(());
+    };
+    // A missing branch is reported by the reset that follows.
+    let Ok(tip) = repo.refname_to_id(&format!("refs/remotes/origin/{branch}")) else {
+        return Ok(());
+    };
+    if tip == head
+        || accept_rewrite_to == Some(tip.to_string().as_str())
+        || repo
+            .graph_descendant_of(tip, head)
+            .context("failed comparing commit history")?
+    {
+        return Ok(());
+    }
+    Err(SyncError {
+        kind: SyncErrorKind::NonFastForward,
+        message: format!(
+            "origin/{branch} moved from {head} to {tip}, which does not descend from it; \
+             set GIT_ACCEPT_REWRITE_TO={tip} to accept"
+        ),
+    }
+    .into())
+}
+
+/// Refuses to reset to `origin/<branch>` when its blobs add up to more than
+/// `max_bytes` (0 disables the check). Sizes come from the object headers, so
+/// nothing is written and a checkout that would run into a filesystem quota
+/// partway never starts.
+fn check_worktree_size(repo: &Repository, branch: &str, max_bytes: u64) -> Result<()> {
+    if max_bytes == 0 {
+        return Ok(());
+    }
+    // A missing branch is reported by the reset that follows.
+    let Ok(tip) = repo.refname_to_id(&format!("refs/remotes/origin/{branch}")) else {
+        return Ok(());
+    };
+    let tree = repo
+        .find_commit(tip)
+        .and_then(|commit| commit.tree())
+        .with_context(|| format!("failed reading tree of {tip}"))?;
+    let odb = repo.odb().context("failed opening object database")?;
+    let mut total: u64 = 0;
+    let mut failure = None;
+    // An aborted walk returns an error of its own; `total` and `failure`
+    // say why it stopped.
+    let _ = tree.walk(TreeWalkMode::PreOrder, |_, entry| {
+        if entry.kind() != Some(ObjectType::Blob) {
+            return TreeWalkResult::Ok;
+        }
+        match odb.read_header(entry.id()) {
+            Ok((size, _)) => {
+                total = total.saturating_add(size as u64);
+                if total > max_bytes {
+                    TreeWalkResult::Abort
+                } else {
+                    TreeWalkResult::Ok
+                }
+            }
+            Err(err) => {
+                failure = Some(err);
+                TreeWalkResult::Abort
+            }
+        }
+    });
+    if let Some(err) = failure {
+        return Err(anyhow::Error::new(err).context(format!("failed sizing tree of {tip}")));
+    }
+    if total > max_bytes {
+        return Err(SyncError {
+            kind: SyncErrorKind::WorktreeQuotaExceeded,
+            message: format!(
+                "origin/{branch} at {tip} needs more than MAX_WORKTREE_BYTES={max_bytes} bytes \
+                 of worktree; keeping the current commit"
+            ),
+        }
+        .into());
+    }
     Ok(())
 }
 
 fn hard_reset_to_origin_branch(repo: &Repository, branch: &str) -> Result<()> {
     let reference = repo
         .find_reference(&format!("refs/remotes/origin/{branch}"))
-        .with_context(|| format!("origin branch ref not found: {branch}"))?;
+        .map_err(|err| match err.code() {
+            git2::ErrorCode::NotFound => anyhow::Error::new(SyncError::branch_missing(branch)),
+            _ => anyhow::Error::new(err).context(format!("origin branch ref not found: {branch}")),
+        })?;
     let commit = reference
         .peel_to_commit()
         .with_context(|| format!("failed resolving origin/{branch} to commit"))?;
@@ -224,13 +1222,278 @@ fn remove_empty_parents_until_workdir(workdir: &Path, mut current: Option<&Path>
     Ok(())
 }
 
-fn build_remote_callbacks(git_token: Option<&str>) -> RemoteCallbacks<'static> {
+/// Sets the `User-Agent` libgit2 sends on every HTTP(S) request in this
+/// process. libgit2 otherwise appends its own comment (`git/2.0 (libgit2
+/// 1.9.x)`), so that is cleared to send `user_agent` verbatim.
+pub fn set_git_user_agent(user_agent: &str) -> Result<()> {
+    let product = CString::new(user_agent).context("user agent contains a NUL byte")?;
+    let comment = CString::default();
+    libgit2_sys::init();
+    // SAFETY: libgit2 copies both strings before returning.
+    let result = unsafe {
+        let product_result = libgit2_sys::git_libgit2_opts(
+            libgit2_sys::GIT_OPT_SET_USER_AGENT_PRODUCT as c_int,
+            product.as_ptr(),
+        );
+        if product_result < 0 {
+            product_result
+        } else {
+            libgit2_sys::git_libgit2_opts(
+                libgit2_sys::GIT_OPT_SET_USER_AGENT as c_int,
+                comment.as_ptr(),
+            )
+        }
+    };
+    if result < 0 {
+        return Err(anyhow!("failed setting git user agent"));
+    }
+    Ok(())
+}
+
+/// The `User-Agent` product libgit2 currently sends.
+pub fn git_user_agent() -> Result<String> {
+    libgit2_sys::init();
+    let mut buf = libgit2_sys::git_buf {
+        ptr: std::ptr::null_mut(),
+        reserved: 0,
+        size: 0,
+    };
+    // SAFETY: libgit2 fills `buf`, which is read and then disposed once.
+    unsafe {
+        if libgit2_sys::git_libgit2_opts(
+            libgit2_sys::GIT_OPT_GET_USER_AGENT_PRODUCT as c_int,
+            &mut buf,
+        ) < 0
+        {
+            return Err(anyhow!("failed reading git user agent"));
+        }
+        let user_agent = if buf.ptr.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(buf.ptr).to_string_lossy().into_owned()
+        };
+        libgit2_sys::git_buf_dispose(&mut buf);
+        Ok(user_agent)
+    }
+}
+
+fn build_remote_callbacks(
+    config: &AppConfig,
+    transfer: Arc<Mutex<TransferStats>>,
+) -> RemoteCallbacks<'static> {
     let mut callbacks = RemoteCallbacks::new();
-    if let Some(token) = git_token {
-        let token = token.to_string();
+    callbacks.transfer_progress(move |progress| {
+        if let Ok(mut stats) = transfer.lock() {
+            *stats = TransferStats {
+                received_objects: progress.received_objects(),
+                total_objects: progress.total_objects(),
+                received_bytes: progress.received_bytes(),
+            };
+        }
+        true
+    });
+    if let Some(command) = config.git_credential_command.clone() {
+        // Each set of callbacks serves a single clone/fetch, so the helper runs
+        // at most once per operation and hands out a fresh token every sync.
+        let repo_url = config.git_repo_url.clone();
+        let mut cached: Option<String> = None;
+        callbacks.credentials(move |_url, _username_from_url, _allowed_types| {
+            let token = match &cached {
+                Some(token) => token.clone(),
+                None => {
+                    let token = run_credential_command(&command, &repo_url)
+                        .map_err(|err| git2::Error::from_str(&format!("{err:#}")))?;
+                    redact_from_logs(&token);
+                    cached.insert(token).clone()
+                }
+            };
+            Cred::userpass_plaintext("x-access-token", &token)
+        });
+    } else if let Some(app) = config.github_app.clone() {
+        callbacks.credentials(move |_url, _username_from_url, _allowed_types| {
+            let token = installation_token(&app)
+                .map_err(|err| git2::Error::from_str(&format!("{err:#}")))?;
+            redact_from_logs(&token);
+            Cred::userpass_plaintext("x-access-token", &token)
+        });
+    } else if let Some(token) = config.git_token.clone() {
         callbacks.credentials(move |_url, _username_from_url, _allowed_types| {
             Cred::userpass_plaintext("x-access-token", &token)
         });
     }
     callbacks
 }
+
+/// Keeps a token handed to git out of `/meta/logs`.
+fn redact_from_logs(token: &str) {
+    if let Some(buffer) = log_buffer::installed() {
+        buffer.redact_short_lived(token.to_string());
+    }
+}
+
+/// Runs `GIT_CREDENTIAL_COMMAND` (a program followed by whitespace-separated
+/// arguments, executed without a shell) with the repository URL appended, and
+/// returns the first line of its stdout as the token.
+pub fn run_credential_command(command: &str, repo_url: &str) -> Result<String> {
+    let mut parts = command.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| anyhow!("GIT_CREDENTIAL_COMMAND is empty"))?;
+    let output = Command::new(program)
+        .args(parts)
+        .arg(repo_url)
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("failed running credential command {program}"))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "credential command {program} failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let token = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .map(str::trim)
+        .unwrap_or_default()
+        .to_string();
+    if token.is_empty() {
+        return Err(anyhow!("credential command {program} printed no token"));
+    }
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn write_script(dir: &Path, name: &str, body: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{body}\n")).expect("write script");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).expect("chmod script");
+        path
+    }
+
+    #[test]
+    fn credential_command_prints_token_for_repo_url() {
+        let temp = tempdir().expect("temp dir");
+        let helper = write_script(temp.path(), "helper", r#"echo "token-$1-for-$2""#);
+        let command = format!("{} prod", helper.display());
+
+        let token = run_credential_command(&command, "https://github.com/org/repo.git")
+            .expect("helper should succeed");
+        assert_eq!(token, "token-prod-for-https://github.com/org/repo.git");
+    }
+
+    #[test]
+    fn credential_command_failure_is_an_error() {
+        let temp = tempdir().expect("temp dir");
+        let failing = write_script(temp.path(), "failing", "echo denied >&2; exit 3");
+        let silent = write_script(temp.path(), "silent", "exit 0");
+
+        let err = run_credential_command(&failing.display().to_string(), "url")
+            .expect_err("non-zero exit should fail");
+        assert!(err.to_string().contains("denied"), "{err}");
+        assert!(run_credential_command(&silent.display().to_string(), "url").is_err());
+    }
+
+    #[test]
+    fn free_space_below_threshold_is_disk_low() {
+        let temp = tempdir().expect("temp dir");
+        let mirror = temp.path().join("not-cloned-yet");
+
+        let err = check_free_space(&mirror, 4096, |dir| {
+            assert_eq!(dir, temp.path(), "probes the nearest existing ancestor");
+            Ok(1024)
+        })
+        .expect_err("low space should fail");
+        assert_eq!(error_kind(&err), SyncErrorKind::DiskLow);
+
+        check_free_space(&mirror, 4096, |_| Ok(4096)).expect("enough space");
+        check_free_space(&mirror, 0, |_| panic!("disabled check must not probe"))
+            .expect("disabled check");
+    }
+
+    #[test]
+    fn jittered_sync_delay_stays_within_bounds() {
+        assert_eq!(sync_delay(30, 0), Duration::from_secs(30));
+        for _ in 0..1000 {
+            let delay = sync_delay(30, 10);
+            assert!(delay >= Duration::from_secs(20), "{delay:?}");
+            assert!(delay <= Duration::from_secs(40), "{delay:?}");
+        }
+        // Jitter wider than the interval still never reaches zero.
+        for _ in 0..1000 {
+            let delay = sync_delay(5, 60);
+            assert!(delay >= MIN_SYNC_DELAY, "{delay:?}");
+            assert!(delay <= Duration::from_secs(65), "{delay:?}");
+        }
+    }
+
+    #[test]
+    fn retries_only_transient_fetch_errors() {
+        let net_error = || {
+            anyhow::Error::new(git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Net,
+                "failed to resolve address",
+            ))
+            .context("git fetch origin main failed")
+        };
+
+        let mut calls = 0;
+        let (result, retries) = with_retries(2, Duration::ZERO, || {
+            calls += 1;
+            if calls == 1 {
+                Err(net_error())
+            } else {
+                Ok("tip")
+            }
+        });
+        assert_eq!(result.expect("second attempt succeeds"), "tip");
+        assert_eq!((calls, retries), (2, 1));
+
+        let mut calls = 0;
+        let (result, retries) = with_retries(2, Duration::ZERO, || {
+            calls += 1;
+            Err::<(), _>(net_error())
+        });
+        assert!(result.is_err());
+        assert_eq!((calls, retries), (3, 2));
+
+        let mut calls = 0;
+        let (result, retries) = with_retries(2, Duration::ZERO, || {
+            calls += 1;
+            Err::<(), _>(anyhow::Error::new(git2::Error::new(
+                git2::ErrorCode::Auth,
+                git2::ErrorClass::Http,
+                "authentication required",
+            )))
+        });
+        assert!(result.is_err());
+        assert_eq!((calls, retries), (1, 0));
+
+        let mut calls = 0;
+        let (result, _) = with_retries(2, Duration::ZERO, || {
+            calls += 1;
+            Err::<(), _>(SyncError::branch_missing("main").into())
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn applies_configured_git_user_agent() {
+        set_git_user_agent("acme-mirror/1.2 (+ops@example.com)").expect("set user agent");
+        assert_eq!(
+            git_user_agent().expect("read user agent"),
+            "acme-mirror/1.2 (+ops@example.com)"
+        );
+        assert!(set_git_user_agent("bad\0agent").is_err());
+    }
+}
@@ -1,17 +1,40 @@
-use std::{process::Command, sync::Arc};
+use std::{
+    io::Read,
+    process::Command,
+    sync::{Arc, atomic::Ordering},
+    time::Duration,
+};
 
+use axum::{
+    body::Body,
+    http::{Request, StatusCode, header},
+};
 use repo_sync::{
-    config::AppConfig,
-    sync::{SyncStatus, sync_once},
+    check::check,
+    config::{AppConfig, LastModifiedSource, ServeMount, SyncMode},
+    history::{SyncHistory, SyncOutcomeKind},
+    serve::{ServeOptions, serve},
+    server::{AppState, router},
+    sync::{
+        SyncErrorKind, SyncStatus, SyncWarning, lock_mirror, precompute_etags, prepare_work_dir,
+        sync_loop, sync_once,
+    },
 };
 use tempfile::tempdir;
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, Semaphore};
+use tokio_util::sync::CancellationToken;
+use tower::ServiceExt;
 
 fn run_git(cwd: &std::path::Path, args: &[&str]) {
+    run_git_with_env(cwd, args, &[]);
+}
+
+fn run_git_with_env(cwd: &std::path::Path, args: &[&str], envs: &[(&str, &str)]) {
     let mut cmd = Command::new("git");
-    cmd.args(args).current_dir(cwd);
+    cmd.args(args).current_dir(cwd).envs(envs.iter().copied());
     // When tests run under git hooks, inherited GIT_* vars can make commands
-    // target the outer repository instead of this temp repository.
+    // target the outer repository instead of this temp repository, or
+    // override the identity set up by `init_source_repo`.
     for key in [
         "GIT_DIR",
         "GIT_WORK_TREE",
@@ -21,6 +44,10 @@ fn run_git(cwd: &std::path::Path, args: &[&str]) {
         "GIT_COMMON_DIR",
         "GIT_PREFIX",
         "GIT_CEILING_DIRECTORIES",
+        "GIT_AUTHOR_NAME",
+        "GIT_AUTHOR_EMAIL",
+        "GIT_COMMITTER_NAME",
+        "GIT_COMMITTER_EMAIL",
     ] {
         cmd.env_remove(key);
     }
@@ -33,37 +60,95 @@ fn run_git(cwd: &std::path::Path, args: &[&str]) {
     );
 }
 
+fn init_source_repo(source: &std::path::Path) {
+    std::fs::create_dir_all(source).expect("create source dir");
+    run_git(source, &["init"]);
+    run_git(source, &["checkout", "-b", "main"]);
+    run_git(source, &["config", "user.email", "bot@example.com"]);
+    run_git(source, &["config", "user.name", "Bot"]);
+    run_git(source, &["config", "commit.gpgsign", "false"]);
+}
+
+fn test_config(source: &std::path::Path, mirror: &std::path::Path) -> AppConfig {
+    AppConfig {
+        git_repo_url: format!("file://{}", source.display()),
+        mirror_dir: mirror.to_path_buf(),
+        work_dir: mirror.with_file_name("work"),
+        http_bind_addr: "127.0.0.1:0".to_string(),
+        max_file_size_bytes: 1024 * 1024,
+        ..AppConfig::default()
+    }
+}
+
 #[tokio::test]
-async fn sync_once_updates_mirror_when_source_changes() {
+async fn first_clone_is_staged_in_work_dir() {
     let tmp = tempdir().expect("temp dir");
     let source = tmp.path().join("source");
     let mirror = tmp.path().join("mirror");
-    std::fs::create_dir_all(&source).expect("create source dir");
+    let work_dir = tmp.path().join("custom-work");
+    init_source_repo(&source);
+    std::fs::write(source.join("collections.json"), "{}").expect("write file");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    std::fs::create_dir_all(work_dir.join("clone-stale/.git")).expect("create leftover");
+    std::fs::write(work_dir.join("keep.txt"), "").expect("write unrelated file");
+    prepare_work_dir(&work_dir, &mirror).expect("work dir should be usable");
+    assert!(!work_dir.join("clone-stale").exists());
+    assert!(work_dir.join("keep.txt").exists());
 
-    run_git(&source, &["init"]);
-    run_git(&source, &["checkout", "-b", "main"]);
-    run_git(&source, &["config", "user.email", "bot@example.com"]);
-    run_git(&source, &["config", "user.name", "Bot"]);
-    run_git(&source, &["config", "commit.gpgsign", "false"]);
+    // A work dir that can't hold the staging clone fails the sync, so the
+    // clone must be going through it.
+    let blocked = tmp.path().join("blocked");
+    std::fs::write(&blocked, "").expect("write file");
+    let err = sync_once(
+        &AppConfig {
+            work_dir: blocked.clone(),
+            ..test_config(&source, &mirror)
+        },
+        Arc::new(RwLock::new(SyncStatus::default())),
+        &SyncHistory::new(10),
+    )
+    .await
+    .expect_err("sync should fail without a work dir");
+    assert!(format!("{err:#}").contains(&blocked.display().to_string()));
+    assert!(!mirror.exists());
+
+    sync_once(
+        &AppConfig {
+            work_dir: work_dir.clone(),
+            ..test_config(&source, &mirror)
+        },
+        Arc::new(RwLock::new(SyncStatus::default())),
+        &SyncHistory::new(10),
+    )
+    .await
+    .expect("sync should work");
+    assert!(mirror.join("collections.json").exists());
+    let mut left: Vec<_> = std::fs::read_dir(&work_dir)
+        .expect("read work dir")
+        .map(|entry| entry.expect("entry").file_name())
+        .collect();
+    left.sort();
+    assert_eq!(left, ["keep.txt"]);
+}
+
+#[tokio::test]
+async fn sync_once_updates_mirror_when_source_changes() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
 
     std::fs::write(source.join("collections.json"), "{\"version\":1}").expect("write v1");
     run_git(&source, &["add", "."]);
     run_git(&source, &["commit", "-m", "v1"]);
 
-    let config = AppConfig {
-        git_repo_url: format!("file://{}", source.display()),
-        git_branch: "main".to_string(),
-        git_sync_interval_seconds: 30,
-        git_token: None,
-        mirror_dir: mirror.clone(),
-        serve_subdir: None,
-        http_bind_addr: "127.0.0.1:0".to_string(),
-        max_path_length: 512,
-        max_file_size_bytes: 1024 * 1024,
-    };
+    let config = test_config(&source, &mirror);
     let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let history = SyncHistory::new(10);
 
-    sync_once(&config, status.clone())
+    sync_once(&config, status.clone(), &history)
         .await
         .expect("first sync should work");
     let current_after_first = status.read().await.current_sha.clone();
@@ -77,7 +162,7 @@ async fn sync_once_updates_mirror_when_source_changes() {
     run_git(&source, &["add", "."]);
     run_git(&source, &["commit", "-m", "v2"]);
 
-    sync_once(&config, status.clone())
+    sync_once(&config, status.clone(), &history)
         .await
         .expect("second sync should work");
 
@@ -92,17 +177,234 @@ async fn sync_once_updates_mirror_when_source_changes() {
 }
 
 #[tokio::test]
-async fn sync_once_removes_untracked_files_and_dirs_from_mirror() {
+async fn previous_sha_carries_over_a_restart() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    let config = test_config(&source, &mirror);
+    let history = SyncHistory::new(10);
+    let commit = |version: u32| {
+        std::fs::write(source.join("a.json"), format!("{{\"version\":{version}}}"))
+            .expect("write file");
+        run_git(&source, &["add", "."]);
+        run_git(&source, &["commit", "-m", &format!("v{version}")]);
+    };
+
+    commit(1);
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("sync v1");
+    let v1 = status.read().await.current_sha.clone();
+    commit(2);
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("sync v2");
+    let v2 = status.read().await.current_sha.clone();
+
+    let restored = SyncStatus::restore(&mirror);
+    assert_eq!(restored.current_sha, v2);
+    assert_eq!(restored.previous_sha, v1);
+    assert!(restored.last_success_at.is_none());
+
+    // Same SHA after the restart keeps the persisted previous one.
+    let status = Arc::new(RwLock::new(restored));
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("resync v2");
+    assert_eq!(status.read().await.previous_sha, v1);
+
+    commit(3);
+    let status = Arc::new(RwLock::new(SyncStatus::restore(&mirror)));
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("sync v3");
+    assert_eq!(status.read().await.previous_sha, v2);
+
+    let state_file = mirror.join(".git/repo-sync-state.json");
+    assert!(!mirror.join("repo-sync-state.json").exists());
+    std::fs::write(&state_file, "{not json").expect("corrupt state");
+    assert!(SyncStatus::restore(&mirror).current_sha.is_none());
+    std::fs::write(
+        &state_file,
+        r#"{"current_sha":"../../etc","previous_sha":null}"#,
+    )
+    .expect("bogus state");
+    assert!(SyncStatus::restore(&mirror).current_sha.is_none());
+}
+
+#[tokio::test]
+async fn sha_changes_are_counted_in_metrics() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    let config = test_config(&source, &mirror);
+    let history = SyncHistory::new(10);
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let commit = |version: u32| {
+        std::fs::write(source.join("a.json"), format!("{{\"version\":{version}}}"))
+            .expect("write file");
+        run_git(&source, &["add", "."]);
+        run_git(&source, &["commit", "-m", &format!("v{version}")]);
+    };
+
+    // The initial clone and an unchanged resync are not changes.
+    commit(1);
+    for _ in 0..2 {
+        sync_once(&config, status.clone(), &history)
+            .await
+            .expect("sync v1");
+    }
+    assert_eq!(status.read().await.sha_changes, 0);
+    assert!(status.read().await.last_change_at.is_some());
+
+    commit(2);
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("sync v2");
+    assert_eq!(status.read().await.sha_changes, 1);
+    let changed_at = status.read().await.last_change_at;
+    assert_eq!(SyncStatus::restore(&mirror).last_change_at, changed_at);
+
+    let response = router(AppState::new(config, status))
+        .oneshot(
+            Request::builder()
+                .uri("/metrics")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let text = String::from_utf8(body.to_vec()).expect("utf-8");
+    assert!(text.contains("repo_sync_sha_changes_total 1\n"), "{text}");
+    assert!(
+        text.lines()
+            .any(|line| line.starts_with("repo_sync_seconds_since_last_change ")),
+        "{text}"
+    );
+}
+
+#[tokio::test]
+async fn non_fastforward_updates_are_refused_until_accepted() {
     let tmp = tempdir().expect("temp dir");
     let source = tmp.path().join("source");
     let mirror = tmp.path().join("mirror");
-    std::fs::create_dir_all(&source).expect("create source dir");
+    init_source_repo(&source);
+    let mut config = AppConfig {
+        git_reject_non_fastforward: true,
+        ..test_config(&source, &mirror)
+    };
+    let history = SyncHistory::new(10);
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let commit = |content: &str| {
+        std::fs::write(source.join("a.json"), content).expect("write file");
+        run_git(&source, &["add", "."]);
+        run_git(&source, &["commit", "-m", content]);
+    };
+
+    commit("v1");
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("sync v1");
+    commit("v2");
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("fast-forward to v2");
+    let v2 = status.read().await.current_sha.clone();
+
+    // Force-push a divergent history.
+    run_git(&source, &["reset", "--hard", "HEAD~1"]);
+    commit("evil");
+    let err = sync_once(&config, status.clone(), &history)
+        .await
+        .expect_err("rewrite refused");
+    assert!(err.to_string().contains("does not descend"), "{err:#}");
+    let snapshot = status.read().await.clone();
+    assert_eq!(snapshot.error_kind, Some(SyncErrorKind::NonFastForward));
+    assert_eq!(snapshot.current_sha, v2);
+    assert_eq!(
+        std::fs::read_to_string(mirror.join("a.json")).expect("read mirrored file"),
+        "v2"
+    );
+
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(&source)
+        .output()
+        .expect("rev-parse");
+    let evil = String::from_utf8(output.stdout)
+        .expect("utf-8")
+        .trim()
+        .to_string();
+    config.git_accept_rewrite_to = Some(evil.clone());
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("accepted rewrite");
+    assert_eq!(
+        status.read().await.current_sha.as_deref(),
+        Some(evil.as_str())
+    );
+    assert_eq!(
+        std::fs::read_to_string(mirror.join("a.json")).expect("read mirrored file"),
+        "evil"
+    );
+}
+
+#[tokio::test]
+async fn empty_repository_waits_for_first_commit() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    let config = test_config(&source, &mirror);
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let history = SyncHistory::new(10);
+
+    for _ in 0..2 {
+        sync_once(&config, status.clone(), &history)
+            .await
+            .expect("an empty branch is not a failure");
+    }
+    let snapshot = status.read().await.clone();
+    assert_eq!(snapshot.error_kind, Some(SyncErrorKind::EmptyBranch));
+    assert_eq!(snapshot.consecutive_failures, 0);
+    assert!(snapshot.current_sha.is_none());
+    assert_eq!(history.newest_first()[0].outcome, SyncOutcomeKind::Empty);
+    let response = router(AppState::new(config.clone(), status.clone()))
+        .oneshot(
+            Request::builder()
+                .uri("/readyz")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    std::fs::write(source.join("a.json"), "{}").expect("write file");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "first"]);
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("sync first commit");
+    let snapshot = status.read().await.clone();
+    assert!(snapshot.current_sha.is_some());
+    assert_eq!(snapshot.error_kind, None);
+    assert!(mirror.join("a.json").is_file());
+}
 
-    run_git(&source, &["init"]);
-    run_git(&source, &["checkout", "-b", "main"]);
-    run_git(&source, &["config", "user.email", "bot@example.com"]);
-    run_git(&source, &["config", "user.name", "Bot"]);
-    run_git(&source, &["config", "commit.gpgsign", "false"]);
+#[tokio::test]
+async fn sync_once_removes_untracked_files_and_dirs_from_mirror() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
 
     std::fs::create_dir_all(source.join("nested")).expect("create source nested dir");
     std::fs::write(source.join("collections.json"), "{\"version\":1}").expect("write v1");
@@ -110,20 +412,11 @@ async fn sync_once_removes_untracked_files_and_dirs_from_mirror() {
     run_git(&source, &["add", "."]);
     run_git(&source, &["commit", "-m", "initial"]);
 
-    let config = AppConfig {
-        git_repo_url: format!("file://{}", source.display()),
-        git_branch: "main".to_string(),
-        git_sync_interval_seconds: 30,
-        git_token: None,
-        mirror_dir: mirror.clone(),
-        serve_subdir: None,
-        http_bind_addr: "127.0.0.1:0".to_string(),
-        max_path_length: 512,
-        max_file_size_bytes: 1024 * 1024,
-    };
+    let config = test_config(&source, &mirror);
     let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let history = SyncHistory::new(10);
 
-    sync_once(&config, status.clone())
+    sync_once(&config, status.clone(), &history)
         .await
         .expect("first sync should work");
 
@@ -134,7 +427,7 @@ async fn sync_once_removes_untracked_files_and_dirs_from_mirror() {
     std::fs::create_dir_all(&untracked_dir).expect("create untracked dir");
     std::fs::write(&untracked_nested_file, "{\"ephemeral\":true}").expect("write untracked nested");
 
-    sync_once(&config, status)
+    sync_once(&config, status, &history)
         .await
         .expect("second sync should work");
 
@@ -151,3 +444,1769 @@ async fn sync_once_removes_untracked_files_and_dirs_from_mirror() {
         "{\"tracked\":true}"
     );
 }
+
+#[tokio::test]
+async fn sync_once_records_transfer_stats() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+
+    std::fs::write(source.join("collections.json"), "{\"version\":1}").expect("write v1");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let config = test_config(&source, &mirror);
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let history = SyncHistory::new(10);
+
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("sync should work");
+
+    let transfer = status
+        .read()
+        .await
+        .last_transfer
+        .expect("transfer stats recorded");
+    assert!(transfer.received_bytes > 0);
+    assert!(transfer.received_objects > 0);
+}
+
+#[tokio::test]
+async fn sync_once_reports_missing_branch_and_keeps_last_good_tree() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+
+    std::fs::write(source.join("collections.json"), "{\"version\":1}").expect("write v1");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let config = test_config(&source, &mirror);
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let history = SyncHistory::new(10);
+
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("first sync should work");
+
+    run_git(&source, &["checkout", "-b", "renamed"]);
+    run_git(&source, &["branch", "-D", "main"]);
+
+    let err = sync_once(&config, status.clone(), &history)
+        .await
+        .expect_err("sync of deleted branch should fail");
+    assert_eq!(err.to_string(), "branch 'main' no longer exists on origin");
+
+    let status_snapshot = status.read().await.clone();
+    assert_eq!(
+        status_snapshot.error_kind,
+        Some(SyncErrorKind::BranchMissing)
+    );
+    assert_eq!(
+        status_snapshot.last_error.as_deref(),
+        Some("branch 'main' no longer exists on origin")
+    );
+    assert!(status_snapshot.current_sha.is_some());
+    assert_eq!(
+        std::fs::read_to_string(mirror.join("collections.json")).expect("read mirrored file"),
+        "{\"version\":1}"
+    );
+}
+
+#[test]
+fn check_reports_branch_tips_without_cloning() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    std::fs::write(source.join("collections.json"), "{}").expect("write file");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let report = check(&AppConfig {
+        git_branches: vec!["missing".to_string()],
+        ..test_config(&source, &mirror)
+    });
+    assert!(!report.ok);
+    assert!(!mirror.exists(), "check must not clone");
+    let [main, missing] = &report.branches[..] else {
+        panic!("expected two branches, got {:?}", report.branches);
+    };
+    assert!(main.ok);
+    assert_eq!(main.tip.as_ref().map(String::len), Some(40));
+    assert!(!missing.ok);
+    assert_eq!(missing.error_kind, Some(SyncErrorKind::BranchMissing));
+
+    let report = check(&test_config(&source, &mirror));
+    assert!(report.ok);
+}
+
+#[tokio::test]
+async fn precomputed_etags_serve_first_request_without_reading() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    std::fs::write(source.join("collections.json"), "{\"version\":1}").expect("write v1");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let config = AppConfig {
+        precompute_etags: true,
+        ..test_config(&source, &mirror)
+    };
+    let state = AppState::new(config.clone(), Arc::new(RwLock::new(SyncStatus::default())));
+    sync_once(&config, state.status.clone(), &state.history)
+        .await
+        .expect("sync should work");
+    precompute_etags(&config, state.digests.clone()).await;
+
+    let response = router(state.clone())
+        .oneshot(
+            Request::builder()
+                .method("HEAD")
+                .uri("/files/collections.json")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key(header::ETAG));
+    assert_eq!(state.files_read.load(Ordering::Relaxed), 0);
+}
+
+#[tokio::test]
+async fn files_deleted_upstream_answer_gone() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    std::fs::write(source.join("old.json"), "{}").expect("write file");
+    std::fs::write(source.join("kept.json"), "{}").expect("write file");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let config = AppConfig {
+        gone_window_seconds: 3600,
+        ..test_config(&source, &mirror)
+    };
+    let state = AppState::new(config.clone(), Arc::new(RwLock::new(SyncStatus::default())));
+    sync_once(&config, state.status.clone(), &state.history)
+        .await
+        .expect("first sync should work");
+
+    run_git(&source, &["rm", "old.json"]);
+    run_git(&source, &["commit", "-m", "remove old.json"]);
+    sync_once(&config, state.status.clone(), &state.history)
+        .await
+        .expect("second sync should work");
+    let sha = state.status.read().await.current_sha.clone().expect("sha");
+
+    let app = router(state);
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/files/old.json")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::GONE);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let body: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(body["code"], "gone");
+    assert!(body["error"].as_str().expect("message").contains(&sha));
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/files/never.json")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn git_config_set_is_written_to_the_mirror() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    std::fs::write(source.join("collections.json"), "{}").expect("write file");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let config = AppConfig {
+        git_config_set: [("core.compression".to_string(), "0".to_string())].into(),
+        ..test_config(&source, &mirror)
+    };
+    sync_once(
+        &config,
+        Arc::new(RwLock::new(SyncStatus::default())),
+        &SyncHistory::new(10),
+    )
+    .await
+    .expect("sync should work");
+
+    let repo = git2::Repository::open(&mirror).expect("open mirror");
+    let git_config = repo
+        .config()
+        .and_then(|config| config.open_level(git2::ConfigLevel::Local))
+        .expect("mirror config");
+    assert_eq!(git_config.get_i32("core.compression").expect("key set"), 0);
+}
+
+#[tokio::test]
+async fn sync_loop_stops_when_cancelled() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    std::fs::write(source.join("collections.json"), "{}").expect("write file");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let state = AppState::new(
+        test_config(&source, &mirror),
+        Arc::new(RwLock::new(SyncStatus::default())),
+    );
+    let shutdown = CancellationToken::new();
+    let handle = sync_loop(
+        state.live_config.clone(),
+        None,
+        state.status.clone(),
+        state.history.clone(),
+        state.digests.clone(),
+        Arc::new(Semaphore::new(1)),
+        shutdown.clone(),
+    );
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while state.status.read().await.current_sha.is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("first sync should complete");
+
+    // The default 30s interval means the loop is asleep; cancelling must
+    // wake it rather than wait the interval out.
+    shutdown.cancel();
+    tokio::time::timeout(Duration::from_secs(5), handle)
+        .await
+        .expect("loop should stop promptly")
+        .expect("loop should not panic");
+    assert_eq!(state.history.newest_first().len(), 1);
+}
+
+#[tokio::test]
+async fn sync_loop_reports_when_the_next_sync_is_due() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    std::fs::write(source.join("collections.json"), "{}").expect("write file");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let state = AppState::new(
+        AppConfig {
+            git_sync_interval_seconds: 60,
+            ..test_config(&source, &mirror)
+        },
+        Arc::new(RwLock::new(SyncStatus::default())),
+    );
+    let shutdown = CancellationToken::new();
+    let handle = sync_loop(
+        state.live_config.clone(),
+        None,
+        state.status.clone(),
+        state.history.clone(),
+        state.digests.clone(),
+        Arc::new(Semaphore::new(1)),
+        shutdown.clone(),
+    );
+    let (last_attempt_at, next_sync_at) = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let status = state.status.read().await;
+            if let (Some(last_attempt_at), Some(next_sync_at)) =
+                (status.last_attempt_at, status.next_sync_at)
+            {
+                return (last_attempt_at, next_sync_at);
+            }
+            drop(status);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("first sync should complete");
+    shutdown.cancel();
+    handle.await.expect("loop should not panic");
+
+    let wait = next_sync_at - last_attempt_at;
+    assert!(wait >= chrono::Duration::seconds(60), "{wait}");
+    assert!(wait < chrono::Duration::seconds(70), "{wait}");
+}
+
+#[tokio::test]
+async fn branch_sync_loops_share_the_concurrency_bound() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    std::fs::write(source.join("collections.json"), "{}").expect("write file");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+    let branches = ["one", "two", "three"];
+    for branch in branches {
+        run_git(&source, &["branch", branch]);
+    }
+
+    let state = AppState::new(
+        AppConfig {
+            git_branches: branches.iter().map(|b| b.to_string()).collect(),
+            ..test_config(&source, &mirror)
+        },
+        Arc::new(RwLock::new(SyncStatus::default())),
+    );
+    let sync_slots = Arc::new(Semaphore::new(2));
+    // A slow sync holding every slot keeps the other loops waiting.
+    let slow = sync_slots
+        .clone()
+        .acquire_many_owned(2)
+        .await
+        .expect("acquire slots");
+    let shutdown = CancellationToken::new();
+    let mut statuses = vec![state.status.clone()];
+    let mut handles = vec![sync_loop(
+        state.live_config.clone(),
+        None,
+        state.status.clone(),
+        state.history.clone(),
+        state.digests.clone(),
+        sync_slots.clone(),
+        shutdown.clone(),
+    )];
+    for (branch, branch_state) in state.branches.iter() {
+        statuses.push(branch_state.status.clone());
+        handles.push(sync_loop(
+            state.live_config.clone(),
+            Some(branch.clone()),
+            branch_state.status.clone(),
+            branch_state.history.clone(),
+            state.digests.clone(),
+            sync_slots.clone(),
+            shutdown.clone(),
+        ));
+    }
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    for status in &statuses {
+        assert!(status.read().await.last_attempt_at.is_none());
+    }
+
+    // Freeing one slot lets the waiting loops through one at a time.
+    drop(slow);
+    let held = sync_slots
+        .clone()
+        .acquire_owned()
+        .await
+        .expect("acquire slot");
+    tokio::time::timeout(Duration::from_secs(20), async {
+        for status in &statuses {
+            while status.read().await.current_sha.is_none() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    })
+    .await
+    .unwrap_or_else(|_| {
+        let errors: Vec<_> = statuses
+            .iter()
+            .map(|status| status.try_read().map(|s| s.last_error.clone()))
+            .collect();
+        panic!("every branch should sync through the free slot: {errors:?}")
+    });
+    drop(held);
+
+    shutdown.cancel();
+    for handle in handles {
+        tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .expect("loop should stop promptly")
+            .expect("loop should not panic");
+    }
+}
+
+#[tokio::test]
+async fn last_modified_uses_commit_time_of_last_change() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+
+    std::fs::write(source.join("a.json"), "{\"version\":1}").expect("write a");
+    std::fs::write(source.join("b.json"), "{}").expect("write b");
+    run_git(&source, &["add", "."]);
+    run_git_with_env(
+        &source,
+        &["commit", "-m", "initial"],
+        &[("GIT_COMMITTER_DATE", "2020-01-02T03:04:05Z")],
+    );
+    std::fs::write(source.join("b.json"), "{\"changed\":true}").expect("rewrite b");
+    run_git(&source, &["add", "."]);
+    run_git_with_env(
+        &source,
+        &["commit", "-m", "change b"],
+        &[("GIT_COMMITTER_DATE", "2021-06-07T08:09:10Z")],
+    );
+
+    let config = AppConfig {
+        last_modified_source: LastModifiedSource::Commit,
+        ..test_config(&source, &mirror)
+    };
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let history = SyncHistory::new(10);
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("sync should work");
+
+    let app = router(AppState::new(config, status));
+    for (uri, expected) in [
+        ("/files/a.json", "Thu, 02 Jan 2020 03:04:05 GMT"),
+        ("/files/b.json", "Mon, 07 Jun 2021 08:09:10 GMT"),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(uri)
+                    .body(Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response
+                .headers()
+                .get(header::LAST_MODIFIED)
+                .and_then(|v| v.to_str().ok()),
+            Some(expected),
+            "{uri}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn object_db_is_measured_after_sync_and_gc_repacks_it() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+
+    let config = test_config(&source, &mirror);
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let history = SyncHistory::new(10);
+    for version in 1..=3 {
+        std::fs::write(
+            source.join("collections.json"),
+            format!("{{\"version\":{version}}}"),
+        )
+        .expect("write file");
+        run_git(&source, &["add", "."]);
+        run_git(&source, &["commit", "-m", &format!("v{version}")]);
+        sync_once(&config, status.clone(), &history)
+            .await
+            .expect("sync should work");
+    }
+    let before = status.read().await.object_db.expect("object db measured");
+    assert!(before.size_bytes > 0);
+    assert!(before.pack_count >= 1);
+
+    let config = AppConfig {
+        admin_token: Some("t0ken".to_string()),
+        ..config
+    };
+    let app = router(AppState::new(config, status.clone()));
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/admin/gc")
+                .header(header::AUTHORIZATION, "Bearer t0ken")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(
+        &axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("read body"),
+    )
+    .expect("json body");
+    assert_eq!(body["object_db"]["pack_count"], 1);
+    assert!(body["last_gc_at"].is_string());
+
+    // Every commit is still there, and nothing is left loose.
+    run_git(&mirror, &["fsck", "--full", "--strict"]);
+    run_git(&mirror, &["cat-file", "-e", "HEAD~2"]);
+    let loose = std::fs::read_dir(mirror.join(".git/objects"))
+        .expect("list objects")
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().len() == 2)
+        .count();
+    assert_eq!(loose, 0);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/meta")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    let meta: serde_json::Value = serde_json::from_slice(
+        &axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("read body"),
+    )
+    .expect("json body");
+    assert_eq!(meta["sync"]["object_db"], body["object_db"]);
+    assert_eq!(meta["sync"]["last_gc_at"], body["last_gc_at"]);
+}
+
+#[tokio::test]
+async fn drift_reports_mirror_one_commit_behind() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+
+    std::fs::write(source.join("collections.json"), "{\"version\":1}").expect("write v1");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let config = test_config(&source, &mirror);
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let history = SyncHistory::new(10);
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("sync should work");
+
+    std::fs::write(source.join("collections.json"), "{\"version\":2}").expect("write v2");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v2"]);
+    // Fetch without resetting, so the remote tip is known locally but the
+    // served checkout stays on v1.
+    run_git(&mirror, &["fetch", "origin"]);
+
+    let app = router(AppState::new(config, status.clone()));
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/meta/drift?refresh=true")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(
+        &axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("read body"),
+    )
+    .expect("json body");
+    assert_ne!(body["synced_sha"], body["remote_tip_sha"]);
+    assert_eq!(body["behind_commits"], 1);
+    assert!(body["behind_seconds"].as_i64().expect("behind seconds") >= 0);
+}
+
+#[tokio::test]
+async fn meta_refresh_reports_remote_tip_ahead_of_mirror() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+
+    std::fs::write(source.join("collections.json"), "{\"version\":1}").expect("write v1");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let config = test_config(&source, &mirror);
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let history = SyncHistory::new(10);
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("sync should work");
+    {
+        let snapshot = status.read().await;
+        assert_eq!(snapshot.remote_tip_sha, snapshot.current_sha);
+        assert!(!snapshot.behind);
+    }
+
+    std::fs::write(source.join("collections.json"), "{\"version\":2}").expect("write v2");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v2"]);
+    let source_head = git2::Repository::open(&source)
+        .expect("open source")
+        .head()
+        .expect("source head")
+        .target()
+        .expect("source head target")
+        .to_string();
+
+    let app = router(AppState::new(config, status.clone()));
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/meta?refresh=true")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(
+        &axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("read body"),
+    )
+    .expect("json body");
+    assert_eq!(body["sync"]["remote_tip_sha"], source_head.as_str());
+    assert_eq!(body["sync"]["behind"], true);
+    assert_ne!(body["sync"]["current_sha"], source_head.as_str());
+}
+
+#[tokio::test]
+async fn sync_once_keeps_untracked_files_when_cleaning_disabled() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+
+    std::fs::write(source.join("collections.json"), "{\"version\":1}").expect("write v1");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let config = AppConfig {
+        clean_untracked: false,
+        ..test_config(&source, &mirror)
+    };
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let history = SyncHistory::new(10);
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("first sync should work");
+
+    let untracked_file = mirror.join("shared/other-process.json");
+    std::fs::create_dir_all(untracked_file.parent().expect("parent")).expect("create dir");
+    std::fs::write(&untracked_file, "{}").expect("write untracked file");
+    std::fs::write(mirror.join("collections.json"), "local edit").expect("edit tracked file");
+
+    sync_once(&config, status, &history)
+        .await
+        .expect("second sync should work");
+
+    assert!(untracked_file.exists());
+    assert_eq!(
+        std::fs::read_to_string(mirror.join("collections.json")).expect("read mirrored file"),
+        "{\"version\":1}"
+    );
+}
+
+#[tokio::test]
+async fn sync_history_records_attempts_newest_first() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+
+    std::fs::write(source.join("collections.json"), "{\"version\":1}").expect("write v1");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let config = test_config(&source, &mirror);
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let history = SyncHistory::new(10);
+
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("first sync should work");
+    let first_sha = status.read().await.current_sha.clone();
+
+    std::fs::write(source.join("collections.json"), "{\"version\":2}").expect("write v2");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v2"]);
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("second sync should work");
+    let second_sha = status.read().await.current_sha.clone();
+
+    let broken = AppConfig {
+        git_branch: "missing".to_string(),
+        ..config
+    };
+    sync_once(&broken, status, &history)
+        .await
+        .expect_err("third sync should fail");
+
+    let events = history.newest_first();
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[0].outcome, SyncOutcomeKind::Failure);
+    assert!(events[0].error.is_some());
+    assert_eq!(events[1].sha, second_sha);
+    assert_eq!(events[2].sha, first_sha);
+    assert!(events[1].at >= events[2].at);
+}
+
+#[tokio::test]
+async fn serves_each_configured_branch_under_its_prefix() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+
+    std::fs::write(source.join("env.json"), "{\"env\":\"main\"}").expect("write main");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "main"]);
+    run_git(&source, &["checkout", "-b", "staging"]);
+    std::fs::write(source.join("env.json"), "{\"env\":\"staging\"}").expect("write staging");
+    run_git(&source, &["commit", "-am", "staging"]);
+    run_git(&source, &["checkout", "main"]);
+
+    let state = AppState::new(
+        AppConfig {
+            git_branches: vec!["staging".to_string()],
+            ..test_config(&source, &mirror)
+        },
+        Arc::new(RwLock::new(SyncStatus::default())),
+    );
+    sync_once(&state.config, state.status.clone(), &state.history)
+        .await
+        .expect("main sync should work");
+    let staging = &state.branches["staging"];
+    sync_once(&staging.config, staging.status.clone(), &state.history)
+        .await
+        .expect("staging sync should work");
+
+    let app = router(state.clone());
+    for (uri, expected) in [
+        ("/files/env.json", "{\"env\":\"main\"}"),
+        ("/b/main/files/env.json", "{\"env\":\"main\"}"),
+        ("/b/staging/files/env.json", "{\"env\":\"staging\"}"),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(uri)
+                    .body(Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body");
+        assert_eq!(body, expected, "{uri}");
+    }
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/b/unknown/files/env.json")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/meta")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let meta: serde_json::Value = serde_json::from_slice(&body).expect("json");
+    assert_eq!(
+        meta["branches"]["main"].as_str(),
+        state.status.read().await.current_sha.as_deref()
+    );
+    assert_eq!(
+        meta["branches"]["staging"].as_str(),
+        staging.status.read().await.current_sha.as_deref()
+    );
+    assert_ne!(meta["branches"]["main"], meta["branches"]["staging"]);
+}
+
+#[tokio::test]
+async fn meta_reports_synced_commit_summary_and_author() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+
+    std::fs::write(source.join("a.json"), "{}").expect("write file");
+    run_git(&source, &["add", "."]);
+    run_git(
+        &source,
+        &[
+            "commit",
+            "-m",
+            "Publish schema v2",
+            "-m",
+            "Body text that stays out of /meta.",
+        ],
+    );
+    let long_summary = "x".repeat(500);
+    std::fs::write(source.join("b.json"), "{}").expect("write file");
+    run_git(&source, &["add", "."]);
+
+    let state = AppState::new(
+        test_config(&source, &mirror),
+        Arc::new(RwLock::new(SyncStatus::default())),
+    );
+    sync_once(&state.config, state.status.clone(), &state.history)
+        .await
+        .expect("sync");
+
+    let meta = || async {
+        let response = router(state.clone())
+            .oneshot(
+                Request::builder()
+                    .uri("/meta")
+                    .body(Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body");
+        serde_json::from_slice::<serde_json::Value>(&body).expect("json")
+    };
+    let body = meta().await;
+    assert_eq!(body["sync"]["commit_summary"], "Publish schema v2");
+    assert_eq!(body["sync"]["commit_author"], "Bot");
+    assert!(body["sync"]["commit_time"].is_string());
+
+    run_git(&source, &["commit", "-m", &long_summary]);
+    sync_once(&state.config, state.status.clone(), &state.history)
+        .await
+        .expect("sync");
+    let summary = meta().await["sync"]["commit_summary"]
+        .as_str()
+        .expect("summary")
+        .to_string();
+    assert!(summary.len() < long_summary.len());
+    assert!(summary.ends_with('…'), "{summary}");
+}
+
+#[tokio::test]
+async fn diff_reports_changes_between_synced_snapshots() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+
+    std::fs::write(source.join("a.json"), "{}").expect("write a");
+    std::fs::write(source.join("b.txt"), "bye").expect("write b");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+    let state = AppState::new(
+        test_config(&source, &mirror),
+        Arc::new(RwLock::new(SyncStatus::default())),
+    );
+    sync_once(&state.config, state.status.clone(), &state.history)
+        .await
+        .expect("sync v1");
+
+    std::fs::write(source.join("a.json"), "{\"version\":2}").expect("update a");
+    std::fs::remove_file(source.join("b.txt")).expect("remove b");
+    std::fs::write(source.join("c.txt"), "new").expect("write c");
+    run_git(&source, &["add", "-A"]);
+    run_git(&source, &["commit", "-m", "v2"]);
+    sync_once(&state.config, state.status.clone(), &state.history)
+        .await
+        .expect("sync v2");
+    let (v1, v2) = {
+        let status = state.status.read().await;
+        (
+            status.previous_sha.clone().expect("previous sha"),
+            status.current_sha.clone().expect("current sha"),
+        )
+    };
+
+    let get = |uri: String| {
+        let app = router(state.clone());
+        async move {
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .uri(uri)
+                        .body(Body::empty())
+                        .expect("request"),
+                )
+                .await
+                .expect("response");
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("body");
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).expect("json"),
+            )
+        }
+    };
+
+    let (status, body) = get("/diff".to_string()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["from"], v1);
+    assert_eq!(body["to"], v2);
+    assert_eq!(body["truncated"], false);
+    assert_eq!(
+        body["changes"],
+        serde_json::json!([
+            {"path": "a.json", "change": "modified", "old_size": 2, "new_size": 13, "size_delta": 11},
+            {"path": "b.txt", "change": "deleted", "old_size": 3, "new_size": null, "size_delta": -3},
+            {"path": "c.txt", "change": "added", "old_size": null, "new_size": 3, "size_delta": 3},
+        ])
+    );
+
+    let (status, body) = get(format!("/diff?from={v2}&to={v1}")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["changes"][1]["change"], "added");
+
+    let (status, body) = get(format!("/diff?from={v1}&to={}", "0".repeat(40))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "not_found");
+    let (status, body) = get("/diff?from=abc".to_string()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_sha");
+}
+
+#[tokio::test]
+async fn missing_serve_subdir_is_reported_as_degraded_health() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+
+    std::fs::write(source.join("collections.json"), "{\"version\":1}").expect("write v1");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let config = AppConfig {
+        serve_subdir: Some("public".into()),
+        ..test_config(&source, &mirror)
+    };
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let history = SyncHistory::new(10);
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("sync should still succeed");
+    {
+        let snapshot = status.read().await;
+        assert!(snapshot.current_sha.is_some());
+        assert_eq!(snapshot.warnings, vec![SyncWarning::ServeRootMissing]);
+    }
+
+    let response = router(AppState::new(config, status))
+        .oneshot(
+            Request::builder()
+                .uri("/health")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    let body: serde_json::Value = serde_json::from_slice(
+        &axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("read body"),
+    )
+    .expect("json body");
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["warnings"][0], "serve_root_missing");
+}
+
+#[tokio::test]
+async fn snapshot_tarball_matches_tree_with_sha_etag() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    std::fs::create_dir_all(source.join("nested")).expect("create dir");
+    std::fs::write(source.join("collections.json"), "{}").expect("write file");
+    std::fs::write(source.join("nested/a.txt"), "a").expect("write file");
+    std::fs::write(source.join("large.bin"), "0123456789").expect("write file");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let config = AppConfig {
+        max_file_size_bytes: 8,
+        ..test_config(&source, &mirror)
+    };
+    let state = AppState::new(config.clone(), Arc::new(RwLock::new(SyncStatus::default())));
+    sync_once(&config, state.status.clone(), &state.history)
+        .await
+        .expect("sync should work");
+    // Untracked files in the worktree are not part of the commit.
+    std::fs::write(mirror.join("untracked.txt"), "x").expect("write file");
+    let sha = state
+        .status
+        .read()
+        .await
+        .current_sha
+        .clone()
+        .expect("synced sha");
+
+    let app = router(state);
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/snapshot.tar.gz")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = format!("\"{sha}\"");
+    assert_eq!(
+        response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|v| v.to_str().ok()),
+        Some(etag.as_str())
+    );
+    assert!(
+        response
+            .headers()
+            .get(header::CONTENT_DISPOSITION)
+            .and_then(|v| v.to_str().ok())
+            .expect("content disposition")
+            .contains(&format!("snapshot-{sha}.tar.gz"))
+    );
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("read body");
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(body.as_ref()));
+    let entries: Vec<(String, String)> = archive
+        .entries()
+        .expect("entries")
+        .map(|entry| {
+            let mut entry = entry.expect("entry");
+            let path = entry.path().expect("path").display().to_string();
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).expect("read entry");
+            (path, contents)
+        })
+        .collect();
+    assert_eq!(
+        entries,
+        [
+            ("files/collections.json".to_string(), "{}".to_string()),
+            ("files/nested/a.txt".to_string(), "a".to_string()),
+        ]
+    );
+
+    let unchanged = app
+        .oneshot(
+            Request::builder()
+                .uri("/snapshot.tar.gz")
+                .header(header::IF_NONE_MATCH, &etag)
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(unchanged.status(), StatusCode::NOT_MODIFIED);
+}
+
+#[tokio::test]
+async fn snapshot_tarball_only_holds_mounted_paths() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    for (path, contents) in [
+        ("site/public/a.txt", "site a"),
+        ("base/public/a.txt", "base a"),
+        ("base/public/b.txt", "base b"),
+        ("base/schemas/s.json", "{}"),
+        ("base/private/secret.txt", "secret"),
+        ("unlayered.txt", "x"),
+    ] {
+        let path = source.join(path);
+        std::fs::create_dir_all(path.parent().expect("parent")).expect("create dir");
+        std::fs::write(path, contents).expect("write file");
+    }
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let config = AppConfig {
+        serve_overlay: vec!["site".into(), "base".into()],
+        serve_mounts: vec![
+            ServeMount {
+                prefix: "/files".to_string(),
+                subdir: "public".into(),
+            },
+            ServeMount {
+                prefix: "/schemas".to_string(),
+                subdir: "schemas".into(),
+            },
+        ],
+        ..test_config(&source, &mirror)
+    };
+    let state = AppState::new(config.clone(), Arc::new(RwLock::new(SyncStatus::default())));
+    sync_once(&config, state.status.clone(), &state.history)
+        .await
+        .expect("sync should work");
+
+    let response = router(state)
+        .oneshot(
+            Request::builder()
+                .uri("/snapshot.tar.gz")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("read body");
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(body.as_ref()));
+    let entries: Vec<(String, String)> = archive
+        .entries()
+        .expect("entries")
+        .map(|entry| {
+            let mut entry = entry.expect("entry");
+            let path = entry.path().expect("path").display().to_string();
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).expect("read entry");
+            (path, contents)
+        })
+        .collect();
+    assert_eq!(
+        entries,
+        [
+            ("files/a.txt".to_string(), "site a".to_string()),
+            ("files/b.txt".to_string(), "base b".to_string()),
+            ("schemas/s.json".to_string(), "{}".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn sync_mode_none_serves_plain_directory() {
+    let tmp = tempdir().expect("temp dir");
+    let dir = tmp.path().join("site");
+    std::fs::create_dir_all(&dir).expect("create dir");
+    std::fs::write(dir.join("data.json"), "{\"local\":true}").expect("write file");
+
+    let config = AppConfig {
+        sync_mode: SyncMode::None,
+        mirror_dir: dir.clone(),
+        http_bind_addr: "127.0.0.1:0".to_string(),
+        ..AppConfig::default()
+    };
+    let state = AppState::new(config, Arc::new(RwLock::new(SyncStatus::default())));
+    sync_once(&state.config, state.status.clone(), &state.history)
+        .await
+        .expect("no-op sync");
+    assert!(!dir.join(".git").exists());
+    assert!(state.history.newest_first().is_empty());
+
+    let get = |uri: &'static str| {
+        let app = router(state.clone());
+        async move {
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .uri(uri)
+                        .body(Body::empty())
+                        .expect("request"),
+                )
+                .await
+                .expect("response");
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("body");
+            (status, body)
+        }
+    };
+    let (status, body) = get("/files/data.json").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(&body[..], b"{\"local\":true}");
+
+    assert_eq!(get("/readyz").await.0, StatusCode::OK);
+
+    let (_, body) = get("/meta").await;
+    let meta: serde_json::Value = serde_json::from_slice(&body).expect("json");
+    assert!(meta["synced_repo_url"].is_null());
+    assert!(meta["branch"].is_null());
+    assert!(meta["sync"]["current_sha"].is_null());
+    assert!(meta["sync"]["last_success_at"].is_string());
+    assert_eq!(meta["branches"], serde_json::json!({}));
+}
+
+#[tokio::test]
+async fn sync_once_reports_read_only_mirror_before_git_runs() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let volume = tmp.path().join("volume");
+    let mirror = volume.join("mirror");
+    init_source_repo(&source);
+    std::fs::write(source.join("collections.json"), "{\"version\":1}").expect("write v1");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    std::fs::create_dir_all(&volume).expect("create volume dir");
+    std::fs::set_permissions(&volume, std::fs::Permissions::from_mode(0o555))
+        .expect("make volume read-only");
+    if std::fs::write(volume.join("probe"), "").is_ok() {
+        // Running with CAP_DAC_OVERRIDE (e.g. as root); permissions can't block writes.
+        return;
+    }
+
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let err = sync_once(
+        &test_config(&source, &mirror),
+        status.clone(),
+        &SyncHistory::new(10),
+    )
+    .await
+    .expect_err("sync into read-only volume should fail");
+    assert!(
+        err.to_string().contains("mirror_dir is not writable"),
+        "{err}"
+    );
+    assert_eq!(
+        status.read().await.error_kind,
+        Some(SyncErrorKind::MirrorNotWritable)
+    );
+    assert!(!mirror.exists());
+
+    std::fs::set_permissions(&volume, std::fs::Permissions::from_mode(0o755))
+        .expect("restore permissions");
+}
+
+#[tokio::test]
+async fn sync_once_skips_cycle_while_mirror_is_locked() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    std::fs::write(source.join("collections.json"), "{\"version\":1}").expect("write v1");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let config = test_config(&source, &mirror);
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let history = SyncHistory::new(10);
+
+    let lock = lock_mirror(&mirror).expect("first lock should succeed");
+    let err = lock_mirror(&mirror)
+        .err()
+        .expect("second lock should fail while held");
+    assert_eq!(err.to_string(), "mirror locked by another instance");
+
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect_err("sync should skip while locked");
+    {
+        let snapshot = status.read().await;
+        assert_eq!(
+            snapshot.last_error.as_deref(),
+            Some("mirror locked by another instance")
+        );
+        assert_eq!(snapshot.error_kind, Some(SyncErrorKind::MirrorLocked));
+        assert!(snapshot.current_sha.is_none());
+    }
+
+    drop(lock);
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("sync should work once the lock is released");
+    assert!(status.read().await.current_sha.is_some());
+}
+
+#[tokio::test]
+async fn sync_once_refuses_commits_over_the_worktree_quota() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    std::fs::write(source.join("collections.json"), "{\"version\":1}").expect("write v1");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let config = AppConfig {
+        max_worktree_bytes: 1024,
+        ..test_config(&source, &mirror)
+    };
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let history = SyncHistory::new(10);
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("initial sync fits");
+    let synced_sha = status.read().await.current_sha.clone();
+    assert_eq!(
+        std::fs::read_to_string(mirror.join("collections.json")).expect("read mirror"),
+        "{\"version\":1}"
+    );
+
+    std::fs::write(source.join("collections.json"), "{\"version\":2}").expect("write v2");
+    std::fs::write(source.join("large.bin"), vec![b'x'; 2048]).expect("write large");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v2"]);
+    let err = sync_once(&config, status.clone(), &history)
+        .await
+        .expect_err("sync should be refused");
+    assert!(err.to_string().contains("MAX_WORKTREE_BYTES"), "{err}");
+    {
+        let snapshot = status.read().await;
+        assert_eq!(
+            snapshot.error_kind,
+            Some(SyncErrorKind::WorktreeQuotaExceeded)
+        );
+        assert_eq!(snapshot.current_sha, synced_sha);
+    }
+    assert!(!mirror.join("large.bin").exists());
+    assert_eq!(
+        std::fs::read_to_string(mirror.join("collections.json")).expect("read mirror"),
+        "{\"version\":1}"
+    );
+
+    // A first clone over the limit writes no files at all.
+    let fresh = tmp.path().join("fresh");
+    let err = sync_once(
+        &AppConfig {
+            max_worktree_bytes: 1024,
+            ..test_config(&source, &fresh)
+        },
+        Arc::new(RwLock::new(SyncStatus::default())),
+        &history,
+    )
+    .await
+    .expect_err("clone should be refused");
+    assert!(err.to_string().contains("MAX_WORKTREE_BYTES"), "{err}");
+    assert!(!fresh.join("large.bin").exists());
+    assert!(!fresh.join("collections.json").exists());
+}
+
+#[tokio::test]
+async fn sync_once_skips_when_disk_is_low_and_keeps_mirror() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    std::fs::write(source.join("collections.json"), "{\"version\":1}").expect("write v1");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let mut config = test_config(&source, &mirror);
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let history = SyncHistory::new(10);
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("initial sync");
+    let synced_sha = status.read().await.current_sha.clone();
+
+    std::fs::write(source.join("collections.json"), "{\"version\":2}").expect("write v2");
+    run_git(&source, &["commit", "-am", "v2"]);
+    config.min_free_disk_bytes = u64::MAX;
+    let err = sync_once(&config, status.clone(), &history)
+        .await
+        .expect_err("sync should be skipped");
+    assert!(err.to_string().contains("skipping sync"), "{err}");
+    {
+        let snapshot = status.read().await;
+        assert_eq!(snapshot.error_kind, Some(SyncErrorKind::DiskLow));
+        assert_eq!(snapshot.current_sha, synced_sha);
+    }
+    assert_eq!(
+        std::fs::read_to_string(mirror.join("collections.json")).expect("read mirror"),
+        "{\"version\":1}"
+    );
+}
+
+#[tokio::test]
+async fn sync_once_records_fetch_and_checkout_timings() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    std::fs::write(source.join("collections.json"), "{\"version\":1}").expect("write v1");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let config = test_config(&source, &mirror);
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    assert!(status.read().await.last_timings.is_none());
+    sync_once(&config, status.clone(), &SyncHistory::new(10))
+        .await
+        .expect("sync should work");
+    assert!(status.read().await.last_timings.is_some());
+
+    let response = router(AppState::new(config, status))
+        .oneshot(
+            Request::builder()
+                .uri("/meta")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    let body: serde_json::Value = serde_json::from_slice(
+        &axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("read body"),
+    )
+    .expect("json body");
+    assert!(body["sync"]["last_timings"]["fetch_ms"].is_u64());
+    assert!(body["sync"]["last_timings"]["checkout_ms"].is_u64());
+}
+
+#[tokio::test]
+async fn http2_serves_concurrent_file_requests_on_one_connection() {
+    let tmp = tempdir().expect("temp dir");
+    let mirror = tmp.path().join("mirror");
+    std::fs::create_dir_all(&mirror).expect("create mirror dir");
+    for i in 0..8 {
+        std::fs::write(mirror.join(format!("{i}.json")), format!("{{\"n\":{i}}}"))
+            .expect("write file");
+    }
+    let state = AppState::new(
+        AppConfig {
+            http2_enabled: true,
+            ..test_config(tmp.path(), &mirror)
+        },
+        Arc::new(RwLock::new(SyncStatus::default())),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local addr");
+    tokio::spawn(serve(
+        listener,
+        router(state),
+        ServeOptions {
+            http2: true,
+            ..ServeOptions::default()
+        },
+    ));
+
+    let stream = tokio::net::TcpStream::connect(addr).await.expect("connect");
+    let (sender, connection) = hyper::client::conn::http2::handshake(
+        hyper_util::rt::TokioExecutor::new(),
+        hyper_util::rt::TokioIo::new(stream),
+    )
+    .await
+    .expect("h2c handshake");
+    tokio::spawn(connection);
+
+    let handles: Vec<_> = (0..8)
+        .map(|i| {
+            let mut sender = sender.clone();
+            tokio::spawn(async move {
+                let request = Request::builder()
+                    .uri(format!("http://{addr}/files/{i}.json"))
+                    .body(Body::empty())
+                    .expect("request");
+                let response = sender.send_request(request).await.expect("response");
+                assert_eq!(response.version(), axum::http::Version::HTTP_2);
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(Body::new(response.into_body()), usize::MAX)
+                    .await
+                    .expect("read body");
+                assert_eq!(body, format!("{{\"n\":{i}}}"));
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.expect("request task");
+    }
+}
+
+#[tokio::test]
+async fn closes_connections_that_send_headers_too_slowly() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let tmp = tempdir().expect("temp dir");
+    let mirror = tmp.path().join("mirror");
+    std::fs::create_dir_all(&mirror).expect("create mirror dir");
+    let state = AppState::new(
+        test_config(tmp.path(), &mirror),
+        Arc::new(RwLock::new(SyncStatus::default())),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind");
+    let addr = listener.local_addr().expect("local addr");
+    tokio::spawn(serve(
+        listener,
+        router(state),
+        ServeOptions {
+            header_read_timeout: Some(Duration::from_millis(100)),
+            ..ServeOptions::default()
+        },
+    ));
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.expect("connect");
+    stream
+        .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\n")
+        .await
+        .expect("write partial headers");
+    let mut buf = Vec::new();
+    let read = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut buf))
+        .await
+        .expect("server closes the connection");
+    assert_eq!(read.expect("read"), 0);
+    assert!(buf.is_empty());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn serves_files_over_a_unix_socket() {
+    use std::os::unix::fs::PermissionsExt;
+
+    use repo_sync::serve::Listener;
+
+    let tmp = tempdir().expect("temp dir");
+    let mirror = tmp.path().join("mirror");
+    std::fs::create_dir_all(&mirror).expect("create mirror dir");
+    std::fs::write(mirror.join("a.json"), "{\"n\":1}").expect("write file");
+    let socket = tmp.path().join("repo-sync.sock");
+    let state = AppState::new(
+        test_config(tmp.path(), &mirror),
+        Arc::new(RwLock::new(SyncStatus::default())),
+    );
+
+    // A socket file left by an unclean shutdown doesn't block binding.
+    drop(std::os::unix::net::UnixListener::bind(&socket).expect("stale socket"));
+    let addr = format!("unix:{}", socket.display());
+    let listener = Listener::bind(&addr, Some(0o600)).await.expect("bind");
+    assert_eq!(
+        std::fs::metadata(&socket)
+            .expect("socket metadata")
+            .permissions()
+            .mode()
+            & 0o777,
+        0o600
+    );
+    assert!(Listener::bind(&addr, None).await.is_err(), "socket in use");
+    let server = tokio::spawn(serve(listener, router(state), ServeOptions::default()));
+
+    let stream = tokio::net::UnixStream::connect(&socket)
+        .await
+        .expect("connect");
+    let (mut sender, connection) =
+        hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream))
+            .await
+            .expect("handshake");
+    tokio::spawn(connection);
+    let response = sender
+        .send_request(
+            Request::builder()
+                .uri("/files/a.json")
+                .header(header::HOST, "localhost")
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(Body::new(response.into_body()), usize::MAX)
+        .await
+        .expect("read body");
+    assert_eq!(body, "{\"n\":1}");
+
+    server.abort();
+    let _ = server.await;
+    assert!(!socket.exists(), "socket file removed on shutdown");
+}
+
+#[tokio::test]
+async fn mirror_clone_keeps_all_branches_and_tags_up_to_date() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    std::fs::write(source.join("collections.json"), "{\"version\":1}").expect("write v1");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+    run_git(&source, &["tag", "v1"]);
+
+    let config = AppConfig {
+        git_mirror_clone: true,
+        ..test_config(&source, &mirror)
+    };
+    let status = Arc::new(RwLock::new(SyncStatus::default()));
+    let history = SyncHistory::new(10);
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("first sync should work");
+
+    run_git(&source, &["branch", "archive/2024"]);
+    run_git(&source, &["tag", "v2"]);
+    sync_once(&config, status.clone(), &history)
+        .await
+        .expect("second sync should work");
+
+    let repo = git2::Repository::open(&mirror).expect("open mirror");
+    for reference in [
+        "refs/remotes/origin/main",
+        "refs/remotes/origin/archive/2024",
+        "refs/tags/v1",
+        "refs/tags/v2",
+    ] {
+        assert!(repo.find_reference(reference).is_ok(), "{reference}");
+    }
+    assert_eq!(
+        std::fs::read_to_string(mirror.join("collections.json")).expect("read mirrored file"),
+        "{\"version\":1}"
+    );
+}
+
+#[tokio::test]
+async fn readyz_flips_only_after_consecutive_failure_threshold() {
+    let tmp = tempdir().expect("temp dir");
+    let source = tmp.path().join("source");
+    let moved = tmp.path().join("moved");
+    let mirror = tmp.path().join("mirror");
+    init_source_repo(&source);
+    std::fs::write(source.join("collections.json"), "{\"version\":1}").expect("write v1");
+    run_git(&source, &["add", "."]);
+    run_git(&source, &["commit", "-m", "v1"]);
+
+    let config = AppConfig {
+        unready_after_failures: 2,
+        ..test_config(&source, &mirror)
+    };
+    let state = AppState::new(config, Arc::new(RwLock::new(SyncStatus::default())));
+    let app = router(state.clone());
+    let readyz = || async {
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/readyz")
+                    .body(Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response")
+            .status()
+    };
+
+    assert_eq!(readyz().await, StatusCode::SERVICE_UNAVAILABLE);
+    sync_once(&state.config, state.status.clone(), &state.history)
+        .await
+        .expect("sync should work");
+    assert_eq!(readyz().await, StatusCode::OK);
+
+    std::fs::rename(&source, &moved).expect("break source");
+    for (failures, expected) in [(1, StatusCode::OK), (2, StatusCode::SERVICE_UNAVAILABLE)] {
+        sync_once(&state.config, state.status.clone(), &state.history)
+            .await
+            .expect_err("sync should fail without source");
+        assert_eq!(state.status.read().await.consecutive_failures, failures);
+        assert_eq!(readyz().await, expected, "after {failures} failures");
+    }
+
+    std::fs::rename(&moved, &source).expect("restore source");
+    sync_once(&state.config, state.status.clone(), &state.history)
+        .await
+        .expect("sync should recover");
+    assert_eq!(state.status.read().await.consecutive_failures, 0);
+    assert_eq!(readyz().await, StatusCode::OK);
+}
+
+/// Starts the binary against a repository that can't be cloned, so the first
+/// sync never completes, and returns `/readyz`'s status line once it listens.
+fn readyz_without_first_sync(sync_on_startup: &str) -> Option<String> {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        time::{Duration, Instant},
+    };
+
+    let tmp = tempdir().expect("temp dir");
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("free port");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_repo-sync"))
+        .env_remove("CONFIG_ENV_FILE")
+        .env(
+            "GIT_REPO_URL",
+            format!("file://{}", tmp.path().join("missing").display()),
+        )
+        .env("MIRROR_DIR", tmp.path().join("mirror"))
+        .env("HTTP_BIND_ADDR", addr.to_string())
+        .env("SYNC_ON_STARTUP", sync_on_startup)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("spawn repo-sync");
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status_line = loop {
+        if let Ok(mut stream) = TcpStream::connect(addr) {
+            stream
+                .write_all(b"GET /readyz HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n")
+                .expect("write request");
+            let mut response = String::new();
+            stream.read_to_string(&mut response).expect("read response");
+            break response.lines().next().map(str::to_string);
+        }
+        if child.try_wait().expect("poll child").is_some() || Instant::now() > deadline {
+            break None;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    let _ = child.kill();
+    let _ = child.wait();
+    status_line
+}
+
+#[test]
+fn serves_before_first_sync_when_sync_on_startup_disabled() {
+    assert_eq!(
+        readyz_without_first_sync("false").as_deref(),
+        Some("HTTP/1.1 503 Service Unavailable")
+    );
+    // By default the failed startup sync aborts before the listener binds.
+    assert_eq!(readyz_without_first_sync("true"), None);
+}